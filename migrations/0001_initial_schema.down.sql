@@ -0,0 +1,12 @@
+DROP TABLE base_sets;
+DROP TABLE lendings;
+DROP TABLE aliases;
+DROP TABLE books;
+DROP TABLE teachers;
+DROP TABLE students;
+DROP TABLE authentication_tokens;
+DROP TABLE schools;
+
+DROP FUNCTION check_auth_tokens();
+DROP FUNCTION delete_lendings();
+DROP FUNCTION check_person_id();
@@ -0,0 +1,109 @@
+CREATE TABLE schools (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    encrypted_password TEXT NOT NULL
+);
+
+CREATE TABLE authentication_tokens (
+    id OID PRIMARY KEY,
+    hashed_secret TEXT NOT NULL,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE students (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    class_letter TEXT NOT NULL,
+    graduation_year INTEGER NOT NULL,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE
+);
+
+CREATE TABLE teachers (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE
+);
+
+CREATE TABLE books (
+    id SERIAL PRIMARY KEY,
+    isbn TEXT NOT NULL,
+    title TEXT NOT NULL,
+    form TEXT NOT NULL,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE
+);
+
+CREATE TABLE aliases (
+    id SERIAL PRIMARY KEY,
+    book_id INTEGER NOT NULL REFERENCES books ON DELETE CASCADE,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE lendings (
+    id SERIAL PRIMARY KEY,
+    person_type TEXT NOT NULL CHECK (person_type IN ('student', 'teacher')),
+    person_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL REFERENCES books ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE base_sets (
+    id SERIAL PRIMARY KEY,
+    student_id INTEGER NOT NULL REFERENCES students ON DELETE CASCADE,
+    book_id INTEGER NOT NULL REFERENCES books ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX students_school_id_idx ON students (school_id);
+CREATE INDEX teachers_school_id_idx ON teachers (school_id);
+CREATE INDEX books_school_id_idx ON books (school_id);
+CREATE INDEX aliases_book_id_idx ON aliases (book_id);
+CREATE INDEX lendings_person_idx ON lendings (person_type, person_id);
+CREATE INDEX base_sets_student_id_idx ON base_sets (student_id);
+
+CREATE FUNCTION check_person_id() RETURNS trigger AS $check_person_id$
+BEGIN
+    IF NEW.person_type = 'student' THEN
+        PERFORM 1 FROM students WHERE id=NEW.person_id;
+        IF NOT FOUND THEN
+            RAISE EXCEPTION 'There is no student with id %', NEW.person_id;
+        END IF;
+    ELSE
+        PERFORM 1 FROM teachers WHERE id=NEW.person_id;
+        IF NOT FOUND THEN
+            RAISE EXCEPTION 'There is no teacher with id %', NEW.person_id;
+        END IF;
+    END IF;
+    RETURN NEW;
+END;
+$check_person_id$ LANGUAGE plpgsql;
+
+CREATE FUNCTION delete_lendings() RETURNS trigger AS $check_lendings$
+BEGIN
+    IF TG_TABLE_NAME = 'students' THEN
+        DELETE FROM lendings WHERE person_type='student' AND person_id=OLD.id;
+        DELETE FROM base_sets WHERE student_id=OLD.id;
+    ELSE
+        DELETE FROM lendings WHERE person_type='teacher' AND person_id=OLD.id;
+    END IF;
+    RETURN OLD;
+END;
+$check_lendings$ LANGUAGE plpgsql;
+
+CREATE FUNCTION check_auth_tokens() RETURNS trigger AS $auth_tokens$
+BEGIN
+    WHILE EXISTS (SELECT 1 FROM authentication_tokens WHERE id=NEW.id) LOOP
+        NEW.id := NEW.id + 1;
+    END LOOP;
+    RETURN NEW;
+END;
+$auth_tokens$ LANGUAGE plpgsql;
+
+CREATE TRIGGER lendings_ins BEFORE INSERT OR UPDATE ON lendings FOR EACH ROW
+EXECUTE PROCEDURE check_person_id();
+CREATE TRIGGER del_student_lendings AFTER DELETE ON students FOR EACH ROW
+EXECUTE PROCEDURE delete_lendings();
+CREATE TRIGGER del_teacher_lendings AFTER DELETE ON teachers FOR EACH ROW
+EXECUTE PROCEDURE delete_lendings();
+CREATE TRIGGER ins_token BEFORE INSERT ON authentication_tokens FOR EACH ROW
+EXECUTE PROCEDURE check_auth_tokens();
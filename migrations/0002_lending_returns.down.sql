@@ -0,0 +1,4 @@
+DROP INDEX lendings_open_idx;
+DELETE FROM lendings WHERE returned_at IS NOT NULL;
+ALTER TABLE lendings DROP COLUMN return_note;
+ALTER TABLE lendings DROP COLUMN returned_at;
@@ -0,0 +1,4 @@
+ALTER TABLE lendings ADD COLUMN returned_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE lendings ADD COLUMN return_note TEXT;
+
+CREATE INDEX lendings_open_idx ON lendings (person_type, person_id) WHERE returned_at IS NULL;
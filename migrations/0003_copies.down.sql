@@ -0,0 +1,14 @@
+DROP TRIGGER del_base_set_copy ON base_sets;
+DROP TRIGGER del_lending_copy ON lendings;
+DROP FUNCTION release_copy();
+
+ALTER TABLE lendings ADD COLUMN book_id INTEGER REFERENCES books ON DELETE CASCADE;
+ALTER TABLE base_sets ADD COLUMN book_id INTEGER REFERENCES books ON DELETE CASCADE;
+UPDATE lendings SET book_id = copies.book_id FROM copies WHERE copies.id = lendings.copy_id;
+UPDATE base_sets SET book_id = copies.book_id FROM copies WHERE copies.id = base_sets.copy_id;
+ALTER TABLE lendings ALTER COLUMN book_id SET NOT NULL;
+ALTER TABLE base_sets ALTER COLUMN book_id SET NOT NULL;
+
+ALTER TABLE lendings DROP COLUMN copy_id;
+ALTER TABLE base_sets DROP COLUMN copy_id;
+DROP TABLE copies;
@@ -0,0 +1,55 @@
+CREATE TABLE copies (
+    id SERIAL PRIMARY KEY,
+    book_id INTEGER NOT NULL REFERENCES books ON DELETE CASCADE,
+    inventory_number TEXT NOT NULL,
+    condition TEXT,
+    acquired_on DATE,
+    status TEXT NOT NULL DEFAULT 'available'
+        CHECK (status IN ('available', 'lent', 'lost', 'retired')),
+    UNIQUE (book_id, inventory_number)
+);
+
+CREATE INDEX copies_book_id_idx ON copies (book_id);
+
+-- Existing lendings and base sets did not know which copy was handed out,
+-- so each of them gets a placeholder copy of its own.
+ALTER TABLE lendings ADD COLUMN copy_id INTEGER REFERENCES copies ON DELETE CASCADE;
+ALTER TABLE base_sets ADD COLUMN copy_id INTEGER REFERENCES copies ON DELETE CASCADE;
+
+INSERT INTO copies (book_id, inventory_number, status)
+    SELECT book_id, 'legacy-L' || id, CASE WHEN returned_at IS NULL THEN 'lent' ELSE 'available' END
+    FROM lendings;
+UPDATE lendings SET copy_id = copies.id FROM copies
+    WHERE copies.book_id = lendings.book_id AND copies.inventory_number = 'legacy-L' || lendings.id;
+
+INSERT INTO copies (book_id, inventory_number, status)
+    SELECT book_id, 'legacy-B' || id, 'lent' FROM base_sets;
+UPDATE base_sets SET copy_id = copies.id FROM copies
+    WHERE copies.book_id = base_sets.book_id AND copies.inventory_number = 'legacy-B' || base_sets.id;
+
+ALTER TABLE lendings ALTER COLUMN copy_id SET NOT NULL;
+ALTER TABLE base_sets ALTER COLUMN copy_id SET NOT NULL;
+ALTER TABLE lendings DROP COLUMN book_id;
+ALTER TABLE base_sets DROP COLUMN book_id;
+
+CREATE INDEX lendings_copy_id_idx ON lendings (copy_id);
+CREATE INDEX base_sets_copy_id_idx ON base_sets (copy_id);
+CREATE UNIQUE INDEX lendings_open_copy_idx ON lendings (copy_id) WHERE returned_at IS NULL;
+CREATE UNIQUE INDEX base_sets_copy_idx ON base_sets (copy_id);
+
+CREATE FUNCTION release_copy() RETURNS trigger AS $release_copy$
+BEGIN
+    IF TG_TABLE_NAME = 'lendings' THEN
+        IF OLD.returned_at IS NOT NULL THEN
+            RETURN OLD;
+        END IF;
+    END IF;
+    UPDATE copies SET status='available' WHERE id=OLD.copy_id AND status='lent';
+    RETURN OLD;
+END;
+$release_copy$ LANGUAGE plpgsql;
+
+CREATE TRIGGER del_lending_copy AFTER DELETE ON lendings FOR EACH ROW
+EXECUTE PROCEDURE release_copy();
+CREATE TRIGGER del_base_set_copy AFTER DELETE ON base_sets FOR EACH ROW
+EXECUTE PROCEDURE release_copy();
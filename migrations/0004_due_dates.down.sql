@@ -0,0 +1,3 @@
+DROP INDEX lendings_overdue_idx;
+ALTER TABLE lendings DROP COLUMN due_at;
+ALTER TABLE schools DROP COLUMN loan_period_days;
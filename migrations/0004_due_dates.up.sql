@@ -0,0 +1,8 @@
+ALTER TABLE schools ADD COLUMN loan_period_days INTEGER NOT NULL DEFAULT 28
+    CHECK (loan_period_days > 0);
+
+ALTER TABLE lendings ADD COLUMN due_at TIMESTAMP WITH TIME ZONE;
+UPDATE lendings SET due_at = created_at + interval '28 days';
+ALTER TABLE lendings ALTER COLUMN due_at SET NOT NULL;
+
+CREATE INDEX lendings_overdue_idx ON lendings (due_at) WHERE returned_at IS NULL;
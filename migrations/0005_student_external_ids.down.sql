@@ -0,0 +1,2 @@
+DROP INDEX students_external_id_idx;
+ALTER TABLE students DROP COLUMN external_id;
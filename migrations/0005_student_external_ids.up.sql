@@ -0,0 +1,3 @@
+ALTER TABLE students ADD COLUMN external_id TEXT;
+
+CREATE UNIQUE INDEX students_external_id_idx ON students (school_id, external_id) WHERE external_id IS NOT NULL;
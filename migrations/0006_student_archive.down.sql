@@ -0,0 +1,2 @@
+DROP INDEX students_active_idx;
+ALTER TABLE students DROP COLUMN archived_at;
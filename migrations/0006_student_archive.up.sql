@@ -0,0 +1,3 @@
+ALTER TABLE students ADD COLUMN archived_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX students_active_idx ON students (school_id, graduation_year) WHERE archived_at IS NULL;
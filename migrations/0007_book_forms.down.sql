@@ -0,0 +1,2 @@
+DROP INDEX books_form_idx;
+ALTER TABLE books ALTER COLUMN form TYPE TEXT USING array_to_string(form, ',');
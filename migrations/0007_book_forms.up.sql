@@ -0,0 +1,12 @@
+ALTER TABLE books ADD COLUMN forms INTEGER[] NOT NULL DEFAULT '{}';
+
+UPDATE books SET forms = ARRAY(
+    SELECT DISTINCT CAST(entry AS INTEGER) FROM regexp_split_to_table(form, '[^0-9]+') AS entry
+    WHERE entry <> '' ORDER BY 1
+);
+
+ALTER TABLE books DROP COLUMN form;
+ALTER TABLE books RENAME COLUMN forms TO form;
+ALTER TABLE books ALTER COLUMN form DROP DEFAULT;
+
+CREATE INDEX books_form_idx ON books USING GIN (form);
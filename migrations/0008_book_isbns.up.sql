@@ -0,0 +1,44 @@
+-- ISBNs are stored as ISBN-13 without separators; ISBN-10s are converted. Values that are
+-- not a valid ISBN-10 or ISBN-13 once hyphens and whitespace are removed are left as they are.
+CREATE FUNCTION pg_temp.normalise_isbn(isbn TEXT) RETURNS TEXT AS $$
+DECLARE
+    digits TEXT := regexp_replace(upper(isbn), '[-[:space:]]', '', 'g');
+    total INTEGER := 0;
+BEGIN
+    IF digits ~ '^[0-9]{9}[0-9X]$' THEN
+        FOR i IN 1..10 LOOP
+            total := total + (11 - i) * CASE WHEN substr(digits, i, 1) = 'X' THEN 10
+                ELSE CAST(substr(digits, i, 1) AS INTEGER) END;
+        END LOOP;
+        IF total % 11 <> 0 THEN
+            RETURN isbn;
+        END IF;
+        digits := '978' || left(digits, 9);
+    ELSIF digits !~ '^97[89][0-9]{10}$' THEN
+        RETURN isbn;
+    END IF;
+
+    total := 0;
+    FOR i IN 1..12 LOOP
+        total := total + CAST(substr(digits, i, 1) AS INTEGER) * CASE WHEN i % 2 = 0 THEN 3 ELSE 1 END;
+    END LOOP;
+    IF length(digits) = 13 AND right(digits, 1) <> CAST((10 - total % 10) % 10 AS TEXT) THEN
+        RETURN isbn;
+    END IF;
+    RETURN left(digits, 12) || CAST((10 - total % 10) % 10 AS TEXT);
+END
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+UPDATE books SET isbn = pg_temp.normalise_isbn(isbn);
+
+DROP FUNCTION pg_temp.normalise_isbn(TEXT);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM books GROUP BY school_id, isbn HAVING count(*) > 1) THEN
+        RAISE EXCEPTION 'Books sharing an ISBN within a school need to be merged before this migration';
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX books_isbn_idx ON books (school_id, isbn);
@@ -0,0 +1,4 @@
+DROP INDEX aliases_name_trgm_idx;
+DROP INDEX books_title_trgm_idx;
+DROP INDEX teachers_name_trgm_idx;
+DROP INDEX students_name_trgm_idx;
@@ -0,0 +1,6 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX students_name_trgm_idx ON students USING GIN (name gin_trgm_ops);
+CREATE INDEX teachers_name_trgm_idx ON teachers USING GIN (name gin_trgm_ops);
+CREATE INDEX books_title_trgm_idx ON books USING GIN (title gin_trgm_ops);
+CREATE INDEX aliases_name_trgm_idx ON aliases USING GIN (name gin_trgm_ops);
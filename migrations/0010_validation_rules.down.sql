@@ -0,0 +1,3 @@
+ALTER TABLE schools DROP COLUMN graduation_years_ahead;
+ALTER TABLE schools DROP COLUMN graduation_years_back;
+ALTER TABLE schools DROP COLUMN class_letters;
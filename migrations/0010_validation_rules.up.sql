@@ -0,0 +1,5 @@
+ALTER TABLE schools ADD COLUMN class_letters TEXT NOT NULL DEFAULT '';
+ALTER TABLE schools ADD COLUMN graduation_years_back INTEGER NOT NULL DEFAULT 1
+    CHECK (graduation_years_back >= 0);
+ALTER TABLE schools ADD COLUMN graduation_years_ahead INTEGER NOT NULL DEFAULT 13
+    CHECK (graduation_years_ahead >= 0);
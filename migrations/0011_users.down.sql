@@ -0,0 +1,24 @@
+ALTER TABLE authentication_tokens DROP COLUMN user_id;
+
+ALTER TABLE schools ADD COLUMN encrypted_password TEXT;
+-- The oldest admin's password becomes the school password, any user's if no admin is left
+UPDATE schools SET encrypted_password = (
+    SELECT encrypted_password FROM users
+    WHERE users.school_id = schools.id
+    ORDER BY users.role = 'admin' DESC, users.id LIMIT 1
+);
+-- Schools without any user cannot be logged into afterwards, but must not lose their data
+DO $$
+DECLARE
+    names TEXT;
+BEGIN
+    SELECT string_agg(name, ', ') INTO names FROM schools WHERE encrypted_password IS NULL;
+    IF names IS NOT NULL THEN
+        RAISE EXCEPTION 'Schools without users cannot be migrated back: %', names
+            USING HINT = 'Create a user for them first.';
+    END IF;
+END
+$$;
+ALTER TABLE schools ALTER COLUMN encrypted_password SET NOT NULL;
+
+DROP TABLE users;
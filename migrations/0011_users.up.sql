@@ -0,0 +1,20 @@
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    encrypted_password TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('admin', 'librarian', 'read_only'))
+);
+
+CREATE UNIQUE INDEX users_username_idx ON users (school_id, username);
+
+-- The shared school password becomes the password of an admin account
+INSERT INTO users (school_id, username, encrypted_password, role)
+SELECT id, 'admin', encrypted_password, 'admin' FROM schools;
+
+ALTER TABLE schools DROP COLUMN encrypted_password;
+
+-- Existing sessions cannot be attributed to a user
+DELETE FROM authentication_tokens;
+ALTER TABLE authentication_tokens ADD COLUMN user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE;
+CREATE INDEX authentication_tokens_user_id_idx ON authentication_tokens (user_id);
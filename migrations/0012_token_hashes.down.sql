@@ -0,0 +1,2 @@
+DROP INDEX authentication_tokens_created_at_idx;
+DELETE FROM authentication_tokens;
@@ -0,0 +1,3 @@
+-- Secrets are now hashed with SHA-256 instead of bcrypt, so existing tokens cannot be verified
+DELETE FROM authentication_tokens;
+CREATE INDEX authentication_tokens_created_at_idx ON authentication_tokens (created_at);
@@ -0,0 +1,27 @@
+DROP TABLE authentication_tokens;
+
+CREATE TABLE authentication_tokens (
+    id OID PRIMARY KEY,
+    hashed_secret TEXT NOT NULL,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE
+);
+
+CREATE INDEX authentication_tokens_user_id_idx ON authentication_tokens (user_id);
+CREATE INDEX authentication_tokens_created_at_idx ON authentication_tokens (created_at);
+
+CREATE FUNCTION check_auth_tokens() RETURNS trigger AS $auth_tokens$
+BEGIN
+    WHILE EXISTS (SELECT 1 FROM authentication_tokens WHERE id=NEW.id) LOOP
+        NEW.id := NEW.id + 1;
+    END LOOP;
+    RETURN NEW;
+END;
+$auth_tokens$ LANGUAGE plpgsql;
+
+CREATE TRIGGER ins_token BEFORE INSERT ON authentication_tokens FOR EACH ROW
+EXECUTE PROCEDURE check_auth_tokens();
+
+ALTER TABLE schools DROP COLUMN session_idle_minutes;
+ALTER TABLE schools DROP COLUMN session_lifetime_hours;
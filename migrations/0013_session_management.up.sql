@@ -0,0 +1,21 @@
+ALTER TABLE schools ADD COLUMN session_lifetime_hours INTEGER NOT NULL DEFAULT 24
+    CHECK (session_lifetime_hours > 0);
+ALTER TABLE schools ADD COLUMN session_idle_minutes INTEGER CHECK (session_idle_minutes > 0);
+
+-- Token ids come from a sequence now, which makes the collision trigger unnecessary
+DROP TRIGGER ins_token ON authentication_tokens;
+DROP FUNCTION check_auth_tokens();
+DROP TABLE authentication_tokens;
+
+CREATE TABLE authentication_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    hashed_secret TEXT NOT NULL,
+    school_id INTEGER NOT NULL REFERENCES schools ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_used_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    user_agent TEXT
+);
+
+CREATE INDEX authentication_tokens_user_id_idx ON authentication_tokens (user_id);
+CREATE INDEX authentication_tokens_created_at_idx ON authentication_tokens (created_at);
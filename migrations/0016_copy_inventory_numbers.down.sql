@@ -0,0 +1,3 @@
+DROP INDEX copies_inventory_number_idx;
+ALTER TABLE copies ADD CONSTRAINT copies_book_id_inventory_number_key UNIQUE (book_id, inventory_number);
+ALTER TABLE copies DROP COLUMN school_id;
@@ -0,0 +1,15 @@
+-- Inventory numbers are scanned without knowing the book, so they need to be unique per school.
+ALTER TABLE copies ADD COLUMN school_id INTEGER REFERENCES schools ON DELETE CASCADE;
+UPDATE copies SET school_id = books.school_id FROM books WHERE books.id = copies.book_id;
+ALTER TABLE copies ALTER COLUMN school_id SET NOT NULL;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM copies GROUP BY school_id, inventory_number HAVING count(*) > 1) THEN
+        RAISE EXCEPTION 'Copies sharing an inventory number within a school need to be renumbered before this migration';
+    END IF;
+END
+$$;
+
+ALTER TABLE copies DROP CONSTRAINT copies_book_id_inventory_number_key;
+CREATE UNIQUE INDEX copies_inventory_number_idx ON copies (school_id, inventory_number);
@@ -0,0 +1,30 @@
+use chrono::UTC;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::DatabaseConfig;
+use crate::error::ObsidianError;
+use crate::migrations;
+use crate::models::{sessions, throttle};
+
+/// How often expired tokens are removed.
+const INTERVAL_SECS: u64 = 60 * 60;
+
+fn run(config: &DatabaseConfig) -> Result<u64, ObsidianError> {
+    let conn = migrations::connect(config)?;
+    throttle::delete_expired(&conn)?;
+    sessions::delete_expired(&conn)
+}
+
+/// Removes expired tokens and forgotten login failures in a background thread, keeping the
+/// writes out of authenticated requests. Failures are logged and retried in the next round.
+pub fn spawn(config: &DatabaseConfig) {
+    let config = config.clone();
+    thread::spawn(move || loop {
+        match run(&config) {
+            Ok(deleted) => println!("[{}] Deleted {} expired token(s)", UTC::now().format("%FT%T%:z"), deleted),
+            Err(err) => println!("[{}] Could not delete expired tokens: {}", UTC::now().format("%FT%T%:z"), err)
+        }
+        thread::sleep(Duration::from_secs(INTERVAL_SECS));
+    });
+}
@@ -0,0 +1,281 @@
+use postgres::tls::TlsHandshake;
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Error as FError, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use toml;
+
+#[cfg(feature = "postgres-tls")]
+use openssl::ssl::{SslConnectorBuilder, SslMethod};
+#[cfg(feature = "postgres-tls")]
+use postgres::tls::openssl::OpenSsl;
+
+pub const DEFAULT_BIND: &str = "localhost:3000";
+pub const DEFAULT_THREADS: usize = 8;
+pub const DEFAULT_MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+pub const DEFAULT_POOL_MIN: u32 = 1;
+pub const DEFAULT_POOL_MAX: u32 = 10;
+pub const DEFAULT_CONNECTION_TIMEOUT: u64 = 30;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Unreadable(String, ::std::io::Error),
+    Syntax(String),
+    Missing(&'static str),
+    Invalid(&'static str, String),
+    NotCompiled(&'static str, &'static str),
+    Tls(String)
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ConfigError::Unreadable(_, ref a) => Some(a),
+            _ => None
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FError> {
+        match *self {
+            ConfigError::Unreadable(ref path, ref inner) =>
+                write!(f, "Configuration file could not be read ({}): {}", path, inner),
+            ConfigError::Syntax(ref msg) => write!(f, "Configuration file is invalid: {}", msg),
+            ConfigError::Missing(key) => write!(f, "Configuration value missing: {}", key),
+            ConfigError::Invalid(key, ref msg) => write!(f, "Configuration value invalid: {} {}", key, msg),
+            ConfigError::NotCompiled(key, feature) =>
+                write!(f, "Feature not compiled in: {} needs a build with `--features {}`", key, feature),
+            ConfigError::Tls(ref msg) => write!(f, "TLS could not be set up: {}", msg)
+        }
+    }
+}
+
+/// Whether and how connections to Postgres are encrypted, matching Postgres' `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbTls {
+    None,
+    Prefer,
+    Require
+}
+
+impl FromStr for DbTls {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<DbTls, ConfigError> {
+        match s {
+            "none" => Ok(DbTls::None),
+            "prefer" => Ok(DbTls::Prefer),
+            "require" => Ok(DbTls::Require),
+            _ => Err(ConfigError::Invalid("database.tls", format!("must be none, prefer or require, not {}", s)))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub threads: usize,
+    /// The largest request body accepted, in bytes.
+    pub max_body_bytes: usize,
+    pub https: Option<HttpsConfig>
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub pool_min: u32,
+    pub pool_max: u32,
+    /// Seconds to wait for a pooled connection before a request fails.
+    pub connection_timeout: u64,
+    pub tls: DbTls,
+    pub ca_file: Option<PathBuf>
+}
+
+/// Settings of the server, read from an optional TOML file and overridden by environment
+/// variables. Everything is validated on load so misconfiguration fails at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FileConfig {
+    server: Option<FileServer>,
+    database: Option<FileDatabase>
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FileServer {
+    bind: Option<String>,
+    threads: Option<usize>,
+    max_body_bytes: Option<usize>,
+    https_cert: Option<String>,
+    https_key: Option<String>
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct FileDatabase {
+    url: Option<String>,
+    pool_min: Option<u32>,
+    pool_max: Option<u32>,
+    connection_timeout: Option<u64>,
+    tls: Option<String>,
+    ca_file: Option<String>
+}
+
+fn read_file(path: &str) -> Result<FileConfig, ConfigError> {
+    let mut buf = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut buf))
+        .map_err(|err| ConfigError::Unreadable(path.to_string(), err))?;
+    toml::from_str(&buf).map_err(|err| ConfigError::Syntax(err.to_string()))
+}
+
+/// Reads the environment variable `name`, falling back to `value` from the file.
+fn env_or(name: &str, value: Option<String>) -> Option<String> {
+    match env::var(name) {
+        Ok(var) => Some(var),
+        Err(_) => value
+    }
+}
+
+fn env_parse<T: FromStr>(name: &'static str, key: &'static str, value: Option<T>) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(var) => var.trim().parse::<T>()
+            .map(Some)
+            .map_err(|_| ConfigError::Invalid(key, format!("has an invalid value {} in {}", var, name))),
+        Err(_) => Ok(value)
+    }
+}
+
+fn check_file(key: &'static str, path: &str) -> Result<PathBuf, ConfigError> {
+    let path = PathBuf::from(path);
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(ConfigError::Invalid(key, format!("{} is not a readable file", path.display())))
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the file at `path`, if any, and the environment.
+    pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+        let file = match path {
+            Some(path) => read_file(path)?,
+            None => FileConfig::default()
+        };
+        let server = file.server.unwrap_or_default();
+        let database = file.database.unwrap_or_default();
+
+        let bind = env_or("OBSIDIAN_BIND", server.bind).unwrap_or(DEFAULT_BIND.to_string());
+        if bind.to_socket_addrs().map(|mut addrs| addrs.next().is_none()).unwrap_or(true) {
+            return Err(ConfigError::Invalid("server.bind", format!("{} is not a valid address", bind)));
+        }
+        let threads = env_parse("OBSIDIAN_THREADS", "server.threads", server.threads)?.unwrap_or(DEFAULT_THREADS);
+        if threads == 0 {
+            return Err(ConfigError::Invalid("server.threads", "must be at least 1".to_string()));
+        }
+        let max_body_bytes = env_parse("OBSIDIAN_MAX_BODY_BYTES", "server.max_body_bytes", server.max_body_bytes)?
+            .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+        if max_body_bytes == 0 {
+            return Err(ConfigError::Invalid("server.max_body_bytes", "must be at least 1".to_string()));
+        }
+        let https = match (env_or("OBSIDIAN_HTTPS_CERT", server.https_cert), env_or("OBSIDIAN_HTTPS_KEY", server.https_key)) {
+            (Some(cert), Some(key)) => Some(HttpsConfig {
+                cert: check_file("server.https_cert", &cert)?,
+                key: check_file("server.https_key", &key)?
+            }),
+            (None, None) => None,
+            (Some(_), None) => return Err(ConfigError::Missing("server.https_key")),
+            (None, Some(_)) => return Err(ConfigError::Missing("server.https_cert"))
+        };
+        if https.is_some() && !cfg!(feature = "https") {
+            return Err(ConfigError::NotCompiled("server.https_cert", "https"));
+        }
+
+        let url = env_or("DATABASE_URL", database.url).ok_or(ConfigError::Missing("database.url"))?;
+        let pool_min = env_parse("OBSIDIAN_POOL_MIN", "database.pool_min", database.pool_min)?
+            .unwrap_or(DEFAULT_POOL_MIN);
+        let pool_max = env_parse("OBSIDIAN_POOL_MAX", "database.pool_max", database.pool_max)?
+            .unwrap_or(DEFAULT_POOL_MAX);
+        if pool_max == 0 {
+            return Err(ConfigError::Invalid("database.pool_max", "must be at least 1".to_string()));
+        }
+        if pool_min > pool_max {
+            return Err(ConfigError::Invalid("database.pool_min", format!("must not exceed pool_max ({})", pool_max)));
+        }
+        let connection_timeout = env_parse("OBSIDIAN_CONNECTION_TIMEOUT", "database.connection_timeout",
+            database.connection_timeout)?.unwrap_or(DEFAULT_CONNECTION_TIMEOUT);
+        if connection_timeout == 0 {
+            return Err(ConfigError::Invalid("database.connection_timeout", "must be at least 1 second".to_string()));
+        }
+        let tls = match env_or("OBSIDIAN_DB_TLS", database.tls) {
+            Some(tls) => tls.trim().parse::<DbTls>()?,
+            None => DbTls::None
+        };
+        let ca_file = match env_or("OBSIDIAN_DB_CA_FILE", database.ca_file) {
+            Some(ca_file) => Some(check_file("database.ca_file", &ca_file)?),
+            None => None
+        };
+        if tls != DbTls::None && !cfg!(feature = "postgres-tls") {
+            return Err(ConfigError::NotCompiled("database.tls", "postgres-tls"));
+        }
+
+        let config = Config {
+            server: ServerConfig {
+                bind,
+                threads,
+                max_body_bytes,
+                https
+            },
+            database: DatabaseConfig {
+                url,
+                pool_min,
+                pool_max,
+                connection_timeout,
+                tls,
+                ca_file
+            }
+        };
+        // Builds the handshake once so a broken CA file is reported now rather than on connecting
+        config.database.tls_handshake()?;
+        Ok(config)
+    }
+}
+
+impl DatabaseConfig {
+    /// The handshake used to encrypt connections, `None` if `tls` is `none`.
+    #[cfg(feature = "postgres-tls")]
+    pub fn tls_handshake(&self) -> Result<Option<Box<dyn TlsHandshake + Sync + Send>>, ConfigError> {
+        if self.tls == DbTls::None {
+            return Ok(None);
+        }
+        let mut builder = SslConnectorBuilder::new(SslMethod::tls())
+            .map_err(|err| ConfigError::Tls(format!("{}", err)))?;
+        if let Some(ref ca_file) = self.ca_file {
+            builder.builder_mut().set_ca_file(ca_file)
+                .map_err(|err| ConfigError::Tls(format!("{}: {}", ca_file.display(), err)))?;
+        }
+        Ok(Some(Box::new(OpenSsl::from(builder.build()))))
+    }
+
+    /// The handshake used to encrypt connections, always `None` without the `postgres-tls`
+    /// feature as `Config::load` rejects any other mode.
+    #[cfg(not(feature = "postgres-tls"))]
+    pub fn tls_handshake(&self) -> Result<Option<Box<dyn TlsHandshake + Sync + Send>>, ConfigError> {
+        Ok(None)
+    }
+}
@@ -1,32 +1,66 @@
-use iron::error::{IronError, HttpError};
-use iron::headers::{Header as THeader, HeaderFormat};
-use iron::modifiers::Header;
-use iron::status::Status;
+use axum::http::StatusCode;
+use axum::http::header::{CONTENT_TYPE, LOCATION, RETRY_AFTER, WWW_AUTHENTICATE};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::Value as Json;
+use serde_json::error::Category;
 use std::error::Error;
 use std::fmt::{Display, Error as FError, Formatter};
-use std::convert::From;
 
 #[derive(Debug)]
 pub enum ReqError {
     NoID,
     WrongContentType,
-    NoAuth
+    NoAuth,
+    InvalidParameter(&'static str),
+    UnknownField(String),
+    ExpectedCsv,
+    MissingColumn(String),
+    BodyTooLarge(usize)
 }
 
 #[derive(Debug)]
 pub enum ObsidianError {
-    RequestError(::error::ReqError),
+    RequestError(crate::error::ReqError),
     PostgresError(::postgres::error::Error),
     ConnectionError(::postgres::error::ConnectError),
     GetConnError(::r2d2::GetTimeout),
     RecordNotFound(&'static str),
     PoolError(::r2d2::InitializationError),
-    ParseError(::rustc_serialize::json::DecoderError),
-    SerializeError(::rustc_serialize::json::EncoderError),
+    ParseError(::serde_json::Error),
+    SerializeError(::serde_json::Error),
     IoError(::std::io::Error),
-    IncludeNotSupported(::models::Includable),
+    IncludeNotSupported(crate::models::Includable),
     WrongPassword,
-    BCryptError(::bcrypt::BcryptError)
+    BCryptError(::bcrypt::BcryptError),
+    AlreadyReturned,
+    CopyUnavailable,
+    InvalidIsbn(String),
+    DuplicateIsbn(usize),
+    DuplicateInventoryNumber(usize),
+    CopyStatusConflict,
+    InvalidFields(Vec<FieldError>),
+    ConfigError(crate::config::ConfigError),
+    Forbidden(crate::models::users::Role),
+    MissingScope(String),
+    LastAdmin,
+    TooManyAttempts(u64)
+}
+
+/// A field of a record that fails validation.
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> FieldError {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string()
+        }
+    }
 }
 
 macro_rules! impl_oerr {
@@ -37,114 +71,228 @@ macro_rules! impl_oerr {
     });
 }
 
-impl_oerr!(::error::ReqError, RequestError);
+impl_oerr!(crate::error::ReqError, RequestError);
 impl_oerr!(::postgres::error::Error, PostgresError);
 impl_oerr!(::postgres::error::ConnectError, ConnectionError);
 impl_oerr!(::r2d2::InitializationError, PoolError);
-impl_oerr!(::rustc_serialize::json::DecoderError, ParseError);
-impl_oerr!(::rustc_serialize::json::EncoderError, SerializeError);
+impl_oerr!(::serde_json::Error, ParseError);
 impl_oerr!(::r2d2::GetTimeout, GetConnError);
 impl_oerr!(::std::io::Error, IoError);
-impl_oerr!(::models::Includable, IncludeNotSupported);
+impl_oerr!(crate::models::Includable, IncludeNotSupported);
 impl_oerr!(::bcrypt::BcryptError, BCryptError);
+impl_oerr!(crate::config::ConfigError, ConfigError);
 
-impl From<ObsidianError> for IronError {
-    fn from(err: ObsidianError) -> IronError {
-        let just_status = match err {
-            ObsidianError::PostgresError(ref inner) => {
-                println!("PostgresError: {:?}", inner);
-                Some(Status::InternalServerError)
-            },
-            ObsidianError::ConnectionError(ref inner) => {
-                println!("ConnectError: {:?}", inner);
-                Some(Status::InternalServerError)
-            },
-            ObsidianError::GetConnError(ref inner) => {
-                println!("GetTimeout: {:?}", &inner);
-                Some(Status::InternalServerError)
-            },
-            ObsidianError::RecordNotFound(name) if  name != "School" => {
-                println!("{} not found", name);
-                Some(Status::NotFound)
-            },
-            ObsidianError::PoolError(ref inner) => {
-                println!("R2D2 Initialization Error: {:?}", inner);
-                Some(Status::InternalServerError)
-            },
-            ObsidianError::ParseError(ref inner) => {
-                println!("Parser error: {:?}", inner);
-                Some(Status::BadRequest)
-            },
-            ObsidianError::SerializeError(ref inner) => {
-                println!("Serialisation error: {:?}", inner);
-                unreachable!()
-            },
-            ObsidianError::IoError(ref inner) => {
-                println!("Io error: {:?}", inner);
-                Some(Status::BadRequest)
-            },
-            _ => {None}
-        };
+/// The JSON body describing an error to clients. `code` is stable for every kind of error,
+/// `field` names the offending field or parameter and `details` adds what is known about
+/// the specific case, which is a list of field errors for records failing validation.
+#[derive(Serialize, Debug, Clone)]
+pub struct Problem {
+    pub code: &'static str,
+    pub message: String,
+    pub field: Option<String>,
+    pub details: Option<Json>
+}
 
-        let status_and_header = match err {
-            ObsidianError::RequestError(ReqError::NoAuth) => {
-                println!("No authentication token provided");
-                Some((Status::Unauthorized, Header(BasicAuthenticate("Token and secret".to_string()))))
-            },
-            ObsidianError::WrongPassword => {
-                println!("Wrong password");
-                Some((Status::Unauthorized, Header(BasicAuthenticate("Token and secret".to_string()))))
-            },
-            ObsidianError::BCryptError(ref inner) => {
-                println!("BCrypt error: {:?}", inner);
-                Some((Status::Unauthorized, Header(BasicAuthenticate("Token and secret".to_string()))))
-            },
-            ObsidianError::RecordNotFound("School") => {
-                println!("School not found");
-                Some((Status::Unauthorized, Header(BasicAuthenticate("Token and secret".to_string()))))
-            }
-            _ => {None}
-        };
+impl Problem {
+    fn new(code: &'static str, message: &str) -> Problem {
+        Problem {
+            code,
+            message: message.to_string(),
+            field: None,
+            details: None
+        }
+    }
 
-        let header_and_text = match err {
-            ObsidianError::IncludeNotSupported(ref inner) => {
-                println!("{:?} not supported as include", inner);
-                Some((Status::BadRequest, "The relation to be included is not supported by this route"))
-            },
-            ObsidianError::RequestError(ReqError::NoID) => {
-                println!("Wrongly typed id");
-                Some((Status::BadRequest, "The id needs to be an integer"))
-            },
-            ObsidianError::RequestError(ReqError::WrongContentType) => {
-                println!("Wrong Content-Type");
-                Some((Status::BadRequest, "Content-Type need to be application/json"))
-            },
-            _ => {None}
-        };
+    fn field(mut self, field: &str) -> Problem {
+        self.field = Some(field.to_string());
+        self
+    }
 
-        if let Some(st) = just_status {
-            IronError::new(err, st)
-        } else if let Some(st) = status_and_header {
-            IronError::new(err, st)
-        } else if let Some(st) = header_and_text {
-            IronError::new(err, st)
-        } else {
-            unreachable!()
+    fn details(mut self, details: String) -> Problem {
+        self.details = Some(Json::String(details));
+        self
+    }
+}
+
+/// Describes why a body could not be decoded. The messages of serde are rephrased without
+/// the position, which refers to the body as a whole even for items of an array.
+fn decoder_problem(err: &::serde_json::Error) -> Problem {
+    let problem = Problem::new("invalid_body", "The request body could not be parsed");
+    if err.classify() != Category::Data {
+        return problem.details("The body is not valid JSON".to_string());
+    }
+    let message = err.to_string();
+    let position = format!(" at line {} column {}", err.line(), err.column());
+    let message = message.trim_end_matches(&*position);
+    if let Some(field) = message.strip_prefix("missing field `") {
+        problem.field(field.trim_end_matches('`')).details("The field is missing".to_string())
+    } else if let Some(variant) = message.strip_prefix("unknown variant `") {
+        problem.details(format!("Unknown value {}", variant.split('`').next().unwrap_or("")))
+    } else if let Some((found, expected)) = message.strip_prefix("invalid type: ")
+        .or(message.strip_prefix("invalid value: "))
+        .and_then(|rest| rest.split_once(", expected ")) {
+        problem.details(format!("Expected {}, found {}", expected, found))
+    } else {
+        problem.details(message.to_string())
+    }
+}
+
+impl ObsidianError {
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            ObsidianError::RequestError(ReqError::NoAuth) |
+            ObsidianError::WrongPassword |
+            ObsidianError::BCryptError(_) |
+            ObsidianError::RecordNotFound("School") => StatusCode::UNAUTHORIZED,
+            ObsidianError::RequestError(ReqError::BodyTooLarge(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+            ObsidianError::RequestError(_) |
+            ObsidianError::ParseError(_) |
+            ObsidianError::IoError(_) |
+            ObsidianError::IncludeNotSupported(_) => StatusCode::BAD_REQUEST,
+            ObsidianError::RecordNotFound(_) => StatusCode::NOT_FOUND,
+            ObsidianError::AlreadyReturned |
+            ObsidianError::CopyUnavailable |
+            ObsidianError::DuplicateIsbn(_) |
+            ObsidianError::DuplicateInventoryNumber(_) |
+            ObsidianError::CopyStatusConflict |
+            ObsidianError::LastAdmin => StatusCode::CONFLICT,
+            ObsidianError::InvalidIsbn(_) |
+            ObsidianError::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ObsidianError::Forbidden(_) |
+            ObsidianError::MissingScope(_) => StatusCode::FORBIDDEN,
+            ObsidianError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            ObsidianError::PostgresError(_) |
+            ObsidianError::ConnectionError(_) |
+            ObsidianError::GetConnError(_) |
+            ObsidianError::PoolError(_) |
+            ObsidianError::SerializeError(_) |
+            ObsidianError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    /// Describes the error to clients. Internal errors are not detailed any further.
+    pub fn problem(&self) -> Problem {
+        match *self {
+            ObsidianError::RequestError(ReqError::NoID) =>
+                Problem::new("invalid_id", "The id needs to be an integer"),
+            ObsidianError::RequestError(ReqError::WrongContentType) =>
+                Problem::new("expected_json", "Content-Type needs to be application/json"),
+            ObsidianError::RequestError(ReqError::NoAuth) =>
+                Problem::new("missing_credentials", "The request needs to be authenticated"),
+            ObsidianError::RequestError(ReqError::InvalidParameter(name)) =>
+                Problem::new("invalid_parameter", "A query parameter has an invalid value").field(name),
+            ObsidianError::RequestError(ReqError::UnknownField(ref name)) =>
+                Problem::new("unknown_field", "The field cannot be used for filtering or sorting").field(name),
+            ObsidianError::RequestError(ReqError::ExpectedCsv) =>
+                Problem::new("expected_csv", "Content-Type needs to be text/csv"),
+            ObsidianError::RequestError(ReqError::MissingColumn(ref name)) =>
+                Problem::new("missing_column", "The CSV file lacks a required column").field(name),
+            ObsidianError::RequestError(ReqError::BodyTooLarge(limit)) =>
+                Problem::new("body_too_large", "The request body is too large")
+                    .details(format!("At most {} bytes are accepted", limit)),
+            ObsidianError::RecordNotFound("School") |
+            ObsidianError::WrongPassword |
+            ObsidianError::BCryptError(_) =>
+                Problem::new("invalid_credentials", "The credentials are invalid"),
+            ObsidianError::RecordNotFound(name) =>
+                Problem::new("not_found", "The record does not exist").details(format!("{} not found", name)),
+            ObsidianError::ParseError(ref inner) => decoder_problem(inner),
+            ObsidianError::IoError(_) =>
+                Problem::new("unreadable_body", "The request body could not be read"),
+            ObsidianError::IncludeNotSupported(ref inner) =>
+                Problem::new("include_not_supported", "The relation to be included is not supported by this route")
+                    .field("include")
+                    .details(format!("{:?}", inner)),
+            ObsidianError::AlreadyReturned =>
+                Problem::new("already_returned", "The lending has already been returned"),
+            ObsidianError::CopyUnavailable =>
+                Problem::new("copy_unavailable", "The copy is not available for lending"),
+            ObsidianError::InvalidIsbn(ref isbn) =>
+                Problem::new("invalid_isbn", "The ISBN is not a valid ISBN-10 or ISBN-13").field("isbn")
+                    .details(isbn.clone()),
+            ObsidianError::DuplicateIsbn(id) =>
+                Problem::new("duplicate_isbn", "A book with this ISBN already exists").field("isbn")
+                    .details(format!("/books/{}", id)),
+            ObsidianError::DuplicateInventoryNumber(id) =>
+                Problem::new("duplicate_inventory_number", "A copy with this inventory number already exists")
+                    .field("inventory_number")
+                    .details(format!("/copies/{}", id)),
+            ObsidianError::CopyStatusConflict =>
+                Problem::new("copy_status_conflict", "Only lendings and base sets can mark a copy as lent or release it")
+                    .field("status"),
+            ObsidianError::Forbidden(role) =>
+                Problem::new("forbidden", "The user's role does not allow this request")
+                    .details(format!("Needs the role {}", role.as_str())),
+            ObsidianError::MissingScope(ref scope) =>
+                Problem::new("missing_scope", "The API key lacks the scope needed for this request")
+                    .details(scope.clone()),
+            ObsidianError::TooManyAttempts(secs) =>
+                Problem::new("too_many_attempts", "Too many failed attempts, authentication is locked for now")
+                    .details(format!("Retry after {} seconds", secs)),
+            ObsidianError::LastAdmin =>
+                Problem::new("last_admin", "The school needs to keep at least one admin"),
+            ObsidianError::InvalidFields(ref errors) => Problem {
+                code: "invalid_fields",
+                message: "Some fields of the record are invalid".to_string(),
+                field: errors.first().map(|error| error.field.clone()),
+                details: ::serde_json::to_value(errors).ok()
+            },
+            ObsidianError::PostgresError(_) |
+            ObsidianError::ConnectionError(_) |
+            ObsidianError::GetConnError(_) |
+            ObsidianError::PoolError(_) =>
+                Problem::new("database_error", "The database could not process the request"),
+            ObsidianError::SerializeError(_) =>
+                Problem::new("serialisation_error", "The response could not be serialised"),
+            ObsidianError::ConfigError(_) =>
+                Problem::new("configuration_error", "The server is misconfigured")
         }
     }
 }
 
-impl Error for ObsidianError {
-    fn description(&self) -> &str {
-        ""
+impl IntoResponse for ObsidianError {
+    fn into_response(self) -> Response {
+        println!("{}", self);
+        let problem = self.problem();
+        let body = ::serde_json::to_string(&problem)
+            .unwrap_or(format!("{{\"code\":\"{}\",\"message\":null,\"field\":null,\"details\":null}}", problem.code));
+        let mut response = (self.status(), [(CONTENT_TYPE, "application/json")], body).into_response();
+        let header = match self {
+            ObsidianError::RequestError(ReqError::NoAuth) |
+            ObsidianError::WrongPassword |
+            ObsidianError::BCryptError(_) |
+            ObsidianError::RecordNotFound("School") =>
+                Some((WWW_AUTHENTICATE, "Basic: realm=\"Token and secret\"".to_string())),
+            ObsidianError::DuplicateIsbn(id) => Some((LOCATION, format!("/books/{}", id))),
+            ObsidianError::DuplicateInventoryNumber(id) => Some((LOCATION, format!("/copies/{}", id))),
+            ObsidianError::TooManyAttempts(secs) => Some((RETRY_AFTER, secs.to_string())),
+            _ => None
+        };
+        if let Some((name, value)) = header {
+            response.headers_mut().insert(name, value.parse().unwrap());
+        }
+        response
     }
+}
 
-    fn cause(&self) -> Option<&Error> {
+impl Error for ObsidianError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
             ObsidianError::RequestError(_) |
             ObsidianError::RecordNotFound(_) |
             ObsidianError::IncludeNotSupported(_) |
-            ObsidianError::WrongPassword => {None},
+            ObsidianError::WrongPassword |
+            ObsidianError::AlreadyReturned |
+            ObsidianError::CopyUnavailable |
+            ObsidianError::InvalidIsbn(_) |
+            ObsidianError::DuplicateIsbn(_) |
+            ObsidianError::DuplicateInventoryNumber(_) |
+            ObsidianError::CopyStatusConflict |
+            ObsidianError::InvalidFields(_) |
+            ObsidianError::Forbidden(_) |
+            ObsidianError::MissingScope(_) |
+            ObsidianError::LastAdmin |
+            ObsidianError::TooManyAttempts(_) => {None},
             ObsidianError::PostgresError(ref a) => {Some(a)},
             ObsidianError::ConnectionError(ref a) => {Some(a)},
             ObsidianError::GetConnError(ref a) => {Some(a)},
@@ -152,33 +300,247 @@ impl Error for ObsidianError {
             ObsidianError::ParseError(ref a) => {Some(a)},
             ObsidianError::SerializeError(ref a) => {Some(a)},
             ObsidianError::IoError(ref a) => {Some(a)},
-            ObsidianError::BCryptError(ref a) => {Some(a)}
+            ObsidianError::BCryptError(ref a) => {Some(a)},
+            ObsidianError::ConfigError(ref a) => {Some(a)}
         }
     }
 }
 
 impl Display for ObsidianError {
-    fn fmt(&self, _: &mut Formatter) -> Result<(), FError> {
-        unreachable!()
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FError> {
+        match *self {
+            ObsidianError::RequestError(ref inner) => write!(f, "Invalid request: {:?}", inner),
+            ObsidianError::RecordNotFound(name) => write!(f, "{} not found", name),
+            ObsidianError::IncludeNotSupported(ref inner) => write!(f, "{:?} not supported as include", inner),
+            ObsidianError::InvalidIsbn(ref isbn) => write!(f, "Invalid ISBN {}", isbn),
+            ObsidianError::DuplicateIsbn(id) => write!(f, "ISBN already used by book {}", id),
+            ObsidianError::DuplicateInventoryNumber(id) => write!(f, "Inventory number already used by copy {}", id),
+            ObsidianError::InvalidFields(ref errors) => write!(f, "Invalid fields: {:?}", errors),
+            ObsidianError::WrongPassword => write!(f, "Wrong password"),
+            ObsidianError::AlreadyReturned => write!(f, "Lending already returned"),
+            ObsidianError::CopyUnavailable => write!(f, "Copy not available"),
+            ObsidianError::CopyStatusConflict => write!(f, "Copy status managed by lendings"),
+            ObsidianError::LastAdmin => write!(f, "Last admin of the school"),
+            ObsidianError::Forbidden(role) => write!(f, "Forbidden: needs role {}", role.as_str()),
+            ObsidianError::MissingScope(ref scope) => write!(f, "Missing scope: {}", scope),
+            ObsidianError::TooManyAttempts(secs) => write!(f, "Too many failed attempts: locked for {}s", secs),
+            ObsidianError::PostgresError(ref inner) => write!(f, "Database error: {:?}", inner),
+            ObsidianError::ConnectionError(ref inner) => write!(f, "Database connection error: {:?}", inner),
+            ObsidianError::GetConnError(ref inner) => write!(f, "No database connection available: {:?}", inner),
+            ObsidianError::PoolError(ref inner) => write!(f, "Database pool error: {:?}", inner),
+            ObsidianError::ParseError(ref inner) => write!(f, "Parser error: {:?}", inner),
+            ObsidianError::SerializeError(ref inner) => write!(f, "Serialisation error: {:?}", inner),
+            ObsidianError::IoError(ref inner) => write!(f, "Io error: {:?}", inner),
+            ObsidianError::BCryptError(ref inner) => write!(f, "BCrypt error: {:?}", inner),
+            ObsidianError::ConfigError(ref inner) => write!(f, "{}", inner)
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-struct BasicAuthenticate(String);
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+    use r2d2::{self, Config, Pool};
+    use r2d2_postgres::{PostgresConnectionManager, TlsMode};
+    use serde::Deserialize;
+    use serde::de::Error;
+    use serde_json::{self, Value};
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::time::Duration;
 
-impl THeader for BasicAuthenticate {
-    fn header_name() -> &'static str {
-        "WWW-Authenticate"
+    use crate::config::ConfigError;
+    use crate::models::Includable;
+    use crate::models::users::Role;
+    use super::{FieldError, ObsidianError, ReqError};
+
+    /// Converts `err` into a response and checks its status, its body and what it logs.
+    fn check(err: ObsidianError, status: StatusCode, body: &str, display: &str) -> Response {
+        assert_eq!(err.to_string(), display);
+        let (parts, written) = err.into_response().into_parts();
+        assert_eq!(parts.status, status);
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let written = runtime.block_on(axum::body::to_bytes(written, usize::MAX)).unwrap();
+        assert_eq!(String::from_utf8(written.to_vec()).unwrap(), body);
+        Response::from_parts(parts, axum::body::Body::empty())
     }
 
-    fn parse_header(_: &[Vec<u8>]) -> Result<Self, HttpError> {
-        unreachable!()
+    fn header(res: &Response, name: &str) -> Option<String> {
+        res.headers().get(name).map(|value| value.to_str().unwrap().to_string())
+    }
+
+    fn unreachable_pool(fail_fast: bool) -> Result<Pool<PostgresConnectionManager>, r2d2::InitializationError> {
+        let config = Config::builder()
+            .pool_size(1)
+            .initialization_fail_fast(fail_fast)
+            .connection_timeout(Duration::from_millis(50))
+            .build();
+        let manager = PostgresConnectionManager::new("postgres://obsidian@127.0.0.1:1/obsidian", TlsMode::None).unwrap();
+        Pool::new(config, manager)
+    }
+
+    fn io_error() -> io::Error {
+        io::Error::other("gone")
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Named {
+        #[allow(dead_code)]
+        name: String
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "lowercase")]
+    enum Kind {
+        Student,
+        Teacher
+    }
+
+    /// Checks the response to a body that `T` fails to decode from `body`.
+    fn check_parse<T: ::serde::de::DeserializeOwned + ::std::fmt::Debug>(body: &str, expected: &str) {
+        let err = serde_json::from_str::<T>(body).unwrap_err();
+        let display = format!("Parser error: {:?}", err);
+        check(ObsidianError::from(err), StatusCode::BAD_REQUEST, expected, &display);
+    }
+
+    #[test]
+    fn request_errors() {
+        check(ObsidianError::from(ReqError::NoID), StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid_id","message":"The id needs to be an integer","field":null,"details":null}"#,
+            "Invalid request: NoID");
+        check(ObsidianError::from(ReqError::WrongContentType), StatusCode::BAD_REQUEST,
+            r#"{"code":"expected_json","message":"Content-Type needs to be application/json","field":null,"details":null}"#,
+            "Invalid request: WrongContentType");
+        let res = check(ObsidianError::from(ReqError::NoAuth), StatusCode::UNAUTHORIZED,
+            r#"{"code":"missing_credentials","message":"The request needs to be authenticated","field":null,"details":null}"#,
+            "Invalid request: NoAuth");
+        assert_eq!(header(&res, "WWW-Authenticate"), Some("Basic: realm=\"Token and secret\"".to_string()));
+        check(ObsidianError::from(ReqError::InvalidParameter("page")), StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid_parameter","message":"A query parameter has an invalid value","field":"page","details":null}"#,
+            "Invalid request: InvalidParameter(\"page\")");
+        check(ObsidianError::from(ReqError::UnknownField("colour".to_string())), StatusCode::BAD_REQUEST,
+            r#"{"code":"unknown_field","message":"The field cannot be used for filtering or sorting","field":"colour","details":null}"#,
+            "Invalid request: UnknownField(\"colour\")");
+        check(ObsidianError::from(ReqError::ExpectedCsv), StatusCode::BAD_REQUEST,
+            r#"{"code":"expected_csv","message":"Content-Type needs to be text/csv","field":null,"details":null}"#,
+            "Invalid request: ExpectedCsv");
+        check(ObsidianError::from(ReqError::MissingColumn("name".to_string())), StatusCode::BAD_REQUEST,
+            r#"{"code":"missing_column","message":"The CSV file lacks a required column","field":"name","details":null}"#,
+            "Invalid request: MissingColumn(\"name\")");
+        check(ObsidianError::from(ReqError::BodyTooLarge(1024)), StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"code":"body_too_large","message":"The request body is too large","field":null,"details":"At most 1024 bytes are accepted"}"#,
+            "Invalid request: BodyTooLarge(1024)");
+    }
+
+    #[test]
+    fn parse_errors() {
+        check_parse::<Value>("{",
+            r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,"details":"The body is not valid JSON"}"#);
+        check_parse::<Value>("{]",
+            r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,"details":"The body is not valid JSON"}"#);
+        check_parse::<Named>("5",
+            r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,"details":"Expected struct Named, found integer `5`"}"#);
+        check_parse::<Named>("{}",
+            r#"{"code":"invalid_body","message":"The request body could not be parsed","field":"name","details":"The field is missing"}"#);
+        check_parse::<Kind>(r#""pupil""#,
+            r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,"details":"Unknown value pupil"}"#);
+        let custom = serde_json::Error::custom("Invalid date");
+        check(ObsidianError::from(custom), StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,"details":"Invalid date"}"#,
+            &format!("Parser error: {:?}", serde_json::Error::custom("Invalid date")));
+        check(ObsidianError::from(io_error()), StatusCode::BAD_REQUEST,
+            r#"{"code":"unreadable_body","message":"The request body could not be read","field":null,"details":null}"#,
+            &format!("Io error: {:?}", io_error()));
+        check(ObsidianError::from(Includable::Aliases), StatusCode::BAD_REQUEST,
+            r#"{"code":"include_not_supported","message":"The relation to be included is not supported by this route","field":"include","details":"Aliases"}"#,
+            "Aliases not supported as include");
+    }
+
+    #[test]
+    fn authentication_errors() {
+        for (err, display) in [
+            (ObsidianError::WrongPassword, "Wrong password".to_string()),
+            (ObsidianError::RecordNotFound("School"), "School not found".to_string()),
+            (ObsidianError::from(::bcrypt::BcryptError::InvalidCost(40)),
+                format!("BCrypt error: {:?}", ::bcrypt::BcryptError::InvalidCost(40)))
+        ] {
+            let res = check(err, StatusCode::UNAUTHORIZED,
+                r#"{"code":"invalid_credentials","message":"The credentials are invalid","field":null,"details":null}"#,
+                &display);
+            assert_eq!(header(&res, "WWW-Authenticate"), Some("Basic: realm=\"Token and secret\"".to_string()));
+        }
+        check(ObsidianError::Forbidden(Role::Admin), StatusCode::FORBIDDEN,
+            r#"{"code":"forbidden","message":"The user's role does not allow this request","field":null,"details":"Needs the role admin"}"#,
+            "Forbidden: needs role admin");
+        check(ObsidianError::MissingScope("books:write".to_string()), StatusCode::FORBIDDEN,
+            r#"{"code":"missing_scope","message":"The API key lacks the scope needed for this request","field":null,"details":"books:write"}"#,
+            "Missing scope: books:write");
+        let res = check(ObsidianError::TooManyAttempts(60), StatusCode::TOO_MANY_REQUESTS,
+            r#"{"code":"too_many_attempts","message":"Too many failed attempts, authentication is locked for now","field":null,"details":"Retry after 60 seconds"}"#,
+            "Too many failed attempts: locked for 60s");
+        assert_eq!(header(&res, "Retry-After"), Some("60".to_string()));
+    }
+
+    #[test]
+    fn record_errors() {
+        check(ObsidianError::RecordNotFound("Student"), StatusCode::NOT_FOUND,
+            r#"{"code":"not_found","message":"The record does not exist","field":null,"details":"Student not found"}"#,
+            "Student not found");
+        check(ObsidianError::AlreadyReturned, StatusCode::CONFLICT,
+            r#"{"code":"already_returned","message":"The lending has already been returned","field":null,"details":null}"#,
+            "Lending already returned");
+        check(ObsidianError::CopyUnavailable, StatusCode::CONFLICT,
+            r#"{"code":"copy_unavailable","message":"The copy is not available for lending","field":null,"details":null}"#,
+            "Copy not available");
+        check(ObsidianError::InvalidIsbn("123".to_string()), StatusCode::UNPROCESSABLE_ENTITY,
+            r#"{"code":"invalid_isbn","message":"The ISBN is not a valid ISBN-10 or ISBN-13","field":"isbn","details":"123"}"#,
+            "Invalid ISBN 123");
+        let res = check(ObsidianError::DuplicateIsbn(3), StatusCode::CONFLICT,
+            r#"{"code":"duplicate_isbn","message":"A book with this ISBN already exists","field":"isbn","details":"/books/3"}"#,
+            "ISBN already used by book 3");
+        assert_eq!(header(&res, "Location"), Some("/books/3".to_string()));
+        let res = check(ObsidianError::DuplicateInventoryNumber(4), StatusCode::CONFLICT,
+            r#"{"code":"duplicate_inventory_number","message":"A copy with this inventory number already exists","field":"inventory_number","details":"/copies/4"}"#,
+            "Inventory number already used by copy 4");
+        assert_eq!(header(&res, "Location"), Some("/copies/4".to_string()));
+        check(ObsidianError::CopyStatusConflict, StatusCode::CONFLICT,
+            r#"{"code":"copy_status_conflict","message":"Only lendings and base sets can mark a copy as lent or release it","field":"status","details":null}"#,
+            "Copy status managed by lendings");
+        check(ObsidianError::LastAdmin, StatusCode::CONFLICT,
+            r#"{"code":"last_admin","message":"The school needs to keep at least one admin","field":null,"details":null}"#,
+            "Last admin of the school");
+        check(ObsidianError::InvalidFields(vec![FieldError::new("name", "The name must not be empty"),
+            FieldError::new("graduation_year", "The graduation year must be between 2017 and 2030")]),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            r#"{"code":"invalid_fields","message":"Some fields of the record are invalid","field":"name","details":[{"field":"name","message":"The name must not be empty"},{"field":"graduation_year","message":"The graduation year must be between 2017 and 2030"}]}"#,
+            r#"Invalid fields: [FieldError { field: "name", message: "The name must not be empty" }, FieldError { field: "graduation_year", message: "The graduation year must be between 2017 and 2030" }]"#);
     }
-}
 
-impl HeaderFormat for BasicAuthenticate {
-    fn fmt_header(&self, f: &mut Formatter) -> Result<(), FError> {
-        let BasicAuthenticate(ref a) = *self;
-        write!(f, "Basic: realm=\"{}\"", a)
+    #[test]
+    fn internal_errors() {
+        let database = r#"{"code":"database_error","message":"The database could not process the request","field":null,"details":null}"#;
+        let display = format!("Database error: {:?}", ::postgres::error::Error::Io(io_error()));
+        check(ObsidianError::from(::postgres::error::Error::Io(io_error())), StatusCode::INTERNAL_SERVER_ERROR,
+            database, &display);
+        let display = format!("Database connection error: {:?}", ::postgres::error::ConnectError::Io(io_error()));
+        check(ObsidianError::from(::postgres::error::ConnectError::Io(io_error())), StatusCode::INTERNAL_SERVER_ERROR,
+            database, &display);
+        let err = unreachable_pool(true).err().unwrap();
+        let display = format!("Database pool error: {:?}", err);
+        check(ObsidianError::from(err), StatusCode::INTERNAL_SERVER_ERROR, database, &display);
+        let err = unreachable_pool(false).unwrap().get().err().unwrap();
+        let display = format!("No database connection available: {:?}", err);
+        check(ObsidianError::from(err), StatusCode::INTERNAL_SERVER_ERROR, database, &display);
+        let mut keyed_by_pairs = BTreeMap::new();
+        keyed_by_pairs.insert((1, 2), 3);
+        let err = serde_json::to_string(&keyed_by_pairs).unwrap_err();
+        let display = format!("Serialisation error: {:?}", err);
+        check(ObsidianError::SerializeError(err), StatusCode::INTERNAL_SERVER_ERROR,
+            r#"{"code":"serialisation_error","message":"The response could not be serialised","field":null,"details":null}"#,
+            &display);
+        check(ObsidianError::from(ConfigError::Missing("database.url")), StatusCode::INTERNAL_SERVER_ERROR,
+            r#"{"code":"configuration_error","message":"The server is misconfigured","field":null,"details":null}"#,
+            "Configuration value missing: database.url");
     }
 }
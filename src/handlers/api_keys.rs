@@ -0,0 +1,31 @@
+use chrono::UTC;
+
+use crate::handlers::{HandlerResult, Request, check_content_type, get_db, get_id, get_school_id, parse};
+use crate::models::api_keys::{ApiKey, NewApiKey};
+
+pub fn index(req: &mut Request) -> HandlerResult {
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let keys = ApiKey::find_all(school_id, conn)?;
+    println!("[{}] Successfully handled api_keys::index", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, keys)
+}
+
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let new_key = parse::<NewApiKey>(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let key = new_key.save(school_id, conn)?;
+    println!("[{}] Successfully handled api_keys::new", UTC::now().format("%FT%T%:z"));
+    respond_with!(CREATED, key)
+}
+
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    ApiKey::revoke(id, school_id, conn)?;
+    println!("[{}] Successfully handled api_keys::delete", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
+}
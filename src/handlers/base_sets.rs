@@ -1,43 +1,61 @@
 use chrono::UTC;
-use iron::{IronResult, Request, Response};
-use postgres::Connection;
 
-use error::ObsidianError;
-use handlers::{check_content_type, get_db, get_id, get_school_id, parse, serialise};
-use models::Model;
-use models::base_sets::BaseSet;
+use crate::handlers::{HandlerResult, Request, check_content_type, create, get_body, get_db, get_id, get_includes,
+    get_query, get_school_id, get_time_param, parse, respond_with_page};
+use crate::models::Model;
+use crate::models::base_sets::{Assignment, BaseSet, BaseSetFilter};
 
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    fn single(req: &Request, school_id: usize, conn: &Connection) -> IronResult<String> {
-        let base_set = try!(parse::<BaseSet>(req));
-        let base_set = try!(base_set.save(None, school_id, conn));
-        let ser = try!(serialise(base_set));
-        Ok(ser)
-    }
+pub fn index(req: &mut Request) -> HandlerResult {
+    let query = get_query(req, &["since"])?;
+    let filter = BaseSetFilter {
+        since: get_time_param(req, "since")?
+    };
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let base_sets = BaseSet::find_filtered(school_id, &query, &filter, conn, &includes)?;
+    println!("[{}] Successfully handled base_sets::index (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with_page(req, &query, base_sets)
+}
 
-    fn multiple(req: &Request, school_id: usize, conn: &Connection) -> IronResult<String> {
-        let base_sets = try!(parse::<Vec<BaseSet>>(req));
-        let base_sets = try!(base_sets
-            .into_iter()
-            .map(|base_set| base_set.save(None, school_id, conn))
-            .collect::<Result<Vec<BaseSet>, ObsidianError>>());
-        let ser = try!(serialise(base_sets));
-        Ok(ser)
-    }
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let base_set = BaseSet::find_id(id, school_id, conn, &includes)?;
+    println!("[{}] Successfully handled base_sets::show (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with!(OK, base_set)
+}
 
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    try!(check_content_type(req));
-    let ser = try!(single(req, school_id, conn).or_else(|_| multiple(req, school_id, conn)));
+    let res = create::<BaseSet>(req, school_id, conn)?;
     println!("[{}] Successfully handled base_sets::new", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, ser)
+    Ok(res)
+}
+
+pub fn assign(req: &mut Request) -> HandlerResult {
+    let assignment = if get_body(req).trim().is_empty() {
+        Assignment::default()
+    } else {
+        check_content_type(req)?;
+        parse::<Assignment>(req)?
+    };
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let summary = assignment.perform(school_id, conn)?;
+    println!("[{}] Successfully handled base_sets::assign", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, summary)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    try!(BaseSet::delete(id, school_id, conn));
+    BaseSet::delete(id, school_id, conn)?;
     println!("[{}] Successfully handled base_sets::delete", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
 }
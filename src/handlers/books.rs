@@ -1,55 +1,71 @@
 use chrono::UTC;
-use iron::{IronResult, Request, Response};
 
-use handlers::{check_content_type, get_db, get_id, get_includes, get_school_id, parse};
-use models::Model;
-use models::books::Book;
+use crate::handlers::{HandlerResult, Request, check_content_type, get_body, get_db, get_id, get_includes, get_query,
+    get_school_id, respond_with_csv, respond_with_page, validate, wants_csv};
+use crate::models::Model;
+use crate::models::books::Book;
 
-pub fn index(req: &mut Request) -> IronResult<Response> {
+pub fn index(req: &mut Request) -> HandlerResult {
+    if wants_csv(req) {
+        return export(req);
+    }
+    let query = get_query(req, &[])?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
     let includes = get_includes(req);
-    let books = try!(Book::find_all(school_id, conn, &includes));
+    let books = Book::find_page(school_id, &query, conn, &includes)?;
     println!("[{}] Successfully handled books::index (includes={:?})", UTC::now().format("%FT%T%:z"), &includes);
-    respond_with!(Ok, books)
+    respond_with_page(req, &query, books)
 }
 
-pub fn show(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn export(req: &mut Request) -> HandlerResult {
+    let query = get_query(req, &[])?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
     let includes = get_includes(req);
-    let book = try!(Book::find_id(id, school_id, conn, &includes));
+    let books = Book::find_page(school_id, &query, conn, &includes)?;
+    println!("[{}] Successfully handled books::export (includes={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with_csv(req, "books.csv", &books.items, &includes)
+}
+
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let book = Book::find_id(id, school_id, conn, &includes)?;
     println!("[{}] Successfully handled books::show (includes={:?})", UTC::now().format("%FT%T%:z"), &includes);
-    respond_with!(Ok, book)
+    respond_with!(OK, book)
 }
 
-pub fn edit(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let book = try!(parse::<Book>(req));
-    let id = try!(get_id(req));
+pub fn edit(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let book = Book::parse_str(get_body(req))?;
+    validate(req, &book)?;
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let book = try!(book.save(Some(id), school_id, conn));
+    let book = book.save(Some(id), school_id, conn)?;
     println!("[{}] Successfully handled books::edit", UTC::now().format("%FT%T%:z"));
-    respond_with!(Ok, book)
+    respond_with!(OK, book)
 }
 
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let book = try!(parse::<Book>(req));
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let book = Book::parse_str(get_body(req))?;
+    validate(req, &book)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let book = try!(book.save(None, school_id, conn));
+    let book = book.save(None, school_id, conn)?;
     println!("[{}] Successfully handled books::new", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, book)
+    respond_with!(CREATED, book)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    try!(Book::delete(id, school_id, conn));
+    Book::delete(id, school_id, conn)?;
     println!("[{}] Successfully handled books::delete", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
 }
@@ -0,0 +1,56 @@
+use chrono::UTC;
+
+use crate::handlers::{HandlerResult, Request, check_content_type, get_db, get_id, get_includes, get_query,
+    get_school_id, parse, respond_with_page};
+use crate::models::Model;
+use crate::models::copies::BookCopy;
+
+pub fn index(req: &mut Request) -> HandlerResult {
+    let query = get_query(req, &[])?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let copies = BookCopy::find_page(school_id, &query, conn, &includes)?;
+    println!("[{}] Successfully handled copies::index (includes={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with_page(req, &query, copies)
+}
+
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let copy = BookCopy::find_id(id, school_id, conn, &includes)?;
+    println!("[{}] Successfully handled copies::show (includes={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with!(OK, copy)
+}
+
+pub fn edit(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let copy = parse::<BookCopy>(req)?;
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let copy = copy.save(Some(id), school_id, conn)?;
+    println!("[{}] Successfully handled copies::edit", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, copy)
+}
+
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let copy = parse::<BookCopy>(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let copy = copy.save(None, school_id, conn)?;
+    println!("[{}] Successfully handled copies::new", UTC::now().format("%FT%T%:z"));
+    respond_with!(CREATED, copy)
+}
+
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    BookCopy::delete(id, school_id, conn)?;
+    println!("[{}] Successfully handled copies::delete", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
+}
@@ -1,43 +1,97 @@
 use chrono::UTC;
-use iron::{IronResult, Request, Response};
-use postgres::Connection;
-
-use error::ObsidianError;
-use handlers::{check_content_type, get_db, get_id, get_school_id, parse, serialise};
-use models::Model;
-use models::lendings::Lending;
-
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    fn single(req: &Request, school_id: usize, conn: &Connection) -> IronResult<String> {
-        let lending = try!(parse::<Lending>(req));
-        let lending = try!(lending.save(None, school_id, conn));
-        let ser = try!(serialise(lending));
-        Ok(ser)
+
+use crate::error::{ObsidianError, ReqError};
+use crate::handlers::{HandlerResult, Request, check_content_type, create, get_body, get_db, get_id, get_includes,
+    get_parsed_param, get_query, get_school_id, get_time_param, parse, respond_with_csv, respond_with_page,
+    wants_csv};
+use crate::models::{Model, Query};
+use crate::models::lendings::{Lending, LendingFilter, Return};
+use crate::models::overdue::OverdueReport;
+
+fn get_filter(req: &Request) -> Result<(Query, LendingFilter), ObsidianError> {
+    let query = get_query(req, &["since", "open"])?;
+    for (field, value) in &query.filters {
+        if field == "person_type" && value != "student" && value != "teacher" {
+            return Err(ObsidianError::from(ReqError::InvalidParameter("person_type")));
+        }
     }
+    let filter = LendingFilter {
+        since: get_time_param(req, "since")?,
+        open: get_parsed_param(req, "open")?
+    };
+    Ok((query, filter))
+}
 
-    fn multiple(req: &Request, school_id: usize, conn: &Connection) -> IronResult<String> {
-        let lendings = try!(parse::<Vec<Lending>>(req));
-        let lendings = try!(lendings
-            .into_iter()
-            .map(|lending| lending.save(None, school_id, conn))
-            .collect::<Result<Vec<Lending>, ObsidianError>>());
-        let ser = try!(serialise(lendings));
-        Ok(ser)
+pub fn index(req: &mut Request) -> HandlerResult {
+    if wants_csv(req) {
+        return export(req);
     }
+    let (query, filter) = get_filter(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let lendings = Lending::find_filtered(school_id, &query, &filter, conn, &includes)?;
+    println!("[{}] Successfully handled lendings::index (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with_page(req, &query, lendings)
+}
+
+pub fn export(req: &mut Request) -> HandlerResult {
+    let (query, filter) = get_filter(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let lendings = Lending::find_filtered(school_id, &query, &filter, conn, &includes)?;
+    println!("[{}] Successfully handled lendings::export (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with_csv(req, "lendings.csv", &lendings.items, &includes)
+}
+
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let lending = Lending::find_id(id, school_id, conn, &includes)?;
+    println!("[{}] Successfully handled lendings::show (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with!(OK, lending)
+}
 
-    try!(check_content_type(req));
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let ser = try!(single(req, school_id, conn).or_else(|_| multiple(req, school_id, conn)));
+    let res = create::<Lending>(req, school_id, conn)?;
     println!("[{}] Successfully handled lendings::new", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, ser)
+    Ok(res)
+}
+
+pub fn overdue(req: &mut Request) -> HandlerResult {
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let report = OverdueReport::find(school_id, conn)?;
+    println!("[{}] Successfully handled lendings::overdue", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, report)
+}
+
+pub fn return_book(req: &mut Request) -> HandlerResult {
+    let ret = if get_body(req).trim().is_empty() {
+        Return::default()
+    } else {
+        check_content_type(req)?;
+        parse::<Return>(req)?
+    };
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let lending = ret.perform(id, school_id, conn)?;
+    println!("[{}] Successfully handled lendings::return_book", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, lending)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    try!(Lending::delete(id, school_id, conn));
+    Lending::delete(id, school_id, conn)?;
     println!("[{}] Successfully handled lendings::delete", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
 }
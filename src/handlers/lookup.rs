@@ -0,0 +1,16 @@
+use chrono::UTC;
+
+use crate::error::{ObsidianError, ReqError};
+use crate::handlers::{HandlerResult, Request, get_db, get_param, get_school_id};
+use crate::models::lookup;
+
+pub fn index(req: &mut Request) -> HandlerResult {
+    let code = get_param(req, "code")
+        .and_then(|code| if code.trim().is_empty() { None } else { Some(code) })
+        .ok_or_else(|| ObsidianError::from(ReqError::InvalidParameter("code")))?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let hits = lookup::lookup(&code, school_id, conn)?;
+    println!("[{}] Successfully handled lookup::index ({} hits)", UTC::now().format("%FT%T%:z"), hits.len());
+    respond_with!(OK, hits)
+}
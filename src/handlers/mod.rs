@@ -1,87 +1,423 @@
 macro_rules! respond_with {
     ($st:ident) => (
-        ::std::result::Result::Ok(::iron::response::Response::with(::iron::status::Status::$st))
+        ::std::result::Result::Ok(::axum::response::IntoResponse::into_response(::axum::http::StatusCode::$st))
     );
     ($st:ident, $body:expr) => ({
-        let ser = try!($crate::handlers::serialise($body));
-        ::std::result::Result::Ok(::iron::response::Response::with((::iron::status::Status::$st, ser,
-            ::iron::modifiers::Header(::iron::headers::ContentType::json()))))
+        let ser = $crate::handlers::serialise($body)?;
+        ::std::result::Result::Ok($crate::handlers::json_response(::axum::http::StatusCode::$st, ser))
     });
 }
 
 pub mod students;
 pub mod books;
 pub mod aliases;
+pub mod api_keys;
+pub mod copies;
 pub mod teachers;
 pub mod base_sets;
 pub mod lendings;
+pub mod lookup;
 pub mod schools;
+pub mod search;
 pub mod sessions;
+pub mod users;
 
-use iron::{Chain, Handler, Request};
-use iron::headers::ContentType;
-use iron::mime::{TopLevel, SubLevel, Mime};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::http::header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE, LINK, USER_AGENT};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDate, UTC};
 use postgres::Connection;
-use router::Router;
+use r2d2::PooledConnection;
+use r2d2_postgres::PostgresConnectionManager;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::collections::HashSet;
-use rustc_serialize::{Decodable, Encodable, json};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value as Json;
 
-use error::{ObsidianError, ReqError};
-use models::{Includable, Includes};
-use middleware::{PostgresConnection, RequestBody, SchoolID};
+use crate::error::{FieldError, ObsidianError, Problem, ReqError};
+use crate::models::{csv, Includable, Includes, Model, Page, Query, Validate};
+use crate::models::schools::ValidationRules;
+use crate::models::csv::ToCsv;
+use crate::models::api_keys::KeyAccess;
+use crate::models::users::{Account, Role};
+use crate::middleware::{Access, Endpoint};
+
+/// A request as handlers see it: read in full, with a connection from the pool and, on
+/// authenticated routes, the school and the user or API key making it.
+pub struct Request {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub remote_addr: SocketAddr,
+    pub body: String,
+    pub id: Option<String>,
+    pub conn: PooledConnection<PostgresConnectionManager>,
+    pub school_id: Option<usize>,
+    pub user: Option<Account>,
+    pub key: Option<KeyAccess>
+}
+
+pub type HandlerResult = Result<Response, ObsidianError>;
+
+fn json_response(status: StatusCode, body: String) -> Response {
+    (status, [(CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// The media type of a header like `Content-Type`, without its parameters.
+fn media_type(value: &str) -> String {
+    value.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+fn get_content_type(req: &Request) -> Option<String> {
+    req.headers.get(CONTENT_TYPE)
+        .and_then(|ctype| ctype.to_str().ok())
+        .map(media_type)
+}
 
 fn check_content_type(req: &Request) -> Result<(), ObsidianError> {
-    try!(req.headers.get::<ContentType>()
-        .and_then(|ctype| match **ctype {
-            Mime(TopLevel::Application, SubLevel::Json, _) => Some(()),
-            _ => None
-        }).ok_or(ReqError::WrongContentType));
-    Ok(())
+    match get_content_type(req) {
+        Some(ref ctype) if ctype == "application/json" => Ok(()),
+        _ => Err(ObsidianError::from(ReqError::WrongContentType))
+    }
+}
+
+fn check_csv_content_type(req: &Request) -> Result<(), ObsidianError> {
+    match get_content_type(req) {
+        Some(ref ctype) if ctype == "text/csv" => Ok(()),
+        _ => Err(ObsidianError::from(ReqError::ExpectedCsv))
+    }
 }
 
 fn get_id(req: &Request) -> Result<usize, ObsidianError> {
-    let router_params = req.extensions.get::<Router>().unwrap();
-    let id = router_params.find("id").unwrap();
-    usize::from_str(&id).map_err(|_| ObsidianError::from(ReqError::NoID))
+    let id = req.id.as_ref().unwrap();
+    usize::from_str(id).map_err(|_| ObsidianError::from(ReqError::NoID))
+}
+
+fn get_body(req: &Request) -> &str {
+    &req.body
+}
+
+fn get_rules(req: &Request) -> Result<ValidationRules, ObsidianError> {
+    match req.school_id {
+        Some(school_id) => ValidationRules::load(school_id, get_db(req)),
+        None => Ok(ValidationRules::default())
+    }
 }
 
-fn get_body<'a>(req: &'a Request) -> &'a str {
-    req.extensions.get::<RequestBody>().unwrap().as_ref()
+fn validate<T: Validate>(req: &Request, record: &T) -> Result<(), ObsidianError> {
+    let rules = get_rules(req)?;
+    record.check(&rules)
 }
 
-fn parse<T: Decodable>(req: &Request) -> Result<T, ObsidianError> {
-    json::decode::<T>(get_body(req)).map_err(ObsidianError::from)
+fn parse<T: DeserializeOwned + Validate>(req: &Request) -> Result<T, ObsidianError> {
+    let record = serde_json::from_str::<T>(get_body(req))?;
+    validate(req, &record)?;
+    Ok(record)
 }
 
-fn serialise<T: Encodable>(t: T) -> Result<String, ObsidianError> {
-    json::encode(&t).map_err(ObsidianError::from)
+fn serialise<T: Serialize>(t: T) -> Result<String, ObsidianError> {
+    serde_json::to_string(&t).map_err(ObsidianError::SerializeError)
 }
 
-fn get_db<'a>(req: &'a Request) -> &'a Connection {
-    &**req.extensions.get::<PostgresConnection>().unwrap()
+fn get_db(req: &Request) -> &Connection {
+    &req.conn
 }
 
 fn get_includes(req: &Request) -> Includes {
-    req.url.query()
+    req.uri.query()
         .and_then(|query| query
             .split('&')
             .filter_map(|item|
                 if item.starts_with("include=") {
-                    Some(Includable::parse_str(item.trim_left_matches("include=")))
+                    Some(Includable::parse_str(item.trim_start_matches("include=")))
                 } else {
                     None
                 })
             .next())
-        .unwrap_or(HashSet::new())
+        .unwrap_or_default()
+}
+
+fn decode_component(val: &str) -> String {
+    let bytes = val.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            ::std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn get_param(req: &Request, name: &str) -> Option<String> {
+    req.uri.query()
+        .and_then(|query| query
+            .split('&')
+            .filter_map(|item| {
+                let mut pair = item.splitn(2, '=');
+                match (pair.next(), pair.next()) {
+                    (Some(key), Some(val)) if key == name => Some(decode_component(val)),
+                    _ => None
+                }
+            })
+            .next())
+}
+
+fn get_parsed_param<T: FromStr>(req: &Request, name: &'static str) -> Result<Option<T>, ObsidianError> {
+    match get_param(req, name) {
+        Some(val) => T::from_str(&val)
+            .map(Some)
+            .map_err(|_| ObsidianError::from(ReqError::InvalidParameter(name))),
+        None => Ok(None)
+    }
+}
+
+fn get_time_param(req: &Request, name: &'static str) -> Result<Option<DateTime<UTC>>, ObsidianError> {
+    match get_param(req, name) {
+        Some(val) => DateTime::parse_from_rfc3339(&val)
+            .map(|time| time.with_timezone(&UTC))
+            .or_else(|_| NaiveDate::parse_from_str(&val, "%Y-%m-%d")
+                .map(|date| DateTime::from_utc(date.and_hms(0, 0, 0), UTC)))
+            .map(Some)
+            .map_err(|_| ObsidianError::from(ReqError::InvalidParameter(name))),
+        None => Ok(None)
+    }
+}
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 1000;
+
+/// Reads filters, sort order and page from the query string. Every parameter other than
+/// `include`, `delimiter`, `bom` and the route's own `params` is a filter on the field of
+/// the same name, which the model rejects unless it knows the field.
+fn get_query(req: &Request, params: &[&str]) -> Result<Query, ObsidianError> {
+    let mut query = Query::default();
+    let items = req.uri.query().map(|query| query.split('&').collect::<Vec<&str>>()).unwrap_or_default();
+    for item in items {
+        let mut pair = item.splitn(2, '=');
+        let key = decode_component(pair.next().unwrap_or(""));
+        let val = decode_component(pair.next().unwrap_or(""));
+        match key.as_ref() {
+            "" | "include" | "delimiter" | "bom" => {},
+            key if params.contains(&key) => {},
+            "sort" => {
+                query.sort = val.split(',')
+                    .filter(|field| !field.is_empty())
+                    .map(|field| match field.strip_prefix('-') {
+                        Some(field) => (field.to_string(), true),
+                        None => (field.to_string(), false)
+                    })
+                    .collect();
+            },
+            "page" => {
+                query.page = usize::from_str(&val).ok()
+                    .and_then(|page| if page >= 1 { Some(page) } else { None })
+                    .ok_or(ReqError::InvalidParameter("page"))?;
+            },
+            "per_page" => {
+                query.per_page = Some(usize::from_str(&val).ok()
+                    .and_then(|per_page| if (1..=MAX_PER_PAGE).contains(&per_page) { Some(per_page) } else { None })
+                    .ok_or(ReqError::InvalidParameter("per_page"))?);
+            },
+            _ => query.filters.push((key, val))
+        }
+    }
+    if query.page == 0 {
+        query.page = 1;
+    } else if query.per_page.is_none() {
+        query.per_page = Some(DEFAULT_PER_PAGE);
+    }
+    Ok(query)
+}
+
+fn page_link(req: &Request, page: usize, rel: &str) -> String {
+    let page = format!("page={}", page);
+    let mut params = req.uri.query()
+        .map(|query| query
+            .split('&')
+            .filter(|item| !item.is_empty() && !item.starts_with("page="))
+            .collect::<Vec<&str>>())
+        .unwrap_or_default();
+    params.push(&page);
+    format!("<?{}>; rel=\"{}\"", params.join("&"), rel)
+}
+
+fn respond_with_page<T: Serialize>(req: &Request, query: &Query, page: Page<T>) -> HandlerResult {
+    let total = page.total;
+    let ser = serialise(page.items)?;
+    let mut res = json_response(StatusCode::OK, ser);
+    res.headers_mut().insert("X-Total-Count", HeaderValue::from(total));
+    if let Some(per_page) = query.per_page {
+        let last = ::std::cmp::max(1, total.div_ceil(per_page));
+        let mut links = vec![page_link(req, 1, "first")];
+        if query.page > 1 {
+            links.push(page_link(req, ::std::cmp::min(query.page - 1, last), "prev"));
+        }
+        if query.page < last {
+            links.push(page_link(req, query.page + 1, "next"));
+        }
+        links.push(page_link(req, last, "last"));
+        res.headers_mut().insert(LINK, header_value(links.join(", ")));
+    }
+    Ok(res)
+}
+
+#[derive(Serialize)]
+struct ItemResult<T> {
+    index: usize,
+    status: u16,
+    record: Option<T>,
+    error: Option<Problem>
+}
+
+/// Decodes an item of an array through `parse_str`, so that it is read like a single record.
+fn parse_item<T: Model>(item: Json) -> Result<T, ObsidianError> {
+    T::parse_str(&item.to_string())
+}
+
+fn save_record<T: Model>(record: T, school_id: usize, conn: &Connection) -> Result<T, ObsidianError> {
+    let trans = conn.transaction()?;
+    let record = record.save(None, school_id, conn)?;
+    trans.commit()?;
+    Ok(record)
+}
+
+/// Validates all items at once and reports the field errors of every invalid item,
+/// prefixing the fields with the index of the item.
+fn save_all<T: Model>(items: Vec<Json>, rules: &ValidationRules, school_id: usize, conn: &Connection)
+    -> Result<Vec<T>, ObsidianError> {
+    let records = items.into_iter()
+        .map(parse_item)
+        .collect::<Result<Vec<T>, ObsidianError>>()?;
+    let errors = records.iter()
+        .enumerate()
+        .flat_map(|(index, record)| record.validate(rules).into_iter().map(move |mut error| {
+            error.field = format!("{}.{}", index, error.field);
+            error
+        }))
+        .collect::<Vec<FieldError>>();
+    if !errors.is_empty() {
+        return Err(ObsidianError::InvalidFields(errors));
+    }
+    let trans = conn.transaction()?;
+    let records = records.into_iter()
+        .map(|record| record.save(None, school_id, conn))
+        .collect::<Result<Vec<T>, ObsidianError>>()?;
+    trans.commit()?;
+    Ok(records)
+}
+
+/// Creates a single record for a JSON object or one per item for a JSON array. An array is
+/// saved in one transaction, unless `atomic=false` is given: then every item is saved on its
+/// own and the response lists the created record or the error for each index.
+fn create<T: Model>(req: &Request, school_id: usize, conn: &Connection) -> HandlerResult {
+    let rules = get_rules(req)?;
+    let body = serde_json::from_str::<Json>(get_body(req)).map_err(ObsidianError::from)?;
+    let items = match body {
+        Json::Array(items) => items,
+        _ => {
+            let record = T::parse_str(get_body(req))?;
+            record.check(&rules)?;
+            let record = save_record(record, school_id, conn)?;
+            return respond_with!(CREATED, record);
+        }
+    };
+    if get_parsed_param::<bool>(req, "atomic")?.unwrap_or(true) {
+        let records = save_all::<T>(items, &rules, school_id, conn)?;
+        respond_with!(CREATED, records)
+    } else {
+        let results = items.into_iter()
+            .enumerate()
+            .map(|(index, item)| match parse_item::<T>(item)
+                .and_then(|record| record.check(&rules).map(|_| record))
+                .and_then(|record| save_record(record, school_id, conn)) {
+                Ok(record) => ItemResult {
+                    index,
+                    status: StatusCode::CREATED.as_u16(),
+                    record: Some(record),
+                    error: None
+                },
+                Err(err) => ItemResult {
+                    index,
+                    status: err.status().as_u16(),
+                    record: None,
+                    error: Some(err.problem())
+                }
+            })
+            .collect::<Vec<ItemResult<T>>>();
+        respond_with!(MULTI_STATUS, results)
+    }
+}
+
+fn get_delimiter(req: &Request) -> Result<char, ObsidianError> {
+    match get_param(req, "delimiter") {
+        Some(ref delimiter) if delimiter.chars().count() == 1 => Ok(delimiter.chars().next().unwrap()),
+        Some(_) => Err(ObsidianError::from(ReqError::InvalidParameter("delimiter"))),
+        None => Ok(',')
+    }
+}
+
+fn wants_csv(req: &Request) -> bool {
+    req.headers.get_all(ACCEPT).iter()
+        .filter_map(|accept| accept.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .any(|item| media_type(item) == "text/csv")
+}
+
+/// Responds with the records as a CSV attachment. `delimiter` and `bom` allow for the
+/// semicolons and byte order mark expected by Excel in German locales.
+fn respond_with_csv<T: ToCsv>(req: &Request, filename: &'static str, records: &[T], includes: &Includes)
+    -> HandlerResult {
+    let delimiter = get_delimiter(req)?;
+    let bom = get_parsed_param::<bool>(req, "bom")?.unwrap_or(false);
+    let body = csv::write(records, includes, delimiter, bom);
+    let disposition = format!("attachment; filename=\"{}\"", filename);
+    let headers = [(CONTENT_TYPE, "text/csv; charset=utf-8".to_string()), (CONTENT_DISPOSITION, disposition)];
+    Ok((StatusCode::OK, headers, body).into_response())
 }
 
 fn get_school_id(req: &Request) -> usize {
-    *req.extensions.get::<SchoolID>().unwrap()
+    req.school_id.unwrap()
+}
+
+fn get_user(req: &Request) -> Account {
+    req.user.unwrap()
+}
+
+fn get_user_agent(req: &Request) -> Option<&str> {
+    req.headers.get(USER_AGENT).and_then(|agent| agent.to_str().ok())
+}
+
+/// Header values built from the query string, which only holds visible ASCII.
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static(""))
+}
+
+/// Leaves checking credentials to the handler, if it needs any.
+pub fn open(h: fn(&mut Request) -> HandlerResult) -> Endpoint {
+    Endpoint::new(Access::Public, h)
+}
+
+/// Requires a token and a role fitting the method, so that read-only users can only read.
+pub fn auth(h: fn(&mut Request) -> HandlerResult) -> Endpoint {
+    Endpoint::new(Access::ByMethod, h)
 }
 
-pub fn auth<H: Handler>(h: H) -> Chain {
-    let mut res = Chain::new(h);
-    res.link_before(SchoolID::new());
-    res
+/// Requires a token of a user with at least the role `role`.
+pub fn auth_as(role: Role, h: fn(&mut Request) -> HandlerResult) -> Endpoint {
+    Endpoint::new(Access::AtLeast(role), h)
 }
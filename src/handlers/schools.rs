@@ -1,48 +1,101 @@
 use chrono::UTC;
-use iron::{IronResult, Request, Response};
-use postgres::Connection;
+use serde::de::Error;
+use serde_json::Value as Json;
+use std::net::IpAddr;
 
-use handlers::{check_content_type, get_db, get_school_id, parse};
-use models::schools::{AuthData, NameChange, PasswordChange, Deletion};
-use models::sessions::AuthToken;
+use crate::error::ObsidianError;
+use crate::handlers::{HandlerResult, Request, check_content_type, get_body, get_db, get_parsed_param, get_school_id,
+    get_user, get_user_agent, parse};
+use crate::models::schools::{AuthData, NameChange, LoanPeriodChange, RulesChange, SessionChange, Deletion};
+use crate::models::rollover::Rollover;
+use crate::models::{schools, transaction};
+use crate::models::throttle;
+use crate::models::sessions::AuthToken;
 
-pub fn edit(req: &mut Request) -> IronResult<Response> {
-    fn password(req: &Request, id: usize, conn: &Connection) -> IronResult<()> {
-        let pw_change = try!(parse::<PasswordChange>(req));
-        try!(pw_change.perform(id, conn));
-        Ok(())
-    }
-
-    fn name(req: &Request, id: usize, conn: &Connection) -> IronResult<()> {
-        let name_change = try!(parse::<NameChange>(req));
-        try!(name_change.perform(id, conn));
-        Ok(())
-    }
-
-    try!(check_content_type(req));
+pub fn edit(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
     let id = get_school_id(req);
     let conn = get_db(req);
-    try!(password(req, id, conn).or_else(|_| name(req, id, conn)));
+    let body = serde_json::from_str::<Json>(get_body(req)).map_err(ObsidianError::from)?;
+    let has = |key: &str| body.get(key).is_some();
+    let name = if has("name") { Some(parse::<NameChange>(req)?) } else { None };
+    let loan_period = if has("loan_period_days") { Some(parse::<LoanPeriodChange>(req)?) } else { None };
+    let rules = if has("class_letters") || has("graduation_years_back") || has("graduation_years_ahead") {
+        Some(parse::<RulesChange>(req)?)
+    } else {
+        None
+    };
+    let sessions = if has("session_lifetime_hours") || has("session_idle_minutes") {
+        Some(parse::<SessionChange>(req)?)
+    } else {
+        None
+    };
+    if name.is_none() && loan_period.is_none() && rules.is_none() && sessions.is_none() {
+        return Err(ObsidianError::from(serde_json::Error::custom(format!(
+            "Expected name, loan_period_days, class_letters or session_lifetime_hours, found {}", body))));
+    }
+    // A body may combine changes, which are applied together or not at all.
+    transaction(conn, || {
+        if let Some(ref change) = name {
+            change.perform(id, conn)?;
+        }
+        if let Some(ref change) = loan_period {
+            change.perform(id, conn)?;
+        }
+        if let Some(ref change) = rules {
+            change.perform(id, conn)?;
+        }
+        if let Some(ref change) = sessions {
+            change.perform(id, conn)?;
+        }
+        Ok(())
+    })?;
     println!("[{}] Successfully handled schools::edit", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
 }
 
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let auth_data = try!(parse::<AuthData>(req));
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let auth_data = parse::<AuthData>(req)?;
     let conn = get_db(req);
-    let id = try!(auth_data.save(conn));
-    let token = try!(AuthToken::new(id, conn));
+    let account = auth_data.save(conn)?;
+    let token = AuthToken::new(&account, get_user_agent(req), conn)?;
     println!("[{}] Successfully handled schools::new", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, token)
+    respond_with!(CREATED, token)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let deletion = try!(parse::<Deletion>(req));
+pub fn rollover(req: &mut Request) -> HandlerResult {
+    let rollover = if get_body(req).trim().is_empty() {
+        Rollover::default()
+    } else {
+        check_content_type(req)?;
+        parse::<Rollover>(req)?
+    };
     let id = get_school_id(req);
     let conn = get_db(req);
-    try!(deletion.perform(id, conn));
+    let summary = rollover.perform(id, conn)?;
+    println!("[{}] Successfully handled schools::rollover", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, summary)
+}
+
+pub fn delete(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let deletion = parse::<Deletion>(req)?;
+    let id = get_school_id(req);
+    let user = get_user(req);
+    let conn = get_db(req);
+    deletion.perform(id, user.id, conn)?;
     println!("[{}] Successfully handled schools::delete", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
+}
+
+pub fn unlock(req: &mut Request) -> HandlerResult {
+    let id = get_school_id(req);
+    let conn = get_db(req);
+    let name = schools::name(id, conn)?;
+    let address = get_parsed_param::<IpAddr>(req, "address")?.unwrap_or(req.remote_addr.ip());
+    throttle::clear_school(&name, conn)?;
+    throttle::clear(&[throttle::ip_key(&name, &address), throttle::global_ip_key(&address)], conn)?;
+    println!("[{}] Successfully handled schools::unlock", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
 }
@@ -0,0 +1,22 @@
+use chrono::UTC;
+
+use crate::error::{ObsidianError, ReqError};
+use crate::handlers::{HandlerResult, Request, get_db, get_param, get_parsed_param, get_school_id};
+use crate::models::search::{self, DEFAULT_LIMIT, MAX_LIMIT};
+
+pub fn index(req: &mut Request) -> HandlerResult {
+    let term = get_param(req, "q")
+        .and_then(|term| if term.trim().is_empty() { None } else { Some(term) })
+        .ok_or_else(|| ObsidianError::from(ReqError::InvalidParameter("q")))?;
+    let limit = get_parsed_param::<usize>(req, "limit")?.unwrap_or(DEFAULT_LIMIT);
+    (if (1..=MAX_LIMIT).contains(&limit) {
+        Ok(())
+    } else {
+        Err(ObsidianError::from(ReqError::InvalidParameter("limit")))
+    })?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let hits = search::search(&term, limit, school_id, conn)?;
+    println!("[{}] Successfully handled search::index ({} hits)", UTC::now().format("%FT%T%:z"), hits.len());
+    respond_with!(OK, hits)
+}
@@ -1,27 +1,56 @@
 use chrono::UTC;
-use iron::{IronResult, Request, Response};
-use iron::headers::{Authorization, Basic};
 
-use error::{ObsidianError, ReqError};
-use handlers::{check_content_type, get_db, parse};
-use models::schools::AuthData;
-use models::sessions::AuthToken;
+use crate::handlers::{HandlerResult, Request, check_content_type, get_db, get_id, get_user, get_user_agent, parse};
+use crate::middleware::Basic;
+use crate::models::schools::AuthData;
+use crate::models::sessions::{AuthToken, Session};
+use crate::models::throttle;
 
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let auth = try!(parse::<AuthData>(req));
+pub fn index(req: &mut Request) -> HandlerResult {
+    let user = get_user(req);
     let conn = get_db(req);
-    let id = try!(auth.verify(conn));
-    let token = try!(AuthToken::new(id, conn));
+    let sessions = Session::find_all(&user, conn)?;
+    println!("[{}] Successfully handled sessions::index", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, sessions)
+}
+
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let auth = parse::<AuthData>(req)?;
+    let conn = get_db(req);
+    let login_key = throttle::login_key(auth.name(), auth.username());
+    let ip = req.remote_addr.ip();
+    let keys = vec![login_key.clone(), throttle::ip_key(auth.name(), &ip), throttle::global_ip_key(&ip)];
+    let account = throttle::attempt(&keys, conn, || auth.verify(conn))?;
+    throttle::clear(&[login_key], conn)?;
+    let token = AuthToken::new(&account, get_user_agent(req), conn)?;
     println!("[{}] Successfully handled sessions::new", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, token)
+    respond_with!(CREATED, token)
+}
+
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let header = Basic::from_headers(&req.headers)?;
+    let token = AuthToken::from_header(&header)?;
+    let conn = get_db(req);
+    let keys = vec![throttle::token_key(token.id())];
+    throttle::verify_first(&keys, conn, || token.verify_and_delete(conn))?;
+    println!("[{}] Successfully handled sessions::delete", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
+}
+
+pub fn revoke(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let user = get_user(req);
+    let conn = get_db(req);
+    Session::delete(id, &user, conn)?;
+    println!("[{}] Successfully handled sessions::revoke", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    let header = try!(req.headers.get::<Authorization<Basic>>().ok_or(ObsidianError::from(ReqError::NoAuth)));
-    let token = try!(AuthToken::from_header(header));
+pub fn revoke_all(req: &mut Request) -> HandlerResult {
+    let user = get_user(req);
     let conn = get_db(req);
-    try!(token.verify_and_delete(conn));
-    println!("[{}] Successfully handlede sessions::delete", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    Session::delete_all(&user, conn)?;
+    println!("[{}] Successfully handled sessions::revoke_all", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
 }
@@ -1,72 +1,89 @@
 use chrono::UTC;
-use iron::{Request, IronResult, Response};
-use postgres::Connection;
 
-use error::ObsidianError;
-use handlers::{check_content_type, get_db, get_id, get_includes, get_school_id, parse, serialise};
-use models::Model;
-use models::students::Student;
+use crate::handlers::{HandlerResult, Request, check_content_type, check_csv_content_type, create, get_body, get_db,
+    get_delimiter, get_id, get_includes, get_param, get_parsed_param, get_query, get_school_id, parse,
+    respond_with_csv, respond_with_page, wants_csv};
+use crate::models::Model;
+use crate::models::student_import::{ColumnMapping, ImportReport};
+use crate::models::students::Student;
 
-pub fn index(req: &mut Request) -> IronResult<Response> {
+pub fn index(req: &mut Request) -> HandlerResult {
+    if wants_csv(req) {
+        return export(req);
+    }
+    let query = get_query(req, &[])?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
     let includes = get_includes(req);
-    let students = try!(Student::find_all(school_id, conn, &includes));
+    let students = Student::find_page(school_id, &query, conn, &includes)?;
     println!("[{}] Successfully handled students::index request (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
-    respond_with!(Ok, students)
+    respond_with_page(req, &query, students)
 }
 
-pub fn show(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn export(req: &mut Request) -> HandlerResult {
+    let query = get_query(req, &[])?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
     let includes = get_includes(req);
-    let student = try!(Student::find_id(id, school_id, conn, &includes));
+    let students = Student::find_page(school_id, &query, conn, &includes)?;
+    println!("[{}] Successfully handled students::export request (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
+    respond_with_csv(req, "students.csv", &students.items, &includes)
+}
+
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let student = Student::find_id(id, school_id, conn, &includes)?;
     println!("[{}] Successfully handled students::show request (include={:?})", UTC::now().format("%FT%T%:z"), &includes);
-    respond_with!(Ok, student)
+    respond_with!(OK, student)
 }
 
-pub fn edit(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let student = try!(parse::<Student>(req));
-    let id = try!(get_id(req));
+pub fn edit(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let student = parse::<Student>(req)?;
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let student = try!(student.save(Some(id), school_id, conn));
+    let student = student.save(Some(id), school_id, conn)?;
     println!("[{}] Successfully handled students::edit request", UTC::now().format("%FT%T%:z"));
-    respond_with!(Ok, student)
+    respond_with!(OK, student)
 }
 
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    fn single(req: &Request, school_id: usize, conn: &Connection) -> IronResult<String> {
-        let student = try!(parse::<Student>(req));
-        let student = try!(student.save(None, school_id, conn));
-        let ser = try!(serialise(student));
-        Ok(ser)
-    }
-
-    fn multiple(req: &Request, school_id: usize, conn: &Connection) -> IronResult<String> {
-        let students = try!(parse::<Vec<Student>>(req));
-        let students = try!(students.into_iter()
-            .map(|student| student.save(None, school_id, conn))
-            .collect::<Result<Vec<Student>, ObsidianError>>());
-        let ser = try!(serialise(students));
-        Ok(ser)
-    }
-
-    try!(check_content_type(req));
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let ser = try!(single(req, school_id, conn).or_else(|_| multiple(req, school_id, conn)));
+    let res = create::<Student>(req, school_id, conn)?;
     println!("[{}] Successfully handled students::new request", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, ser)
+    Ok(res)
+}
+
+pub fn import(req: &mut Request) -> HandlerResult {
+    check_csv_content_type(req)?;
+    let defaults = ColumnMapping::default();
+    let mapping = ColumnMapping {
+        name: get_param(req, "name_column").unwrap_or(defaults.name),
+        class_letter: get_param(req, "class_letter_column").unwrap_or(defaults.class_letter),
+        graduation_year: get_param(req, "graduation_year_column").unwrap_or(defaults.graduation_year),
+        external_id: get_param(req, "external_id_column").unwrap_or(defaults.external_id)
+    };
+    let delimiter = get_delimiter(req)?;
+    let dry_run = get_parsed_param::<bool>(req, "dry_run")?.unwrap_or(false);
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let report = ImportReport::import(get_body(req), &mapping, delimiter, dry_run, school_id, conn)?;
+    println!("[{}] Successfully handled students::import request (dry_run={})", UTC::now().format("%FT%T%:z"),
+        dry_run);
+    respond_with!(OK, report)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    try!(Student::delete(id, school_id, conn));
+    Student::delete(id, school_id, conn)?;
     println!("[{}] Successfully handled students::delete request", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
 }
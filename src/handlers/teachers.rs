@@ -1,57 +1,58 @@
 use chrono::UTC;
-use iron::{IronResult, Response, Request};
 
-use handlers::{check_content_type, get_db, get_id, get_includes, get_school_id, parse};
-use models::Model;
-use models::teachers::Teacher;
+use crate::handlers::{HandlerResult, Request, check_content_type, get_db, get_id, get_includes, get_query,
+    get_school_id, parse, respond_with_page};
+use crate::models::Model;
+use crate::models::teachers::Teacher;
 
-pub fn index(req: &mut Request) -> IronResult<Response> {
+pub fn index(req: &mut Request) -> HandlerResult {
+    let query = get_query(req, &[])?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
     let includes = get_includes(req);
-    let teachers = try!(Teacher::find_all(school_id, conn, &includes));
+    let teachers = Teacher::find_page(school_id, &query, conn, &includes)?;
     println!("[{}] Successfully handled teachers::index (include={:?})", UTC::now().format("%FT%T%:z"),
         &includes);
-    respond_with!(Ok, teachers)
+    respond_with_page(req, &query, teachers)
 }
 
-pub fn show(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
     let includes = get_includes(req);
-    let teacher = try!(Teacher::find_id(id, school_id, conn, &includes));
+    let teacher = Teacher::find_id(id, school_id, conn, &includes)?;
     println!("[{}] Successfully handled teachers::show (include={:?})", UTC::now().format("%FT%T%:z"),
         &includes);
-    respond_with!(Ok, teacher)
+    respond_with!(OK, teacher)
 }
 
-pub fn edit(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let teacher = try!(parse::<Teacher>(req));
-    let id = try!(get_id(req));
+pub fn edit(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let teacher = parse::<Teacher>(req)?;
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let teacher = try!(teacher.save(Some(id), school_id, conn));
+    let teacher = teacher.save(Some(id), school_id, conn)?;
     println!("[{}] Successfully handled teachers::edit", UTC::now().format("%FT%T%:z"));
-    respond_with!(Ok, teacher)
+    respond_with!(OK, teacher)
 }
 
-pub fn new(req: &mut Request) -> IronResult<Response> {
-    try!(check_content_type(req));
-    let teacher = try!(parse::<Teacher>(req));
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let teacher = parse::<Teacher>(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    let teacher = try!(teacher.save(None, school_id, conn));
+    let teacher = teacher.save(None, school_id, conn)?;
     println!("[{}] Successfully handled teachers::new", UTC::now().format("%FT%T%:z"));
-    respond_with!(Created, teacher)
+    respond_with!(CREATED, teacher)
 }
 
-pub fn delete(req: &mut Request) -> IronResult<Response> {
-    let id = try!(get_id(req));
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
     let school_id = get_school_id(req);
     let conn = get_db(req);
-    try!(Teacher::delete(id, school_id, conn));
+    Teacher::delete(id, school_id, conn)?;
     println!("[{}] Successfully handled teachers::delete", UTC::now().format("%FT%T%:z"));
-    respond_with!(NoContent)
+    respond_with!(NO_CONTENT)
 }
@@ -0,0 +1,69 @@
+use chrono::UTC;
+
+use crate::handlers::{HandlerResult, Request, check_content_type, get_db, get_id, get_includes, get_query,
+    get_school_id, get_user, parse, respond_with_page};
+use crate::models::Model;
+use crate::models::throttle;
+use crate::models::users::{PasswordChange, User};
+
+pub fn index(req: &mut Request) -> HandlerResult {
+    let query = get_query(req, &[])?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let users = User::find_page(school_id, &query, conn, &includes)?;
+    println!("[{}] Successfully handled users::index", UTC::now().format("%FT%T%:z"));
+    respond_with_page(req, &query, users)
+}
+
+pub fn show(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let includes = get_includes(req);
+    let user = User::find_id(id, school_id, conn, &includes)?;
+    println!("[{}] Successfully handled users::show", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, user)
+}
+
+pub fn edit(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let user = parse::<User>(req)?;
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let user = user.save(Some(id), school_id, conn)?;
+    println!("[{}] Successfully handled users::edit", UTC::now().format("%FT%T%:z"));
+    respond_with!(OK, user)
+}
+
+pub fn new(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let user = parse::<User>(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    let user = user.save(None, school_id, conn)?;
+    println!("[{}] Successfully handled users::new", UTC::now().format("%FT%T%:z"));
+    respond_with!(CREATED, user)
+}
+
+pub fn delete(req: &mut Request) -> HandlerResult {
+    let id = get_id(req)?;
+    let school_id = get_school_id(req);
+    let conn = get_db(req);
+    User::delete(id, school_id, conn)?;
+    println!("[{}] Successfully handled users::delete", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
+}
+
+pub fn password(req: &mut Request) -> HandlerResult {
+    check_content_type(req)?;
+    let pw_change = parse::<PasswordChange>(req)?;
+    let user = get_user(req);
+    let conn = get_db(req);
+    let keys = vec![throttle::user_key(user.id)];
+    throttle::attempt(&keys, conn, || pw_change.perform(&user, conn))?;
+    throttle::clear(&keys, conn)?;
+    println!("[{}] Successfully handled users::password", UTC::now().format("%FT%T%:z"));
+    respond_with!(NO_CONTENT)
+}
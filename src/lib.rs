@@ -1,18 +1,21 @@
-extern crate rustc_serialize;
+extern crate serde;
+extern crate serde_json;
 extern crate r2d2;
 extern crate r2d2_postgres;
 extern crate postgres;
-extern crate iron;
-extern crate iron_cors;
 extern crate chrono;
 extern crate rand;
 extern crate bcrypt;
+extern crate crypto;
+extern crate toml;
+#[cfg(feature = "postgres-tls")]
+extern crate openssl;
 
-#[macro_use]
-extern crate router;
-
+pub mod cleanup;
+pub mod config;
 mod error;
 mod models;
 pub mod handlers;
 pub mod middleware;
+pub mod migrations;
 pub mod routes;
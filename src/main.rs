@@ -1,11 +1,132 @@
-extern crate obsidian_rust;
-extern crate iron;
+use axum::Router;
+#[cfg(feature = "https")]
+use axum_server::tls_rustls::RustlsConfig;
+use obsidian_rust::cleanup;
+use obsidian_rust::config::Config;
+use obsidian_rust::migrations;
+use obsidian_rust::routes::get_router;
+use std::env;
+use std::fmt::Display;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(feature = "https")]
+use std::net::ToSocketAddrs;
+use std::process;
 
-use iron::Iron;
-use obsidian_rust::routes::get_chain;
+const USAGE: &str = "Usage: obsidian_rust [--config <file>] [migrate up|down|status]";
+
+/// Unwraps `result` or prints the error and exits, so misconfiguration does not panic.
+fn or_exit<T, E: Display>(result: Result<T, E>, context: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            println!("{}: {}", context, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn migrate(config: &Config, command: Option<&str>) {
+    let conn = or_exit(migrations::connect(&config.database), "Could not connect to the database");
+    match command {
+        Some("up") => {
+            let applied = or_exit(migrations::up(&conn), "Could not apply migrations");
+            for migration in &applied {
+                println!("Applied {}", migration.name);
+            }
+            println!("{} migration(s) applied", applied.len());
+        },
+        Some("down") => {
+            match or_exit(migrations::down(&conn), "Could not revert the migration") {
+                Some(migration) => println!("Reverted {}", migration.name),
+                None => println!("No migrations to revert")
+            }
+        },
+        Some("status") => {
+            for (migration, applied_at) in or_exit(migrations::status(&conn), "Could not read the migration status") {
+                match applied_at {
+                    Some(date) => println!("{} applied at {}", migration.name, date.to_rfc3339()),
+                    None => println!("{} pending", migration.name)
+                }
+            }
+        },
+        _ => {
+            println!("Usage: obsidian_rust migrate up|down|status");
+            process::exit(1);
+        }
+    }
+}
+
+async fn listen_http(router: Router, config: &Config) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(config.server.bind.as_str()).await?;
+    axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>()).await
+}
+
+#[cfg(feature = "https")]
+async fn listen(router: Router, config: &Config) -> io::Result<()> {
+    match config.server.https {
+        Some(ref https) => {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let tls = or_exit(RustlsConfig::from_pem_file(&https.cert, &https.key).await,
+                "Could not load the HTTPS certificate or key");
+            let addr = config.server.bind.to_socket_addrs()?.next()
+                .ok_or(io::Error::new(io::ErrorKind::AddrNotAvailable, "bind resolves to no address"))?;
+            axum_server::bind_rustls(addr, tls)
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        },
+        None => listen_http(router, config).await
+    }
+}
+
+#[cfg(not(feature = "https"))]
+async fn listen(router: Router, config: &Config) -> io::Result<()> {
+    listen_http(router, config).await
+}
+
+fn serve(config: &Config) {
+    {
+        let conn = or_exit(migrations::connect(&config.database), "Could not connect to the database");
+        let pending = or_exit(migrations::pending(&conn), "Could not read the migration status");
+        if !pending.is_empty() {
+            println!("The database schema is behind by {} migration(s), run `obsidian_rust migrate up` first",
+                pending.len());
+            process::exit(1);
+        }
+    }
+    let router = or_exit(get_router(config), "Could not set up the database pool");
+    // Handlers block on the database, so the blocking pool bounds how many run at once.
+    let runtime = or_exit(tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.server.threads)
+        .max_blocking_threads(config.server.threads)
+        .enable_all()
+        .build(), "Could not start the server");
+    cleanup::spawn(&config.database);
+    println!("Server up and running on {}", config.server.bind);
+    or_exit(runtime.block_on(listen(router, config)), "Could not start the server");
+}
 
 fn main() {
-    let c = get_chain();
-    println!("Server up and running");
-    Iron::new(c).http("localhost:3000").unwrap();
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(path)
+        },
+        Some(_) => {
+            println!("{}", USAGE);
+            process::exit(1);
+        },
+        None => env::var("OBSIDIAN_CONFIG").ok()
+    };
+    let config = or_exit(Config::load(config_path.as_ref().map(|path| path.as_ref())), "Invalid configuration");
+    match args.first().map(|arg| arg.as_ref()) {
+        Some("migrate") => migrate(&config, args.get(1).map(|arg| arg.as_ref())),
+        Some(_) => {
+            println!("{}", USAGE);
+            process::exit(1);
+        },
+        None => serve(&config)
+    }
 }
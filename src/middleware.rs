@@ -1,81 +1,238 @@
-use iron::{BeforeMiddleware, IronResult, Request};
-use iron::headers::{Authorization, Basic};
-use iron::typemap::Key;
-use r2d2::{Pool, Config, PooledConnection};
+use axum::extract::{ConnectInfo, FromRequestParts, RawPathParams};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::http::header::AUTHORIZATION;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http_body_util::LengthLimitError;
+use r2d2::{Pool, Config as PoolConfig, PooledConnection};
 use r2d2_postgres::{PostgresConnectionManager, TlsMode};
-use std::env;
-use std::io::Read;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
 
-use error::{ObsidianError, ReqError};
-use models::sessions::AuthToken;
+use crate::config::{DatabaseConfig, DbTls};
+use crate::error::{ObsidianError, ReqError};
+use crate::handlers::{HandlerResult, Request};
+use crate::models::api_keys::ApiKeyAuth;
+use crate::models::sessions::AuthToken;
+use crate::models::throttle;
+use crate::models::users::Role;
 
+/// The connection pool shared by all requests.
+#[derive(Clone)]
 pub struct PostgresConnection {
     pool: Pool<PostgresConnectionManager>
 }
 
+/// The state of the router: the connection pool and the limit on request bodies.
+#[derive(Clone)]
+pub struct AppState {
+    pub db: PostgresConnection,
+    pub max_body_bytes: usize
+}
+
 impl PostgresConnection {
-    pub fn new() -> Result<PostgresConnection, ObsidianError> {
-        let url = env::var("DATABASE_URL").expect("No database url provided");
-        let mgr = try!(PostgresConnectionManager::new(url, TlsMode::None));
-        let pool = try!(Pool::new(Config::default(), mgr));
-        Ok(PostgresConnection{pool: pool})
+    pub fn new(config: &DatabaseConfig) -> Result<PostgresConnection, ObsidianError> {
+        let tls = match (config.tls, config.tls_handshake()?) {
+            (DbTls::Prefer, Some(handshake)) => TlsMode::Prefer(handshake),
+            (DbTls::Require, Some(handshake)) => TlsMode::Require(handshake),
+            _ => TlsMode::None
+        };
+        let mgr = PostgresConnectionManager::new(config.url.as_ref(), tls)?;
+        let pool_config = PoolConfig::builder()
+            .pool_size(config.pool_max)
+            .min_idle(Some(config.pool_min))
+            .connection_timeout(Duration::from_secs(config.connection_timeout))
+            .build();
+        let pool = Pool::new(pool_config, mgr)?;
+        Ok(PostgresConnection{pool})
+    }
+
+    pub fn get(&self) -> Result<PooledConnection<PostgresConnectionManager>, ObsidianError> {
+        self.pool.get().map_err(ObsidianError::from)
     }
 }
 
-impl Key for PostgresConnection {
-    type Value = PooledConnection<PostgresConnectionManager>;
+/// The credentials of Basic authentication.
+#[derive(Debug, Clone)]
+pub struct Basic {
+    pub username: String,
+    pub password: Option<String>
 }
 
-impl BeforeMiddleware for PostgresConnection {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        let conn = try!(self.pool.get().map_err(|err| ObsidianError::from(err)));
-        req.extensions.insert::<Self>(conn);
-        Ok(())
+impl Basic {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Basic, ObsidianError> {
+        headers.get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .map(|decoded| {
+                let mut parts = decoded.splitn(2, ':');
+                Basic {
+                    username: parts.next().unwrap_or("").to_string(),
+                    password: parts.next().map(|password| password.to_string())
+                }
+            })
+            .ok_or_else(|| ObsidianError::from(ReqError::NoAuth))
     }
 }
 
-pub struct RequestBody;
-
-impl RequestBody {
-    pub fn new() -> RequestBody {
-        RequestBody{}
+/// Authenticates the request with a session token or an API key and remembers the school
+/// and the user or key.
+fn authenticate(req: &mut Request) -> Result<(), ObsidianError> {
+    let header = Basic::from_headers(&req.headers)?;
+    let conn = &*req.conn;
+    match ApiKeyAuth::from_header(&header)? {
+        Some(key) => {
+            let keys = vec![throttle::api_key_key(key.id())];
+            let key = throttle::verify_first(&keys, conn, || key.verify(conn))?;
+            req.school_id = Some(key.school_id);
+            req.key = Some(key);
+        },
+        None => {
+            let token = AuthToken::from_header(&header)?;
+            let keys = vec![throttle::token_key(token.id())];
+            let account = throttle::verify_first(&keys, conn, || token.verify(conn))?;
+            req.school_id = Some(account.school_id);
+            req.user = Some(account);
+        }
     }
+    Ok(())
 }
 
-impl Key for RequestBody {
-    type Value = String;
+/// The scope an API key needs for a request, named after the kind of record the route
+/// belongs to and whether the request reads or writes. CSV exports need the scope of their
+/// records.
+fn required_scope(req: &Request) -> Option<String> {
+    let segment = req.uri.path().trim_start_matches('/').split('/').next();
+    let resource = match segment.map(|segment| segment.trim_end_matches(".csv")) {
+        Some("students") => "students",
+        Some("teachers") => "teachers",
+        Some("books") | Some("aliases") | Some("copies") => "books",
+        Some("base_sets") => "base_sets",
+        Some("lendings") => "lendings",
+        Some("lookup") | Some("search") => "search",
+        _ => return None
+    };
+    let access = match req.method {
+        Method::GET | Method::HEAD => "read",
+        _ => "write"
+    };
+    Some(format!("{}:{}", resource, access))
 }
 
-impl BeforeMiddleware for RequestBody {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        let mut buf = String::new();
-        try!(req.body.read_to_string(&mut buf).map_err(ObsidianError::from));
-        req.extensions.insert::<Self>(buf);
+/// Rejects requests by users whose role is below `role`. Without an explicit role reading
+/// requests need `ReadOnly` and all others `Librarian`. API keys are only accepted on routes
+/// without an explicit role and need the scope of the route. Must run after `authenticate`.
+fn require_role(role: Option<Role>, req: &Request) -> Result<(), ObsidianError> {
+    let required = role.unwrap_or(match req.method {
+        Method::GET | Method::HEAD | Method::OPTIONS => Role::ReadOnly,
+        _ => Role::Librarian
+    });
+    if let Some(ref key) = req.key {
+        return match (role, required_scope(req)) {
+            (None, Some(scope)) => if key.allows(&scope) {
+                Ok(())
+            } else {
+                Err(ObsidianError::MissingScope(scope))
+            },
+            _ => Err(ObsidianError::Forbidden(required))
+        };
+    }
+    if req.user.unwrap().role >= required {
         Ok(())
+    } else {
+        Err(ObsidianError::Forbidden(required))
     }
 }
 
-pub struct SchoolID;
+/// Who may call an endpoint.
+#[derive(Debug, Clone, Copy)]
+pub enum Access {
+    /// Anyone; the handler checks credentials itself where it needs them.
+    Public,
+    /// Authenticated requests with a role or scope fitting the method.
+    ByMethod,
+    /// Authenticated users with at least the role.
+    AtLeast(Role)
+}
 
-impl SchoolID {
-    pub fn new() -> SchoolID {
-        SchoolID{}
-    }
+/// A route's handler together with who may call it. The handlers talk to the database
+/// synchronously, so they run on the blocking thread pool with the body already read.
+#[derive(Clone, Copy)]
+pub struct Endpoint {
+    access: Access,
+    handler: fn(&mut Request) -> HandlerResult
 }
 
-impl Key for SchoolID {
-    type Value = usize;
+impl Endpoint {
+    pub fn new(access: Access, handler: fn(&mut Request) -> HandlerResult) -> Endpoint {
+        Endpoint{access, handler}
+    }
+
+    async fn serve(self, req: axum::extract::Request, state: AppState) -> Result<Response, ObsidianError> {
+        let (mut parts, body) = req.into_parts();
+        let id = RawPathParams::from_request_parts(&mut parts, &state).await.ok()
+            .and_then(|params| params.iter().find(|&(key, _)| key == "id").map(|(_, value)| value.to_string()));
+        let remote_addr = parts.extensions.get::<ConnectInfo<SocketAddr>>()
+            .map(|&ConnectInfo(addr)| addr)
+            .unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+        let body = axum::body::to_bytes(body, state.max_body_bytes).await.map_err(|err| {
+            let err = err.into_inner();
+            if err.is::<LengthLimitError>() {
+                ObsidianError::from(ReqError::BodyTooLarge(state.max_body_bytes))
+            } else {
+                ObsidianError::from(io::Error::other(err))
+            }
+        })?;
+        let body = String::from_utf8(body.to_vec())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let task = tokio::task::spawn_blocking(move || {
+            let mut req = Request {
+                method: parts.method,
+                uri: parts.uri,
+                headers: parts.headers,
+                remote_addr,
+                body,
+                id,
+                conn: state.db.get()?,
+                school_id: None,
+                user: None,
+                key: None
+            };
+            match self.access {
+                Access::Public => {},
+                Access::ByMethod => {
+                    authenticate(&mut req)?;
+                    require_role(None, &req)?;
+                },
+                Access::AtLeast(role) => {
+                    authenticate(&mut req)?;
+                    require_role(Some(role), &req)?;
+                }
+            }
+            (self.handler)(&mut req)
+        });
+        task.await.unwrap_or_else(|err| {
+            println!("Handler failed: {}", err);
+            Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        })
+    }
 }
 
-impl BeforeMiddleware for SchoolID {
-    fn before(&self, req: &mut Request) -> IronResult<()> {
-        let school_id = {
-            let header = try!(req.headers.get::<Authorization<Basic>>().ok_or(ObsidianError::from(ReqError::NoAuth)));
-            let token = try!(AuthToken::from_header(header));
-            let conn = req.extensions.get::<PostgresConnection>().unwrap();
-            try!(token.verify(conn))
-        };
-        req.extensions.insert::<Self>(school_id);
-        Ok(())
+impl axum::handler::Handler<(), AppState> for Endpoint {
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, req: axum::extract::Request, state: AppState) -> Self::Future {
+        Box::pin(async move {
+            match self.serve(req, state).await {
+                Ok(res) => res,
+                Err(err) => err.into_response()
+            }
+        })
     }
 }
@@ -0,0 +1,162 @@
+use chrono::{DateTime, UTC};
+use postgres::{Connection, TlsMode};
+
+use crate::config::{DatabaseConfig, DbTls};
+use crate::error::ObsidianError;
+
+macro_rules! migration {
+    ($version:expr, $name:expr) => (Migration {
+        version: $version,
+        name: $name,
+        up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+        down: include_str!(concat!("../migrations/", $name, ".down.sql"))
+    });
+}
+
+const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial_schema"),
+    migration!(2, "0002_lending_returns"),
+    migration!(3, "0003_copies"),
+    migration!(4, "0004_due_dates"),
+    migration!(5, "0005_student_external_ids"),
+    migration!(6, "0006_student_archive"),
+    migration!(7, "0007_book_forms"),
+    migration!(8, "0008_book_isbns"),
+    migration!(9, "0009_search"),
+    migration!(10, "0010_validation_rules"),
+    migration!(11, "0011_users"),
+    migration!(12, "0012_token_hashes"),
+    migration!(13, "0013_session_management"),
+    migration!(14, "0014_api_keys"),
+    migration!(15, "0015_login_failures"),
+    migration!(16, "0016_copy_inventory_numbers")
+];
+
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at TIMESTAMP WITH TIME ZONE NOT NULL
+)";
+const QUERY_APPLIED: &str = "SELECT version, applied_at FROM schema_migrations ORDER BY version";
+const INSERT_APPLIED: &str = "INSERT INTO schema_migrations (version, name, applied_at) VALUES ($1, $2, $3)";
+const DELETE_APPLIED: &str = "DELETE FROM schema_migrations WHERE version=$1";
+
+/// A migration together with when it was applied, if it was.
+pub type MigrationStatus = (&'static Migration, Option<DateTime<UTC>>);
+
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str
+}
+
+pub fn connect(config: &DatabaseConfig) -> Result<Connection, ObsidianError> {
+    let handshake = config.tls_handshake()?;
+    let tls = match (config.tls, handshake.as_ref()) {
+        (DbTls::Prefer, Some(handshake)) => TlsMode::Prefer(&**handshake),
+        (DbTls::Require, Some(handshake)) => TlsMode::Require(&**handshake),
+        _ => TlsMode::None
+    };
+    Connection::connect(config.url.as_ref(), tls).map_err(ObsidianError::from)
+}
+
+fn applied(conn: &Connection) -> Result<Vec<(i32, DateTime<UTC>)>, ObsidianError> {
+    conn.batch_execute(CREATE_MIGRATIONS_TABLE)?;
+    let stmt = conn.prepare(QUERY_APPLIED)?;
+    let rows = stmt.query(&[])?;
+    Ok(rows.iter()
+        .map(|row| (row.get::<usize, i32>(0), row.get::<usize, DateTime<UTC>>(1)))
+        .collect())
+}
+
+pub fn status(conn: &Connection) -> Result<Vec<MigrationStatus>, ObsidianError> {
+    let applied = applied(conn)?;
+    Ok(MIGRATIONS.iter()
+        .map(|migration| (migration, applied.iter()
+            .find(|&&(version, _)| version == migration.version)
+            .map(|&(_, applied_at)| applied_at)))
+        .collect())
+}
+
+pub fn pending(conn: &Connection) -> Result<Vec<&'static Migration>, ObsidianError> {
+    Ok(status(conn)?.into_iter()
+        .filter_map(|(migration, applied_at)| match applied_at {
+            Some(_) => None,
+            None => Some(migration)
+        })
+        .collect())
+}
+
+pub fn up(conn: &Connection) -> Result<Vec<&'static Migration>, ObsidianError> {
+    let pending = pending(conn)?;
+    for migration in &pending {
+        let trans = conn.transaction()?;
+        trans.batch_execute(migration.up)?;
+        let stmt = trans.prepare(INSERT_APPLIED)?;
+        stmt.execute(&[&migration.version, &migration.name, &UTC::now()])?;
+        trans.commit()?;
+    }
+    Ok(pending)
+}
+
+pub fn down(conn: &Connection) -> Result<Option<&'static Migration>, ObsidianError> {
+    let last = status(conn)?.into_iter()
+        .filter(|&(_, applied_at)| applied_at.is_some())
+        .map(|(migration, _)| migration)
+        .next_back();
+    if let Some(migration) = last {
+        let trans = conn.transaction()?;
+        trans.batch_execute(migration.down)?;
+        let stmt = trans.prepare(DELETE_APPLIED)?;
+        stmt.execute(&[&migration.version])?;
+        trans.commit()?;
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres::{Connection, TlsMode};
+    use std::env;
+
+    use super::{down, pending, status, up, Migration, MIGRATIONS};
+
+    fn versions(migrations: &[&Migration]) -> Vec<i32> {
+        migrations.iter().map(|migration| migration.version).collect()
+    }
+
+    /// Needs a scratch database at `TEST_DATABASE_URL`; run it with `cargo test -- --ignored`.
+    /// Migrates a schema of its own, so that the other tests can keep using the database.
+    #[test]
+    #[ignore]
+    fn migrates_all_the_way_up_and_down() {
+        let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a scratch database");
+        let conn = Connection::connect(&*url, TlsMode::None).unwrap();
+        let schema: String = conn.query("SELECT 'migrations_' || md5(random()::text)", &[]).unwrap().get(0).get(0);
+        conn.batch_execute(&format!("CREATE SCHEMA {0}; SET search_path TO {0}, public", schema)).unwrap();
+        let all = MIGRATIONS.iter().map(|migration| migration.version).collect::<Vec<i32>>();
+
+        assert_eq!(versions(&pending(&conn).unwrap()), all);
+        assert_eq!(versions(&up(&conn).unwrap()), all);
+        assert!(pending(&conn).unwrap().is_empty());
+        assert!(status(&conn).unwrap().iter().all(|&(_, applied_at)| applied_at.is_some()));
+
+        let last = down(&conn).unwrap().unwrap();
+        assert_eq!(last.version, *all.last().unwrap());
+        assert_eq!(versions(&pending(&conn).unwrap()), vec![last.version]);
+        assert!(status(&conn).unwrap().last().unwrap().1.is_none());
+        assert_eq!(versions(&up(&conn).unwrap()), vec![last.version]);
+
+        let mut reverted = Vec::new();
+        while let Some(migration) = down(&conn).unwrap() {
+            reverted.push(migration.version);
+        }
+        reverted.reverse();
+        assert_eq!(reverted, all);
+        assert_eq!(versions(&pending(&conn).unwrap()), all);
+        assert!(status(&conn).unwrap().iter().all(|&(_, applied_at)| applied_at.is_none()));
+        conn.batch_execute(&format!("DROP SCHEMA {} CASCADE", schema)).unwrap();
+    }
+}
@@ -1,22 +1,26 @@
 use postgres::Connection;
 use postgres::rows::Row;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
-use error::ObsidianError;
-use models::{Model, Includes};
-use models::books::Book;
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{Columns, Model, Includes, Page, Query, Validate};
+use crate::models::books::Book;
+use crate::models::schools::ValidationRules;
 
-const QUERY_ALIASES: &'static str = "SELECT aliases.id, book_id, name FROM aliases, books WHERE book_id = books.id
+const QUERY_ALIASES: &str = "SELECT aliases.id, book_id, name FROM aliases, books WHERE book_id = books.id
     AND school_id = $1";
+const ALIAS_COLUMNS: Columns = &[("id", "aliases.id"), ("book_id", "aliases.book_id"), ("name", "aliases.name")];
 
-const INSERT_ALIAS: &'static str = "INSERT INTO aliases (book_id, name) VALUES ($1, $2) RETURNING id";
-const UPDATE_ALIAS: &'static str = "UPDATE aliases SET book_id=$2, name=$3 WHERE aliases.id=$1 AND
+const INSERT_ALIAS: &str = "INSERT INTO aliases (book_id, name) VALUES ($1, $2) RETURNING id";
+const UPDATE_ALIAS: &str = "UPDATE aliases SET book_id=$2, name=$3 WHERE aliases.id=$1 AND
 EXISTS (SELECT * FROM books WHERE books.id = aliases.book_id AND books.school_id = $4)";
-const DELETE_ALIAS: &'static str = "DELETE FROM aliases WHERE aliases.id=$1 AND EXISTS
+const DELETE_ALIAS: &str = "DELETE FROM aliases WHERE aliases.id=$1 AND EXISTS
 (SELECT * FROM books WHERE books.id = aliases.book_id AND books.school_id = $2)";
 
-#[derive(RustcEncodable, RustcDecodable, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Alias {
+    #[serde(default)]
     id: Option<usize>,
     book_id: usize,
     name: String
@@ -25,12 +29,16 @@ pub struct Alias {
 impl Alias {
     pub fn new(id: Option<usize>, book_id: usize, name: String) -> Alias {
         Alias {
-            id: id,
-            book_id: book_id,
-            name: name
+            id,
+            book_id,
+            name
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     fn from_db(_: &Connection, _: &Includes, row: Row) -> Alias {
         Alias {
             id: Some(row.get::<usize, i32>(0) as usize),
@@ -40,28 +48,42 @@ impl Alias {
     }
 }
 
+impl Validate for Alias {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.name.trim().is_empty() {
+            vec![FieldError::new("name", "The name must not be empty")]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
 impl Model for Alias {
     fn find_id(_: usize, _: usize, _: &Connection, _: &Includes) -> Result<Self, ObsidianError> {
         unreachable!()
     }
 
     fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        Alias::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
+    }
+
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
         does_not_support!(LentBooks, includes);
         does_not_support!(BaseSetBooks, includes);
         does_not_support!(Aliases, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_ALIASES));
-        let rows = try!(stmt.query(&[&(school_id as i32)]))
-            .iter()
-            .map(|row| Alias::from_db(conn, includes, row))
-            .collect::<Vec<Self>>();
-        Ok(rows)
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        query.execute(QUERY_ALIASES, ALIAS_COLUMNS, school_id, conn, |row| Ok(Alias::from_db(conn, includes, row)))
     }
 
     fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
         if let Some(id) = id {
-            let stmt = try!(conn.prepare_cached(UPDATE_ALIAS));
-            let modified = try!(stmt.execute(&[&(id as i32), &(self.book_id as i32),
-                &self.name, &(school_id as i32)]));
+            let stmt = conn.prepare_cached(UPDATE_ALIAS)?;
+            let modified = stmt.execute(&[&(id as i32), &(self.book_id as i32),
+                &self.name, &(school_id as i32)])?;
             if modified == 1 {
                 self.id = Some(id);
                 Ok(self)
@@ -69,9 +91,9 @@ impl Model for Alias {
                 Err(ObsidianError::RecordNotFound("Alias"))
             }
         } else {
-            try!(Book::find_id(self.book_id, school_id, conn, &HashSet::new()));
-            let stmt = try!(conn.prepare_cached(INSERT_ALIAS));
-            let rows = try!(stmt.query(&[&(self.book_id as i32), &self.name]));
+            Book::find_id(self.book_id, school_id, conn, &HashSet::new())?;
+            let stmt = conn.prepare_cached(INSERT_ALIAS)?;
+            let rows = stmt.query(&[&(self.book_id as i32), &self.name])?;
             let row = rows.iter().next().unwrap();
             self.id = Some(row.get::<usize, i32>(0) as usize);
             Ok(self)
@@ -79,8 +101,8 @@ impl Model for Alias {
     }
 
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(DELETE_ALIAS));
-        let modified = try!(stmt.execute(&[&(id as i32), &(school_id as i32)]));
+        let stmt = conn.prepare_cached(DELETE_ALIAS)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -88,3 +110,17 @@ impl Model for Alias {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::Alias;
+
+    #[test]
+    fn requires_a_name() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<Alias>(r#"{"id":null,"book_id":1,"name":"Mathe 5"}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<Alias>(r#"{"id":null,"book_id":1,"name":" "}"#, &rules), vec!["name"]);
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, UTC};
+use postgres::Connection;
+use postgres::rows::Row;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::error::{FieldError, ObsidianError, ReqError};
+use crate::middleware::Basic;
+use crate::models::Validate;
+use crate::models::schools::ValidationRules;
+use crate::models::sessions::{generate_secret, hash_secret, secret_matches};
+
+const QUERY_KEYS: &str = "SELECT id, name, scopes, created_at, last_used_at, revoked_at FROM api_keys
+WHERE school_id=$1 ORDER BY id";
+const QUERY_KEY: &str = "SELECT hashed_secret, school_id, scopes, last_used_at IS NULL
+OR last_used_at < now() - interval '1 minute' FROM api_keys WHERE id=$1 AND revoked_at IS NULL";
+const INSERT_KEY: &str = "INSERT INTO api_keys (school_id, name, hashed_secret, scopes, created_at)
+VALUES ($1, $2, $3, $4, $5) RETURNING id";
+const UPDATE_LAST_USED: &str = "UPDATE api_keys SET last_used_at=now() WHERE id=$1";
+const REVOKE_KEY: &str = "UPDATE api_keys SET revoked_at=now() WHERE id=$1 AND school_id=$2
+AND revoked_at IS NULL";
+
+/// API keys authenticate as `key-<id>` so they can share the `Authorization` header with
+/// session tokens.
+const USERNAME_PREFIX: &str = "key-";
+
+/// The scopes a key can be granted, each allowing to read or to change one kind of record.
+pub const SCOPES: &[&str] = &[
+    "students:read", "students:write",
+    "teachers:read", "teachers:write",
+    "books:read", "books:write",
+    "base_sets:read", "base_sets:write",
+    "lendings:read", "lendings:write",
+    "search:read"
+];
+
+/// A key for integrations that outlives sessions and is limited to its scopes.
+#[derive(Serialize, Debug)]
+pub struct ApiKey {
+    id: usize,
+    name: String,
+    scopes: Vec<String>,
+    created_at: String,
+    last_used_at: Option<String>,
+    revoked_at: Option<String>,
+    /// The username to authenticate with, only given when the key is created.
+    username: Option<String>,
+    /// Only given when the key is created; it cannot be retrieved afterwards.
+    secret: Option<String>
+}
+
+impl ApiKey {
+    fn from_db(row: Row) -> ApiKey {
+        ApiKey {
+            id: row.get::<usize, i32>(0) as usize,
+            name: row.get::<usize, String>(1),
+            scopes: row.get::<usize, Vec<String>>(2),
+            created_at: row.get::<usize, DateTime<UTC>>(3).to_rfc3339(),
+            last_used_at: row.get::<usize, Option<DateTime<UTC>>>(4).map(|date| date.to_rfc3339()),
+            revoked_at: row.get::<usize, Option<DateTime<UTC>>>(5).map(|date| date.to_rfc3339()),
+            username: None,
+            secret: None
+        }
+    }
+
+    pub fn find_all(school_id: usize, conn: &Connection) -> Result<Vec<ApiKey>, ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_KEYS)?;
+        let rows = stmt.query(&[&(school_id as i32)])?;
+        Ok(rows.iter().map(ApiKey::from_db).collect())
+    }
+
+    /// Keys are kept after being revoked so they still show up in the list.
+    pub fn revoke(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        let stmt = conn.prepare_cached(REVOKE_KEY)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
+        if modified == 1 {
+            Ok(())
+        } else {
+            Err(ObsidianError::RecordNotFound("API key"))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewApiKey {
+    name: String,
+    scopes: Vec<String>
+}
+
+impl NewApiKey {
+    pub fn save(self, school_id: usize, conn: &Connection) -> Result<ApiKey, ObsidianError> {
+        let secret = generate_secret();
+        let now = UTC::now();
+        let stmt = conn.prepare_cached(INSERT_KEY)?;
+        let rows = stmt.query(&[&(school_id as i32), &self.name, &hash_secret(&secret), &self.scopes, &now])?;
+        let id = rows.iter().next().unwrap().get::<usize, i32>(0) as usize;
+        Ok(ApiKey {
+            id,
+            name: self.name,
+            scopes: self.scopes,
+            created_at: now.to_rfc3339(),
+            last_used_at: None,
+            revoked_at: None,
+            username: Some(format!("{}{}", USERNAME_PREFIX, id)),
+            secret: Some(secret)
+        })
+    }
+}
+
+impl Validate for NewApiKey {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push(FieldError::new("name", "The name must not be empty"));
+        }
+        if self.scopes.is_empty() {
+            errors.push(FieldError::new("scopes", "At least one scope is needed"));
+        }
+        for scope in &self.scopes {
+            if !SCOPES.contains(&scope.as_str()) {
+                errors.push(FieldError::new("scopes", &format!("Unknown scope {}", scope)));
+            }
+        }
+        errors
+    }
+}
+
+/// What a request authenticated with an API key may access.
+#[derive(Debug, Clone)]
+pub struct KeyAccess {
+    pub id: usize,
+    pub school_id: usize,
+    pub scopes: Vec<String>
+}
+
+impl KeyAccess {
+    pub fn allows(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiKeyAuth {
+    id: usize,
+    secret: String
+}
+
+impl ApiKeyAuth {
+    /// Reads the key from the header, `None` if the header holds a session token instead.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn from_header(data: &Basic) -> Result<Option<ApiKeyAuth>, ObsidianError> {
+        if !data.username.starts_with(USERNAME_PREFIX) {
+            return Ok(None);
+        }
+        let secret = data.password.clone().ok_or_else(|| ObsidianError::from(ReqError::NoAuth))?;
+        let id = usize::from_str(&data.username[USERNAME_PREFIX.len()..])
+            .map_err(|_| ObsidianError::from(ReqError::NoAuth))?;
+        Ok(Some(ApiKeyAuth{id, secret}))
+    }
+
+    /// Checks the key, which must not be revoked, and records that it was used, at most once
+    /// a minute.
+    pub fn verify(&self, conn: &Connection) -> Result<KeyAccess, ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_KEY)?;
+        let rows = stmt.query(&[&(self.id as i32)])?;
+        let row = rows.iter().next().ok_or_else(|| ObsidianError::from(ReqError::NoAuth))?;
+        (if secret_matches(&self.secret, &row.get::<usize, String>(0)) {
+            Ok(())
+        } else {
+            Err(ObsidianError::from(ReqError::NoAuth))
+        })?;
+        if row.get::<usize, bool>(3) {
+            let stmt2 = conn.prepare_cached(UPDATE_LAST_USED)?;
+            stmt2.execute(&[&(self.id as i32)])?;
+        }
+        Ok(KeyAccess {
+            id: self.id,
+            school_id: row.get::<usize, i32>(1) as usize,
+            scopes: row.get::<usize, Vec<String>>(2)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::NewApiKey;
+
+    fn check(body: &str) -> Vec<String> {
+        invalid_fields::<NewApiKey>(body, &ValidationRules::default())
+    }
+
+    #[test]
+    fn accepts_known_scopes() {
+        assert!(check(r#"{"name":"Card printer","scopes":["students:read","search:read"]}"#).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(check(r#"{"name":" ","scopes":["books:read"]}"#), vec!["name"]);
+        assert_eq!(check(r#"{"name":"Card printer","scopes":[]}"#), vec!["scopes"]);
+        assert_eq!(check(r#"{"name":"Card printer","scopes":["books:read","users:write","admin"]}"#),
+            vec!["scopes", "scopes"]);
+    }
+}
@@ -1,43 +1,259 @@
 use chrono::{DateTime, UTC};
 use postgres::Connection;
-use rustc_serialize::{Encodable, Encoder, Decodable, Decoder};
-use std::collections::HashSet;
+use postgres::rows::Row;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-use error::ObsidianError;
-use models::{Model, Includes};
-use models::students::Student;
-use models::books::Book;
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{write_time, Columns, Includable, Includes, Model, Page, Query, Validate};
+use crate::models::books::Book;
+use crate::models::students::{self, Student, DEFAULT_FINAL_FORM};
+use crate::models::copies::BookCopy;
+use crate::models::rollover::validate_forms;
+use crate::models::schools::ValidationRules;
 
-const INSERT_BASE_SET: &'static str = "INSERT INTO base_sets (student_id, book_id, created_at) VALUES ($1, $2, $3) RETURNING id";
-const DELETE_BASE_SET: &'static str = "DELETE FROM base_sets WHERE base_sets.id=$1 AND EXISTS
-(SELECT * FROM books WHERE books.id = base_sets.book_id AND book.school_id = $2)";
+const QUERY_BASE_SET: &str = "SELECT base_sets.id, base_sets.student_id, base_sets.copy_id, copies.book_id,
+base_sets.created_at FROM base_sets, copies, students WHERE base_sets.id=$1 AND base_sets.copy_id = copies.id
+AND base_sets.student_id = students.id AND students.school_id=$2";
+const QUERY_BASE_SETS: &str = "SELECT base_sets.id, base_sets.student_id, base_sets.copy_id, copies.book_id,
+base_sets.created_at FROM base_sets, copies, students WHERE base_sets.copy_id = copies.id
+AND base_sets.student_id = students.id AND students.school_id=$1
+AND ($2::timestamptz IS NULL OR base_sets.created_at >= $2)";
+const BASE_SET_COLUMNS: Columns = &[("id", "base_sets.id"), ("student_id", "base_sets.student_id"),
+    ("copy_id", "base_sets.copy_id"), ("book_id", "copies.book_id"), ("created_at", "base_sets.created_at")];
 
-#[derive(Debug)]
+const QUERY_ASSIGNABLE_STUDENTS: &str = "SELECT id, graduation_year FROM students
+WHERE school_id=$1 AND archived_at IS NULL AND ($2::integer IS NULL OR graduation_year=$2)
+AND ($3::text IS NULL OR class_letter=$3) ORDER BY graduation_year DESC, class_letter, name, id";
+const QUERY_HELD: &str = "SELECT base_sets.student_id, copies.book_id FROM base_sets, copies, students
+WHERE base_sets.copy_id = copies.id AND base_sets.student_id = students.id AND students.school_id=$1";
+const QUERY_AVAILABLE_COPIES: &str = "SELECT copies.id, copies.book_id FROM copies, books
+WHERE copies.book_id = books.id AND books.school_id=$1 AND copies.status='available'
+ORDER BY copies.inventory_number DESC, copies.id DESC";
+
+const INSERT_BASE_SET: &str = "INSERT INTO base_sets (student_id, copy_id, created_at) VALUES ($1, $2, $3) RETURNING id";
+const DELETE_BASE_SET: &str = "DELETE FROM base_sets WHERE base_sets.id=$1 AND EXISTS
+(SELECT * FROM students WHERE students.id = base_sets.student_id AND students.school_id = $2)";
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BaseSet {
+    #[serde(default)]
     id: Option<usize>,
     student_id: usize,
-    book_id: usize,
-    created_at: DateTime<UTC>
+    copy_id: usize,
+    #[serde(skip_deserializing)]
+    book_id: Option<usize>,
+    #[serde(serialize_with = "write_time", skip_deserializing, default = "UTC::now")]
+    created_at: DateTime<UTC>,
+    #[serde(skip_deserializing)]
+    book: Option<Book>,
+    #[serde(skip_deserializing)]
+    student: Option<Student>
+}
+
+/// Conditions beyond the field filters of a `Query`.
+#[derive(Debug, Default)]
+pub struct BaseSetFilter {
+    pub since: Option<DateTime<UTC>>
+}
+
+impl BaseSet {
+    fn from_db(row: Row) -> BaseSet {
+        BaseSet {
+            id: Some(row.get::<usize, i32>(0) as usize),
+            student_id: row.get::<usize, i32>(1) as usize,
+            copy_id: row.get::<usize, i32>(2) as usize,
+            book_id: Some(row.get::<usize, i32>(3) as usize),
+            created_at: row.get::<usize, DateTime<UTC>>(4),
+            book: None,
+            student: None
+        }
+    }
+
+    fn load_includes(base_sets: &mut [BaseSet], school_id: usize, conn: &Connection, includes: &Includes)
+        -> Result<(), ObsidianError> {
+        if includes.contains(&Includable::Book) {
+            let book_ids = base_sets.iter().filter_map(|base_set| base_set.book_id).collect::<Vec<usize>>();
+            let books = Book::find_ids(&book_ids, school_id, conn)?;
+            for base_set in base_sets.iter_mut() {
+                base_set.book = base_set.book_id.and_then(|id| books.get(&id).cloned());
+            }
+        }
+        if includes.contains(&Includable::Person) {
+            let student_ids = base_sets.iter().map(|base_set| base_set.student_id).collect::<Vec<usize>>();
+            let students = Student::find_ids(&student_ids, school_id, conn)?;
+            for base_set in base_sets.iter_mut() {
+                base_set.student = students.get(&base_set.student_id).cloned();
+            }
+        }
+        Ok(())
+    }
+
+    /// Base sets are sorted newest first unless `query` sorts them otherwise.
+    pub fn find_filtered(school_id: usize, query: &Query, filter: &BaseSetFilter, conn: &Connection,
+        includes: &Includes) -> Result<Page<BaseSet>, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        let mut query = query.clone();
+        if query.sort.is_empty() {
+            query.sort = vec![("created_at".to_string(), true), ("id".to_string(), true)];
+        }
+        let mut page = query.execute_with(QUERY_BASE_SETS, &[&(school_id as i32), &filter.since],
+            BASE_SET_COLUMNS, conn, |row| Ok(BaseSet::from_db(row)))?;
+        BaseSet::load_includes(&mut page.items, school_id, conn, includes)?;
+        Ok(page)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Assignment {
+    form: Option<i32>,
+    class_letter: Option<String>,
+    school_year: Option<i32>,
+    final_form: Option<i32>,
+    dry_run: Option<bool>
+}
+
+#[derive(Serialize, Debug)]
+struct Shortage {
+    student_id: usize,
+    book_id: usize
+}
+
+#[derive(Serialize, Debug)]
+pub struct AssignmentSummary {
+    school_year: i32,
+    dry_run: bool,
+    students: usize,
+    already_held: usize,
+    assigned: Vec<BaseSet>,
+    missing_copies: Vec<Shortage>
+}
+
+impl Assignment {
+    /// Hands every student of the selected form and class (or of the whole school) a copy of
+    /// each book whose `form` contains the student's current form, unless the student already
+    /// has a base set of that book. Books without an available copy are reported instead.
+    pub fn perform(&self, school_id: usize, conn: &Connection) -> Result<AssignmentSummary, ObsidianError> {
+        let school_year = self.school_year.unwrap_or(students::current_school_year());
+        let final_form = self.final_form.unwrap_or(DEFAULT_FINAL_FORM);
+        let dry_run = self.dry_run.unwrap_or(false);
+        let graduation_year = self.form.map(|form| school_year + final_form - form);
+        let books = Book::find_forms(school_id, conn)?;
+        let trans = conn.transaction()?;
+
+        let stmt = conn.prepare_cached(QUERY_ASSIGNABLE_STUDENTS)?;
+        let rows = stmt.query(&[&(school_id as i32), &graduation_year, &self.class_letter])?;
+        let targets = rows.iter()
+            .map(|row| (row.get::<usize, i32>(0) as usize, row.get::<usize, i32>(1)))
+            .collect::<Vec<(usize, i32)>>();
+
+        let stmt2 = conn.prepare_cached(QUERY_HELD)?;
+        let rows = stmt2.query(&[&(school_id as i32)])?;
+        let held = rows.iter()
+            .map(|row| (row.get::<usize, i32>(0) as usize, row.get::<usize, i32>(1) as usize))
+            .collect::<HashSet<(usize, usize)>>();
+
+        let stmt3 = conn.prepare_cached(QUERY_AVAILABLE_COPIES)?;
+        let rows = stmt3.query(&[&(school_id as i32)])?;
+        let mut copies: HashMap<usize, Vec<usize>> = HashMap::new();
+        for row in rows.iter() {
+            copies.entry(row.get::<usize, i32>(1) as usize)
+                .or_default()
+                .push(row.get::<usize, i32>(0) as usize);
+        }
+
+        let mut summary = AssignmentSummary {
+            school_year,
+            dry_run,
+            students: targets.len(),
+            already_held: 0,
+            assigned: Vec::new(),
+            missing_copies: Vec::new()
+        };
+        for &(student_id, graduation_year) in &targets {
+            let form = students::form(graduation_year, school_year, final_form);
+            for &(book_id, _) in books.iter().filter(|&(_, forms)| forms.contains(&form)) {
+                if held.contains(&(student_id, book_id)) {
+                    summary.already_held += 1;
+                    continue;
+                }
+                match copies.get_mut(&book_id).and_then(|copies| copies.pop()) {
+                    Some(copy_id) => {
+                        let base_set = BaseSet {
+                            id: None,
+                            student_id,
+                            copy_id,
+                            book_id: None,
+                            created_at: UTC::now(),
+                            book: None,
+                            student: None
+                        };
+                        summary.assigned.push(base_set.save(None, school_id, conn)?);
+                    },
+                    None => summary.missing_copies.push(Shortage {
+                        student_id,
+                        book_id
+                    })
+                }
+            }
+        }
+
+        if !dry_run {
+            trans.commit()?;
+        }
+        Ok(summary)
+    }
+}
+
+impl Validate for BaseSet {}
+
+impl Validate for Assignment {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = validate_forms(self.form, self.final_form);
+        if self.class_letter.as_ref().map(|letter| letter.chars().count() > 1).unwrap_or(false) {
+            errors.push(FieldError::new("class_letter", "The class letter must be a single letter"));
+        }
+        errors
+    }
 }
 
 impl Model for BaseSet {
-    fn find_id(_: usize, _: usize, _: &Connection, _: &Includes) -> Result<Self, ObsidianError> {
-        unreachable!()
+    fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        let stmt = conn.prepare_cached(QUERY_BASE_SET)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("BaseSet"))?;
+        let mut base_sets = vec![BaseSet::from_db(row)];
+        BaseSet::load_includes(&mut base_sets, school_id, conn, includes)?;
+        Ok(base_sets.pop().unwrap())
+    }
+
+    fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        BaseSet::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
     }
 
-    fn find_all(_: usize, _: &Connection, _: &Includes) -> Result<Vec<Self>, ObsidianError> {
-        unreachable!()
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
+        BaseSet::find_filtered(school_id, query, &BaseSetFilter::default(), conn, includes)
     }
 
     fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
-        if let Some(_) = id {
+        if id.is_some() {
             unreachable!()
         } else {
-            try!(Book::find_id(self.book_id, school_id, conn, &HashSet::new()));
-            try!(Student::find_id(self.book_id, school_id, conn, &HashSet::new()));
-            let stmt = try!(conn.prepare_cached(INSERT_BASE_SET));
-            let rows = try!(stmt.query(&[&(self.student_id as i32), &(self.book_id as i32),
-                &self.created_at]));
+            Student::find_id(self.student_id, school_id, conn, &HashSet::new())?;
+            self.book_id = Some(BookCopy::check_out(self.copy_id, school_id, conn)?);
+            let stmt = conn.prepare_cached(INSERT_BASE_SET)?;
+            let rows = stmt.query(&[&(self.student_id as i32), &(self.copy_id as i32),
+                &self.created_at])?;
             let row = rows.iter().next().unwrap();
             self.id = Some(row.get::<usize, i32>(0) as usize);
             Ok(self)
@@ -45,8 +261,8 @@ impl Model for BaseSet {
     }
 
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(DELETE_BASE_SET));
-        let modified = try!(stmt.execute(&[&(id as i32), &(school_id as i32)]));
+        let stmt = conn.prepare_cached(DELETE_BASE_SET)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -55,39 +271,29 @@ impl Model for BaseSet {
     }
 }
 
-impl Encodable for BaseSet {
-    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-        s.emit_struct("BaseSet", 4, |s| {
-            try!(s.emit_struct_field("id", 0, |s| self.id.encode(s)));
-            try!(s.emit_struct_field("student_id", 1, |s| s.emit_usize(self.student_id)));
-            try!(s.emit_struct_field("book_id", 2, |s| s.emit_usize(self.book_id)));
-            try!(s.emit_struct_field("created_at", 3, |s| s.emit_str(&self.created_at.to_rfc3339())));
-            Ok(())
-        })
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::Assignment;
+
+    fn check(body: &str) -> Vec<String> {
+        invalid_fields::<Assignment>(body, &ValidationRules::default())
+    }
+
+    #[test]
+    fn accepts_valid_assignments() {
+        assert!(check("{}").is_empty());
+        assert!(check(r#"{"form":5,"class_letter":"a","final_form":13}"#).is_empty());
+        assert!(check(r#"{"form":13,"final_form":13}"#).is_empty());
     }
-}
 
-impl Decodable for BaseSet {
-    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        d.read_struct("BaseSet", 2, |d| {
-            let student_id = try!(d.read_struct_field("student_id", 0, D::read_usize));
-            let book_id = try!(d.read_struct_field("book_id", 1, D::read_usize));
-            Ok(BaseSet{
-                id: None,
-                student_id: student_id,
-                book_id: book_id,
-                created_at: UTC::now()
-            })
-        }).or_else(|_| d.read_struct("BaseSet", 3, |d| {
-            let id = try!(d.read_struct_field("id", 0, Option::<usize>::decode));
-            let student_id = try!(d.read_struct_field("student_id", 1, D::read_usize));
-            let book_id = try!(d.read_struct_field("book_id", 2, D::read_usize));
-            Ok(BaseSet{
-                id: id,
-                student_id: student_id,
-                book_id: book_id,
-                created_at: UTC::now()
-            })
-        }))
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(check(r#"{"form":0}"#), vec!["form"]);
+        assert_eq!(check(r#"{"form":13}"#), vec!["form"]);
+        assert_eq!(check(r#"{"final_form":14}"#), vec!["final_form"]);
+        assert_eq!(check(r#"{"final_form":0,"form":1}"#), vec!["final_form", "form"]);
+        assert_eq!(check(r#"{"class_letter":"ab"}"#), vec!["class_letter"]);
     }
 }
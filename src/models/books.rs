@@ -1,62 +1,233 @@
-use models::Model;
+use crate::models::Model;
 
 use postgres::Connection;
+use postgres::error::SqlState;
 use postgres::rows::Row;
-use rustc_serialize::{Decoder, Decodable};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{self, SeqAccess, Visitor};
+use std::collections::HashMap;
+use std::fmt;
 
-use error::ObsidianError;
-use models::{Includes, Includable};
-use models::aliases::Alias;
+use crate::error::{FieldError, ObsidianError, ReqError};
+use crate::models::{load_related, Columns, Includes, Includable, Page, Query, Validate};
+use crate::models::aliases::Alias;
+use crate::models::csv::ToCsv;
+use crate::models::copies::{Availability, BookCopy};
+use crate::models::isbn;
+use crate::models::schools::ValidationRules;
 
-const QUERY_BOOK: &'static str = "SELECT id, isbn, title, form FROM books WHERE id = $1 AND school_id = $2";
-const QUERY_BOOKS: &'static str = "SELECT id, isbn, title, form FROM books WHERE school_id = $1";
-const QUERY_ALIASES: &'static str = "SELECT id, name FROM aliases WHERE book_id=$1";
+const QUERY_BOOK: &str = "SELECT id, isbn, title, form FROM books WHERE id = $1 AND school_id = $2";
+const QUERY_BOOKS_BY_ID: &str = "SELECT id, isbn, title, form FROM books WHERE id = ANY($1) AND school_id = $2";
+const QUERY_BOOKS: &str = "SELECT id, isbn, title, form FROM books WHERE school_id = $1";
+const BOOK_COLUMNS: Columns = &[("id", "id"), ("isbn", "isbn"), ("title", "title"), ("form", "form")];
+const QUERY_FORMS: &str = "SELECT id, form FROM books WHERE school_id = $1 ORDER BY id";
+const QUERY_BY_ISBN: &str = "SELECT id FROM books WHERE school_id = $1 AND isbn = $2
+AND id IS DISTINCT FROM $3";
+/// The unique index that keeps ISBNs apart within a school.
+const ISBN_INDEX: &str = "books_isbn_idx";
+const QUERY_ALIASES: &str = "SELECT id, name, book_id FROM aliases WHERE book_id = ANY($1) ORDER BY id";
 
-const INSERT_BOOK: &'static str = "INSERT INTO books (isbn, title, form, school_id) VALUES ($1, $2, $3, $4) RETURNING id";
-const UPDATE_BOOK: &'static str = "UPDATE books SET isbn=$2, title=$3, form=$4 WHERE id=$1 AND school_id=$5";
-const DELETE_BOOK: &'static str = "DELETE FROM books WHERE id=$1 AND school_id=$2";
+const INSERT_BOOK: &str = "INSERT INTO books (isbn, title, form, school_id) VALUES ($1, $2, $3, $4) RETURNING id";
+const UPDATE_BOOK: &str = "UPDATE books SET isbn=$2, title=$3, form=$4 WHERE id=$1 AND school_id=$5";
+const DELETE_BOOK: &str = "DELETE FROM books WHERE id=$1 AND school_id=$2";
 
-#[derive(RustcEncodable, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Book {
+    #[serde(default)]
     id: Option<usize>,
     isbn: String,
     title: String,
-    form: String,
-    aliases: Option<Vec<Alias>>
+    #[serde(deserialize_with = "read_forms")]
+    form: Vec<i32>,
+    #[serde(skip_deserializing)]
+    aliases: Option<Vec<Alias>>,
+    #[serde(skip_deserializing)]
+    availability: Option<Availability>,
+    #[serde(skip_deserializing)]
+    copies: Option<Vec<BookCopy>>
+}
+
+/// The highest form (Jahrgangsstufe) a book can be meant for.
+pub const MAX_FORM: i32 = 13;
+
+/// Parses the legacy representation of the forms a book is meant for, a list like "5,6,7".
+/// Entries may be separated by commas, semicolons or whitespace.
+pub fn parse_forms(form: &str) -> Result<Vec<i32>, String> {
+    let mut forms = Vec::new();
+    let entries = form.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        match entry.parse::<i32>() {
+            Ok(form) => forms.push(form),
+            Err(_) => return Err(format!("'{}' is not a form", entry))
+        }
+    }
+    check_forms(&forms)?;
+    forms.sort();
+    forms.dedup();
+    Ok(forms)
+}
+
+fn check_forms(forms: &[i32]) -> Result<(), String> {
+    match forms.iter().find(|&&form| !(1..=MAX_FORM).contains(&form)) {
+        Some(form) => Err(format!("{} is not a form between 1 and {}", form, MAX_FORM)),
+        None => Ok(())
+    }
+}
+
+struct FormsVisitor;
+
+impl<'de> Visitor<'de> for FormsVisitor {
+    type Value = Vec<i32>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of forms")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<i32>, A::Error> {
+        let mut forms = Vec::new();
+        while let Some(form) = seq.next_element::<i32>()? {
+            forms.push(form);
+        }
+        check_forms(&forms).map_err(de::Error::custom)?;
+        forms.sort();
+        forms.dedup();
+        Ok(forms)
+    }
+
+    /// Accepts the legacy representation of the forms as a string.
+    fn visit_str<E: de::Error>(self, form: &str) -> Result<Vec<i32>, E> {
+        parse_forms(form).map_err(E::custom)
+    }
+}
+
+fn read_forms<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<i32>, D::Error> {
+    d.deserialize_any(FormsVisitor)
 }
 
 impl Book {
-    pub fn new(id: Option<usize>, isbn: String, title: String, form: String) -> Book {
+    /// Returns the ids of all books of a school together with the forms they are meant for.
+    pub fn find_forms(school_id: usize, conn: &Connection) -> Result<Vec<(usize, Vec<i32>)>, ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_FORMS)?;
+        let rows = stmt.query(&[&(school_id as i32)])?;
+        let forms = rows.iter()
+            .map(|row| (row.get::<usize, i32>(0) as usize, row.get::<usize, Vec<i32>>(1)))
+            .collect::<Vec<(usize, Vec<i32>)>>();
+        Ok(forms)
+    }
+
+    pub fn new(id: Option<usize>, isbn: String, title: String, form: Vec<i32>) -> Book {
         Book {
-            id: id,
-            isbn: isbn,
-            title: title,
-            form: form,
-            aliases: None
-        }
-    }
-
-    fn from_db(conn: &Connection, includes: &Includes, row: Row) -> Result<Book, ObsidianError> {
-        let id = row.get::<usize, i32>(0) as usize;
-        let aliases = if includes.contains(&Includable::Aliases) {
-            let stmt = try!(conn.prepare_cached(QUERY_ALIASES));
-            let rows = try!(stmt.query(&[&(id as i32)]));
-            let aliases = rows
-                .iter()
-                .map(|row| Alias::new(Some(row.get::<usize, i32>(0) as usize),
-                    id, row.get::<usize, String>(1)))
-                .collect::<Vec<Alias>>();
-            Some(aliases)
+            id,
+            isbn,
+            title,
+            form,
+            aliases: None,
+            availability: None,
+            copies: None
+        }
+    }
+
+    fn from_db(row: Row) -> Book {
+        Book::new(Some(row.get::<usize, i32>(0) as usize), row.get::<usize, String>(1),
+            row.get::<usize, String>(2), row.get::<usize, Vec<i32>>(3))
+    }
+
+    pub fn csv_fields(&self) -> Vec<String> {
+        let forms = self.form.iter().map(|form| form.to_string()).collect::<Vec<String>>();
+        vec![self.isbn.clone(), self.title.clone(), forms.join(",")]
+    }
+
+    fn load_includes(books: &mut [Book], conn: &Connection, includes: &Includes) -> Result<(), ObsidianError> {
+        let ids = books.iter().filter_map(|book| book.id).collect::<Vec<usize>>();
+        if includes.contains(&Includable::Aliases) {
+            let mut aliases = load_related(QUERY_ALIASES, &ids, 2, conn, |row|
+                Alias::new(Some(row.get::<usize, i32>(0) as usize), row.get::<usize, i32>(2) as usize,
+                    row.get::<usize, String>(1)))?;
+            for book in books.iter_mut() {
+                book.aliases = Some(book.id.and_then(|id| aliases.remove(&id)).unwrap_or(Vec::new()));
+            }
+        }
+        if includes.contains(&Includable::Copies) {
+            let mut copies = BookCopy::find_by_books(&ids, conn)?;
+            for book in books.iter_mut() {
+                let book_copies = book.id.and_then(|id| copies.remove(&id)).unwrap_or(Vec::new());
+                book.availability = Some(Availability::count(&book_copies));
+                book.copies = Some(book_copies);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find_ids(ids: &[usize], school_id: usize, conn: &Connection) -> Result<HashMap<usize, Book>, ObsidianError> {
+        let ids = ids.iter().map(|&id| id as i32).collect::<Vec<i32>>();
+        let stmt = conn.prepare_cached(QUERY_BOOKS_BY_ID)?;
+        let rows = stmt.query(&[&ids, &(school_id as i32)])?;
+        Ok(rows.iter()
+            .map(|row| (row.get::<usize, i32>(0) as usize, Book::from_db(row)))
+            .collect())
+    }
+
+    /// Inserts the book, or updates it if `id` is given.
+    fn write(&mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        if let Some(id) = id {
+            let stmt = conn.prepare_cached(UPDATE_BOOK)?;
+            let modified = stmt.execute(&[&(id as i32), &self.isbn, &self.title,
+                &self.form, &(school_id as i32)])?;
+            if modified == 1 {
+                self.id = Some(id);
+                Ok(())
+            } else {
+                Err(ObsidianError::RecordNotFound("Book"))
+            }
         } else {
-            None
-        };
-        Ok(Book {
-            id: Some(id),
-            isbn: row.get::<usize, String>(1),
-            title: row.get::<usize, String>(2),
-            form: row.get::<usize, String>(3),
-            aliases: aliases
-        })
+            let stmt = conn.prepare_cached(INSERT_BOOK)?;
+            let rows = stmt.query(&[&self.isbn, &self.title, &self.form,
+                &(school_id as i32)])?;
+            let row = rows.iter().next().unwrap();
+            self.id = Some(row.get::<usize, i32>(0) as usize);
+            Ok(())
+        }
+    }
+}
+
+impl ToCsv for Book {
+    fn csv_header(includes: &Includes) -> Vec<&'static str> {
+        let mut header = vec!["id", "isbn", "title", "form"];
+        if includes.contains(&Includable::Aliases) {
+            header.push("aliases");
+        }
+        if includes.contains(&Includable::Copies) {
+            header.extend_from_slice(&["total", "available", "lent", "lost", "retired"]);
+        }
+        header
+    }
+
+    fn csv_rows(&self, includes: &Includes) -> Vec<Vec<String>> {
+        let mut row = vec![self.id.map(|id| id.to_string()).unwrap_or_default()];
+        row.extend(self.csv_fields());
+        if includes.contains(&Includable::Aliases) {
+            row.push(self.aliases.as_ref()
+                .map(|aliases| aliases.iter().map(Alias::name).collect::<Vec<&str>>().join(", "))
+                .unwrap_or_default());
+        }
+        if includes.contains(&Includable::Copies) {
+            row.extend(self.availability.as_ref().map(Availability::csv_fields).unwrap_or(vec![String::new(); 5]));
+        }
+        vec![row]
+    }
+}
+
+impl Validate for Book {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if isbn::normalise(&self.isbn).is_none() {
+            errors.push(FieldError::new("isbn", "The ISBN is not a valid ISBN-10 or ISBN-13"));
+        }
+        if self.title.trim().is_empty() {
+            errors.push(FieldError::new("title", "The title must not be empty"));
+        }
+        errors
     }
 }
 
@@ -64,52 +235,74 @@ impl Model for Book {
     fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError> {
         does_not_support!(BaseSetBooks, includes);
         does_not_support!(LentBooks, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_BOOK));
-        let rows = try!(stmt.query(&[&(id as i32), &(school_id as i32)]));
-        let row = try!(rows.iter().next().ok_or(ObsidianError::RecordNotFound("Book")));
-        let book = try!(Book::from_db(conn, includes, row));
-        if book.id == Some(id) {
-            Ok(book)
-        } else {
-            Err(ObsidianError::RecordNotFound("Book"))
-        }
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let stmt = conn.prepare_cached(QUERY_BOOK)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Book"))?;
+        let mut books = vec![Book::from_db(row)];
+        Book::load_includes(&mut books, conn, includes)?;
+        Ok(books.pop().unwrap())
     }
 
     fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        Book::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
+    }
+
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
         does_not_support!(BaseSetBooks, includes);
         does_not_support!(LentBooks, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_BOOKS));
-        let rows = try!(stmt.query(&[&(school_id as i32)]));
-        rows
-            .iter()
-            .map(|row| Book::from_db(conn, includes, row))
-            .collect::<Result<Vec<Book>, ObsidianError>>()
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        // `form` holds an array, so filtering on it matches the books meant for the given form
+        let mut base = QUERY_BOOKS.to_string();
+        let mut query = query.clone();
+        for (_, value) in query.filters.iter().filter(|&(field, _)| field == "form") {
+            let form = value.parse::<i32>().map_err(|_| ReqError::InvalidParameter("form"))?;
+            base.push_str(&format!(" AND form @> ARRAY[{}]", form));
+        }
+        query.filters.retain(|(field, _)| field != "form");
+        let mut page = query.execute(&base, BOOK_COLUMNS, school_id, conn, |row| Ok(Book::from_db(row)))?;
+        Book::load_includes(&mut page.items, conn, includes)?;
+        Ok(page)
     }
 
     fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
-        if let Some(id) = id {
-            let stmt = try!(conn.prepare_cached(UPDATE_BOOK));
-            let modified = try!(stmt.execute(&[&(id as i32), &self.isbn, &self.title,
-                &self.form, &(school_id as i32)]));
-            if modified == 1 {
-                self.id = Some(id);
-                Ok(self)
-            } else {
-                Err(ObsidianError::RecordNotFound("Book"))
+        self.isbn = isbn::normalise(&self.isbn).ok_or(ObsidianError::InvalidIsbn(self.isbn.clone()))?;
+        if let Some(owner) = isbn_owner(&self.isbn, id, school_id, conn)? {
+            return Err(ObsidianError::DuplicateIsbn(owner));
+        }
+        // Another request may add the ISBN after the check. A failed statement aborts the
+        // caller's transaction, so it runs in a savepoint to look up that book afterwards.
+        let in_transaction = !conn.is_active();
+        if in_transaction {
+            conn.batch_execute("SAVEPOINT book_save")?;
+        }
+        match self.write(id, school_id, conn) {
+            Err(ObsidianError::PostgresError(err)) if is_isbn_clash(&err) => {
+                if in_transaction {
+                    conn.batch_execute("ROLLBACK TO SAVEPOINT book_save")?;
+                }
+                match isbn_owner(&self.isbn, id, school_id, conn)? {
+                    Some(owner) => Err(ObsidianError::DuplicateIsbn(owner)),
+                    None => Err(ObsidianError::from(err))
+                }
+            },
+            result => {
+                if in_transaction && result.is_ok() {
+                    conn.batch_execute("RELEASE SAVEPOINT book_save")?;
+                }
+                result.map(|_| self)
             }
-        } else {
-            let stmt = try!(conn.prepare_cached(INSERT_BOOK));
-            let rows = try!(stmt.query(&[&self.isbn, &self.title, &self.form,
-                &(school_id as i32)]));
-            let row = rows.iter().next().unwrap();
-            self.id = Some(row.get::<usize, i32>(0) as usize);
-            Ok(self)
         }
     }
 
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(DELETE_BOOK));
-        let modified = try!(stmt.execute(&[&(id as i32), &(school_id as i32)]));
+        let stmt = conn.prepare_cached(DELETE_BOOK)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -118,32 +311,72 @@ impl Model for Book {
     }
 }
 
-impl Decodable for Book {
-    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        d.read_struct("Book", 3, |d| {
-            let isbn = try!(d.read_struct_field("isbn", 0, D::read_str));
-            let title = try!(d.read_struct_field("title", 1, D::read_str));
-            let form = try!(d.read_struct_field("form", 2, D::read_str));
-            Ok(Book {
-                id: None,
-                isbn: isbn,
-                title: title,
-                form: form,
-                aliases: None
-            })
-        })
-        .or_else(|_| d.read_struct("Book", 4, |d| {
-            let id = try!(d.read_struct_field("id", 0, Option::<usize>::decode));
-            let isbn = try!(d.read_struct_field("isbn", 1, D::read_str));
-            let title = try!(d.read_struct_field("title", 2, D::read_str));
-            let form = try!(d.read_struct_field("form", 3, D::read_str));
-            Ok(Book {
-                id: id,
-                isbn: isbn,
-                title: title,
-                form: form,
-                aliases: None
-            })
-        }))
+/// The book of the school other than `id` that has the ISBN, if any.
+fn isbn_owner(isbn: &str, id: Option<usize>, school_id: usize, conn: &Connection)
+    -> Result<Option<usize>, ObsidianError> {
+    let stmt = conn.prepare_cached(QUERY_BY_ISBN)?;
+    let rows = stmt.query(&[&(school_id as i32), &isbn, &id.map(|id| id as i32)])?;
+    Ok(rows.iter().next().map(|row| row.get::<usize, i32>(0) as usize))
+}
+
+/// Whether the database refused a write because the school has a book with the ISBN already.
+fn is_isbn_clash(err: &postgres::error::Error) -> bool {
+    match *err {
+        postgres::error::Error::Db(ref err) =>
+            err.code == SqlState::UniqueViolation && err.constraint.as_deref() == Some(ISBN_INDEX),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres::{Connection, TlsMode};
+    use std::env;
+
+    use crate::error::ObsidianError;
+    use crate::migrations;
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::{is_isbn_clash, Book};
+
+    fn check(body: &str) -> Vec<String> {
+        invalid_fields::<Book>(body, &ValidationRules::default())
+    }
+
+    #[test]
+    fn accepts_valid_books() {
+        assert!(check(r#"{"isbn":"978-3-12-731810-4","title":"Mathematik 5","form":[5]}"#).is_empty());
+        assert!(check(r#"{"isbn":"3-12-731810-3","title":"Mathematik 5","form":[5, 6]}"#).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(check(r#"{"isbn":"978-3-12-731810-3","title":"Mathematik 5","form":[5]}"#), vec!["isbn"]);
+        assert_eq!(check(r#"{"isbn":"","title":"Mathematik 5","form":[5]}"#), vec!["isbn"]);
+        assert_eq!(check(r#"{"isbn":"9783127318104","title":" ","form":[5]}"#), vec!["title"]);
+        assert_eq!(check(r#"{"isbn":"keine","title":"","form":[5]}"#), vec!["isbn", "title"]);
+    }
+
+    /// Needs a scratch database at `TEST_DATABASE_URL`; run it with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn recognises_isbns_the_database_refuses() {
+        let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a scratch database");
+        let conn = Connection::connect(&*url, TlsMode::None).unwrap();
+        migrations::up(&conn).unwrap();
+        let school_id: i32 = conn.query("INSERT INTO schools (name) VALUES ('books ' || md5(random()::text))
+            RETURNING id", &[]).unwrap().get(0).get(0);
+        let isbn = "9783127318104".to_string();
+        let mut first = Book::new(None, isbn.clone(), "Mathematik 5".to_string(), vec![5]);
+        let mut second = Book::new(None, isbn, "Mathematik 5".to_string(), vec![5]);
+        let first_written = first.write(None, school_id as usize, &conn);
+        let second_written = second.write(None, school_id as usize, &conn);
+        conn.execute("DELETE FROM schools WHERE id=$1", &[&school_id]).unwrap();
+
+        assert!(first_written.is_ok());
+        match second_written {
+            Err(ObsidianError::PostgresError(ref err)) => assert!(is_isbn_clash(err), "{:?}", err),
+            other => panic!("expected a unique violation, got {:?}", other.err())
+        }
     }
 }
@@ -0,0 +1,310 @@
+use chrono::NaiveDate;
+use postgres::Connection;
+use postgres::rows::Row;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{load_related, transaction, Columns, Includes, Model, Page, Query, Validate};
+use crate::models::books::Book;
+use crate::models::schools::ValidationRules;
+
+const QUERY_COPY: &str = "SELECT copies.id, book_id, inventory_number, condition, acquired_on, status
+FROM copies, books WHERE copies.id=$1 AND copies.book_id = books.id AND books.school_id=$2";
+const QUERY_COPIES: &str = "SELECT copies.id, book_id, inventory_number, condition, acquired_on, status
+FROM copies, books WHERE copies.book_id = books.id AND books.school_id=$1";
+const QUERY_COPY_BY_NUMBER: &str = "SELECT id, book_id, inventory_number, condition, acquired_on, status
+FROM copies WHERE inventory_number=$1 AND school_id=$2";
+const QUERY_BY_NUMBER: &str = "SELECT id FROM copies WHERE school_id = $1 AND inventory_number = $2
+AND id IS DISTINCT FROM $3";
+const COPY_COLUMNS: Columns = &[("id", "copies.id"), ("book_id", "copies.book_id"),
+    ("inventory_number", "copies.inventory_number"), ("condition", "copies.condition"),
+    ("acquired_on", "copies.acquired_on"), ("status", "copies.status")];
+const QUERY_BOOK_COPIES: &str = "SELECT id, book_id, inventory_number, condition, acquired_on, status
+FROM copies WHERE book_id = ANY($1) ORDER BY id";
+
+const INSERT_COPY: &str = "INSERT INTO copies (book_id, inventory_number, condition, acquired_on, status,
+school_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id";
+/// Locks the copy so that it cannot be lent while its status is changed.
+const QUERY_HELD: &str = "SELECT EXISTS (SELECT * FROM lendings WHERE copy_id = copies.id
+AND returned_at IS NULL) OR EXISTS (SELECT * FROM base_sets WHERE copy_id = copies.id)
+FROM copies WHERE id=$1 AND school_id=$2 FOR UPDATE";
+const UPDATE_COPY: &str = "UPDATE copies SET book_id=$2, inventory_number=$3, condition=$4, acquired_on=$5,
+status=$6 WHERE id=$1 AND school_id=$7";
+const RETIRE_COPY: &str = "UPDATE copies SET status='retired' WHERE id=$1 AND school_id=$2";
+
+const CHECK_OUT_COPY: &str = "UPDATE copies SET status='lent' WHERE copies.id=$1 AND status='available'
+AND EXISTS (SELECT * FROM books WHERE books.id = copies.book_id AND books.school_id=$2) RETURNING book_id";
+const CHECK_IN_COPY: &str = "UPDATE copies SET status='available' WHERE id=$1 AND status='lent'";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Default)]
+pub enum CopyStatus {
+    #[default]
+    Available,
+    Lent,
+    Lost,
+    Retired
+}
+
+impl CopyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CopyStatus::Available => "available",
+            CopyStatus::Lent => "lent",
+            CopyStatus::Lost => "lost",
+            CopyStatus::Retired => "retired"
+        }
+    }
+}
+
+
+impl Serialize for CopyStatus {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl FromStr for CopyStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<CopyStatus, ()> {
+        match s {
+            "available" => Ok(CopyStatus::Available),
+            "lent" => Ok(CopyStatus::Lent),
+            "lost" => Ok(CopyStatus::Lost),
+            "retired" => Ok(CopyStatus::Retired),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Availability {
+    total: usize,
+    available: usize,
+    lent: usize,
+    lost: usize,
+    retired: usize
+}
+
+impl Availability {
+    pub fn csv_fields(&self) -> Vec<String> {
+        vec![self.total.to_string(), self.available.to_string(), self.lent.to_string(), self.lost.to_string(),
+            self.retired.to_string()]
+    }
+
+    pub fn count(copies: &[BookCopy]) -> Availability {
+        copies.iter().fold(Availability::default(), |mut acc, copy| {
+            acc.total += 1;
+            match copy.status {
+                CopyStatus::Available => acc.available += 1,
+                CopyStatus::Lent => acc.lent += 1,
+                CopyStatus::Lost => acc.lost += 1,
+                CopyStatus::Retired => acc.retired += 1
+            }
+            acc
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookCopy {
+    #[serde(default)]
+    id: Option<usize>,
+    book_id: usize,
+    inventory_number: String,
+    #[serde(default)]
+    condition: Option<String>,
+    #[serde(default, serialize_with = "write_date", deserialize_with = "read_date")]
+    acquired_on: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "read_status")]
+    status: CopyStatus
+}
+
+fn write_date<S: Serializer>(date: &Option<NaiveDate>, s: S) -> Result<S::Ok, S::Error> {
+    date.map(|date| date.format("%Y-%m-%d").to_string()).serialize(s)
+}
+
+fn read_date<'de, D: Deserializer<'de>>(d: D) -> Result<Option<NaiveDate>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| de::Error::custom("acquired_on must be a date of the form YYYY-MM-DD")),
+        None => Ok(None)
+    }
+}
+
+/// A missing or null status makes a new copy available.
+fn read_status<'de, D: Deserializer<'de>>(d: D) -> Result<CopyStatus, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(status) => CopyStatus::from_str(&status)
+            .map_err(|_| de::Error::custom("status must be one of 'available', 'lent', 'lost' or 'retired'")),
+        None => Ok(CopyStatus::Available)
+    }
+}
+
+impl BookCopy {
+    fn from_db(row: Row) -> BookCopy {
+        BookCopy {
+            id: Some(row.get::<usize, i32>(0) as usize),
+            book_id: row.get::<usize, i32>(1) as usize,
+            inventory_number: row.get::<usize, String>(2),
+            condition: row.get::<usize, Option<String>>(3),
+            acquired_on: row.get::<usize, Option<NaiveDate>>(4),
+            status: CopyStatus::from_str(&row.get::<usize, String>(5)).unwrap_or(CopyStatus::Retired)
+        }
+    }
+
+    pub fn book_id(&self) -> usize {
+        self.book_id
+    }
+
+    pub fn find_by_inventory_number(inventory_number: &str, school_id: usize, conn: &Connection)
+        -> Result<Option<BookCopy>, ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_COPY_BY_NUMBER)?;
+        let rows = stmt.query(&[&inventory_number, &(school_id as i32)])?;
+        let copy = rows.iter().next().map(BookCopy::from_db);
+        Ok(copy)
+    }
+
+    pub fn find_by_books(book_ids: &[usize], conn: &Connection)
+        -> Result<HashMap<usize, Vec<BookCopy>>, ObsidianError> {
+        load_related(QUERY_BOOK_COPIES, book_ids, 1, conn, BookCopy::from_db)
+    }
+
+    pub fn check_out(id: usize, school_id: usize, conn: &Connection) -> Result<usize, ObsidianError> {
+        let stmt = conn.prepare_cached(CHECK_OUT_COPY)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        if let Some(row) = rows.iter().next() {
+            Ok(row.get::<usize, i32>(0) as usize)
+        } else {
+            BookCopy::find_id(id, school_id, conn, &HashSet::new())?;
+            Err(ObsidianError::CopyUnavailable)
+        }
+    }
+
+    pub fn check_in(id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        let stmt = conn.prepare_cached(CHECK_IN_COPY)?;
+        stmt.execute(&[&(id as i32)])?;
+        Ok(())
+    }
+
+    /// Tells whether the copy `id` has an open lending or belongs to a base set, locking it so
+    /// that it cannot be lent in the meantime.
+    fn is_held(id: usize, school_id: usize, conn: &Connection) -> Result<bool, ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_HELD)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Copy"))?;
+        Ok(row.get::<usize, bool>(0))
+    }
+}
+
+impl Validate for BookCopy {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.inventory_number.trim().is_empty() {
+            vec![FieldError::new("inventory_number", "The inventory number must not be empty")]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Model for BookCopy {
+    fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let stmt = conn.prepare_cached(QUERY_COPY)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Copy"))?;
+        Ok(BookCopy::from_db(row))
+    }
+
+    fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        BookCopy::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
+    }
+
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        query.execute(QUERY_COPIES, COPY_COLUMNS, school_id, conn, |row| Ok(BookCopy::from_db(row)))
+    }
+
+    /// Only lendings and base sets mark a copy as `lent` and release it again, so an update
+    /// must keep the copy `lent` exactly while it is held by one of them.
+    fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
+        Book::find_id(self.book_id, school_id, conn, &HashSet::new())?;
+        transaction(conn, || {
+            let stmt = conn.prepare_cached(QUERY_BY_NUMBER)?;
+            let rows = stmt.query(&[&(school_id as i32), &self.inventory_number, &id.map(|id| id as i32)])?;
+            if let Some(row) = rows.iter().next() {
+                return Err(ObsidianError::DuplicateInventoryNumber(row.get::<usize, i32>(0) as usize));
+            }
+            if let Some(id) = id {
+                let held = BookCopy::is_held(id, school_id, conn)?;
+                if held != (self.status == CopyStatus::Lent) {
+                    return Err(ObsidianError::CopyStatusConflict);
+                }
+                let stmt2 = conn.prepare_cached(UPDATE_COPY)?;
+                stmt2.execute(&[&(id as i32), &(self.book_id as i32), &self.inventory_number,
+                    &self.condition, &self.acquired_on, &self.status.as_str(), &(school_id as i32)])?;
+                self.id = Some(id);
+                Ok(self)
+            } else {
+                if self.status == CopyStatus::Lent {
+                    return Err(ObsidianError::CopyStatusConflict);
+                }
+                let stmt = conn.prepare_cached(INSERT_COPY)?;
+                let rows = stmt.query(&[&(self.book_id as i32), &self.inventory_number, &self.condition,
+                    &self.acquired_on, &self.status.as_str(), &(school_id as i32)])?;
+                let row = rows.iter().next().unwrap();
+                self.id = Some(row.get::<usize, i32>(0) as usize);
+                Ok(self)
+            }
+        })
+    }
+
+    /// Retires the copy rather than deleting it, which would take its lending history with it.
+    fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        transaction(conn, || {
+            if BookCopy::is_held(id, school_id, conn)? {
+                return Err(ObsidianError::CopyStatusConflict);
+            }
+            let stmt = conn.prepare_cached(RETIRE_COPY)?;
+            let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
+            if modified == 1 {
+                Ok(())
+            } else {
+                Err(ObsidianError::RecordNotFound("Copy"))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::BookCopy;
+
+    #[test]
+    fn requires_an_inventory_number() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<BookCopy>(r#"{"book_id":1,"inventory_number":"M5-001"}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<BookCopy>(r#"{"book_id":1,"inventory_number":" "}"#, &rules),
+            vec!["inventory_number"]);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::models::Includes;
+
+/// Implemented by the models that can be exported as CSV. A record may span several rows,
+/// e.g. a student with one row per lent book.
+pub trait ToCsv {
+    fn csv_header(includes: &Includes) -> Vec<&'static str>;
+    fn csv_rows(&self, includes: &Includes) -> Vec<Vec<String>>;
+}
+
+/// Splits `body` into records of fields, each with the number of the line it starts on.
+/// Blank lines are skipped but counted, as are line breaks within quoted fields.
+pub fn parse(body: &str, delimiter: char) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                },
+                '\r' if chars.peek() != Some(&'\n') => {
+                    line += 1;
+                    field.push(c);
+                },
+                c => field.push(c)
+            }
+        } else if c == '"' {
+            quoted = true;
+        } else if c == delimiter {
+            record.push(field);
+            field = String::new();
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            record.push(field);
+            field = String::new();
+            if record.len() > 1 || !record[0].is_empty() {
+                records.push((start, record));
+            }
+            record = Vec::new();
+            line += 1;
+            start = line;
+        } else {
+            field.push(c);
+        }
+    }
+    record.push(field);
+    if record.len() > 1 || !record[0].is_empty() {
+        records.push((start, record));
+    }
+    records
+}
+
+fn push_row(out: &mut String, row: &[String], delimiter: char) {
+    for (i, field) in row.iter().enumerate() {
+        if i > 0 {
+            out.push(delimiter);
+        }
+        if field.contains(delimiter) || field.contains('"') || field.contains('\n') || field.contains('\r') {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+pub fn write<T: ToCsv>(records: &[T], includes: &Includes, delimiter: char, bom: bool) -> String {
+    let mut out = String::new();
+    if bom {
+        out.push('\u{feff}');
+    }
+    let header = T::csv_header(includes).into_iter().map(String::from).collect::<Vec<String>>();
+    push_row(&mut out, &header, delimiter);
+    for record in records {
+        for row in record.csv_rows(includes) {
+            push_row(&mut out, &row, delimiter);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    fn lines(body: &str) -> Vec<usize> {
+        parse(body, ',').into_iter().map(|(line, _)| line).collect()
+    }
+
+    #[test]
+    fn numbers_records_by_their_first_line() {
+        assert_eq!(lines("name\nJael\nKatharina\n"), vec![1, 2, 3]);
+        assert_eq!(lines("name\r\nJael\r\nKatharina"), vec![1, 2, 3]);
+        assert_eq!(lines("name\rJael\rKatharina"), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn counts_skipped_blank_lines() {
+        assert_eq!(lines("name\n\nJael\r\n\r\n\nKatharina\n\n"), vec![1, 3, 6]);
+    }
+
+    #[test]
+    fn counts_line_breaks_in_quoted_fields() {
+        let records = parse("name,note\nJael,\"first\r\nsecond\nthird\"\nKatharina,", ',');
+        assert_eq!(records[1], (2, vec!["Jael".to_string(), "first\r\nsecond\nthird".to_string()]));
+        assert_eq!(records[2].0, 5);
+    }
+}
@@ -0,0 +1,101 @@
+/// Parses an ISBN-10 or ISBN-13, ignoring hyphens and whitespace, and returns it as an
+/// ISBN-13 without separators if its check digit is correct.
+pub fn normalise(isbn: &str) -> Option<String> {
+    let chars = isbn.chars()
+        .filter(|&c| c != '-' && !c.is_whitespace())
+        .collect::<Vec<char>>();
+    match chars.len() {
+        10 => parse_isbn10(&chars),
+        13 => parse_isbn13(&chars),
+        _ => None
+    }
+}
+
+fn digits(chars: &[char]) -> Option<Vec<u32>> {
+    chars.iter().map(|c| c.to_digit(10)).collect()
+}
+
+fn check_digit13(digits: &[u32]) -> u32 {
+    let sum = digits.iter()
+        .enumerate()
+        .map(|(i, &digit)| if i % 2 == 0 { digit } else { 3 * digit })
+        .sum::<u32>();
+    (10 - sum % 10) % 10
+}
+
+fn to_string(digits: &[u32]) -> String {
+    digits.iter().map(|digit| digit.to_string()).collect()
+}
+
+fn parse_isbn13(chars: &[char]) -> Option<String> {
+    let digits = digits(chars)?;
+    let prefix_valid = digits.starts_with(&[9, 7, 8]) || digits.starts_with(&[9, 7, 9]);
+    if prefix_valid && check_digit13(&digits[..12]) == digits[12] {
+        Some(to_string(&digits))
+    } else {
+        None
+    }
+}
+
+fn parse_isbn10(chars: &[char]) -> Option<String> {
+    let check = match chars[9] {
+        'X' | 'x' => 10,
+        c => c.to_digit(10)?
+    };
+    let digits = digits(&chars[..9])?;
+    let sum = digits.iter()
+        .enumerate()
+        .map(|(i, &digit)| (10 - i as u32) * digit)
+        .sum::<u32>() + check;
+    if sum % 11 != 0 {
+        return None;
+    }
+    let mut isbn13 = vec![9, 7, 8];
+    isbn13.extend(digits);
+    let check = check_digit13(&isbn13);
+    isbn13.push(check);
+    Some(to_string(&isbn13))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalise;
+
+    #[test]
+    fn accepts_valid_isbn13() {
+        assert_eq!(normalise("9783127318104"), Some("9783127318104".to_string()));
+        assert_eq!(normalise("978-3-12-731810-4"), Some("9783127318104".to_string()));
+        assert_eq!(normalise(" 979 10 90636 07 1 "), Some("9791090636071".to_string()));
+    }
+
+    #[test]
+    fn converts_valid_isbn10() {
+        assert_eq!(normalise("3127318103"), Some("9783127318104".to_string()));
+        assert_eq!(normalise("3-12-731810-3"), Some("9783127318104".to_string()));
+        assert_eq!(normalise("0 306 40615 2"), Some("9780306406157".to_string()));
+    }
+
+    #[test]
+    fn accepts_x_as_isbn10_check_digit() {
+        assert_eq!(normalise("0-8044-2957-X"), Some("9780804429573".to_string()));
+        assert_eq!(normalise("080442957x"), Some("9780804429573".to_string()));
+    }
+
+    #[test]
+    fn rejects_wrong_check_digits() {
+        assert_eq!(normalise("978-3-12-731810-3"), None);
+        assert_eq!(normalise("3-12-731810-1"), None);
+        assert_eq!(normalise("3-12-731810-X"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_isbns() {
+        assert_eq!(normalise(""), None);
+        assert_eq!(normalise("312731810"), None);
+        assert_eq!(normalise("97831273181045"), None);
+        assert_eq!(normalise("97831273181X4"), None);
+        assert_eq!(normalise("X127318103"), None);
+        assert_eq!(normalise("3-12-731810/3"), None);
+        assert_eq!(normalise("9773127318104"), None);
+    }
+}
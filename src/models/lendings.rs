@@ -1,70 +1,348 @@
 use chrono::{DateTime, UTC};
+use chrono::duration::Duration;
 use postgres::Connection;
-use rustc_serialize::{Encodable, Encoder, Decodable, Decoder};
+use postgres::rows::Row;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de;
 use std::collections::HashSet;
+use std::convert::TryFrom;
 
-use error::ObsidianError;
-use models::{Includes, Model};
-use models::books::Book;
-use models::students::Student;
-use models::teachers::Teacher;
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{transaction, write_optional_time, write_time, Columns, Includable, Includes, Model, Page, Query,
+    Validate};
+use crate::models::books::Book;
+use crate::models::csv::ToCsv;
+use crate::models::copies::BookCopy;
+use crate::models::schools::{self, ValidationRules};
+use crate::models::students::Student;
+use crate::models::teachers::Teacher;
 
-const INSERT_ST_LENDING: &'static str = "INSERT INTO lendings (person_type, person_id, book_id, created_at)
-VALUES ('student', $1, $2, $3) RETURNING id";
-const INSERT_TE_LENDING: &'static str = "INSERT INTO lendings (person_type, person_id, book_id, created_at)
-VALUES ('teacher', $1, $2, $3) RETURNING id";
-const DELETE_LENDING: &'static str = "DELETE FROM lendings WHERE lendings.id=$1 AND EXISTS
-(SELECT * FROM books WHERE book.id = lendings.book_id AND book.school_id=$2)";
+const QUERY_LENDING: &str = "SELECT lendings.id, lendings.created_at, lendings.due_at, lendings.person_type,
+lendings.person_id, lendings.copy_id, copies.book_id, lendings.returned_at, lendings.return_note
+FROM lendings, copies, books WHERE lendings.id=$1 AND lendings.copy_id = copies.id AND copies.book_id = books.id
+AND books.school_id=$2";
+const QUERY_LENDINGS: &str = "SELECT lendings.id, lendings.created_at, lendings.due_at, lendings.person_type,
+lendings.person_id, lendings.copy_id, copies.book_id, lendings.returned_at, lendings.return_note
+FROM lendings, copies, books WHERE lendings.copy_id = copies.id AND copies.book_id = books.id AND books.school_id=$1
+AND ($2::timestamptz IS NULL OR lendings.created_at >= $2) AND ($3::boolean IS NULL OR (lendings.returned_at IS NULL) = $3)";
+const LENDING_COLUMNS: Columns = &[("id", "lendings.id"), ("created_at", "lendings.created_at"),
+    ("due_at", "lendings.due_at"), ("person_type", "lendings.person_type"), ("person_id", "lendings.person_id"),
+    ("copy_id", "lendings.copy_id"), ("book_id", "copies.book_id"), ("returned_at", "lendings.returned_at")];
 
-#[derive(Debug)]
+const INSERT_ST_LENDING: &str = "INSERT INTO lendings (person_type, person_id, copy_id, created_at, due_at)
+VALUES ('student', $1, $2, $3, $4) RETURNING id";
+const INSERT_TE_LENDING: &str = "INSERT INTO lendings (person_type, person_id, copy_id, created_at, due_at)
+VALUES ('teacher', $1, $2, $3, $4) RETURNING id";
+const RETURN_LENDING: &str = "UPDATE lendings SET returned_at=$3, return_note=$4 WHERE lendings.id=$1
+AND lendings.returned_at IS NULL AND EXISTS (SELECT * FROM copies, books WHERE copies.id = lendings.copy_id
+AND books.id = copies.book_id AND books.school_id=$2)
+RETURNING id, created_at, due_at, person_type, person_id, copy_id,
+(SELECT book_id FROM copies WHERE copies.id = lendings.copy_id), returned_at, return_note";
+const QUERY_RETURNED: &str = "SELECT returned_at FROM lendings WHERE lendings.id=$1 AND EXISTS
+(SELECT * FROM copies, books WHERE copies.id = lendings.copy_id AND books.id = copies.book_id AND books.school_id=$2)";
+const DELETE_LENDING: &str = "DELETE FROM lendings WHERE lendings.id=$1 AND EXISTS
+(SELECT * FROM copies, books WHERE copies.id = lendings.copy_id AND books.id = copies.book_id AND books.school_id=$2)";
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(tag = "person_type", content = "person_id", rename_all = "lowercase")]
 enum Person {
     Student(usize),
     Teacher(usize)
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum PersonRecord {
+    Student(Student),
+    Teacher(Teacher)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(try_from = "NewLending")]
 pub struct Lending {
     id: Option<usize>,
+    #[serde(serialize_with = "write_time")]
     created_at: DateTime<UTC>,
+    #[serde(serialize_with = "write_optional_time")]
+    due_at: Option<DateTime<UTC>>,
+    #[serde(flatten)]
     person: Person,
-    book_id: usize
+    copy_id: usize,
+    book_id: Option<usize>,
+    #[serde(serialize_with = "write_optional_time")]
+    returned_at: Option<DateTime<UTC>>,
+    return_note: Option<String>,
+    book: Option<Book>,
+    #[serde(rename = "person")]
+    person_record: Option<PersonRecord>
+}
+
+/// The fields of a lending given by clients.
+#[derive(Deserialize)]
+struct NewLending {
+    #[serde(default)]
+    id: Option<usize>,
+    person_type: String,
+    person_id: usize,
+    copy_id: usize,
+    #[serde(default, deserialize_with = "read_due_at")]
+    due_at: Option<DateTime<UTC>>
+}
+
+fn read_due_at<'de, D: Deserializer<'de>>(d: D) -> Result<Option<DateTime<UTC>>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(due_at) => DateTime::parse_from_rfc3339(&due_at)
+            .map(|due_at| Some(due_at.with_timezone(&UTC)))
+            .map_err(|_| de::Error::custom("due_at must be an RFC3339 timestamp")),
+        None => Ok(None)
+    }
+}
+
+impl TryFrom<NewLending> for Lending {
+    type Error = &'static str;
+
+    fn try_from(new: NewLending) -> Result<Lending, &'static str> {
+        let person = match new.person_type.as_ref() {
+            "student" => Person::Student(new.person_id),
+            "teacher" => Person::Teacher(new.person_id),
+            _ => return Err("person_type must be either 'student' or 'teacher'")
+        };
+        Ok(Lending {
+            id: new.id,
+            created_at: UTC::now(),
+            due_at: new.due_at,
+            person,
+            copy_id: new.copy_id,
+            book_id: None,
+            returned_at: None,
+            return_note: None,
+            book: None,
+            person_record: None
+        })
+    }
+}
+
+/// Conditions beyond the field filters of a `Query`.
+#[derive(Debug, Default)]
+pub struct LendingFilter {
+    pub since: Option<DateTime<UTC>>,
+    pub open: Option<bool>
+}
+
+impl Lending {
+    fn from_db(row: Row) -> Lending {
+        let person_id = row.get::<usize, i32>(4) as usize;
+        let person = if row.get::<usize, String>(3) == "student" {
+            Person::Student(person_id)
+        } else {
+            Person::Teacher(person_id)
+        };
+        Lending {
+            id: Some(row.get::<usize, i32>(0) as usize),
+            created_at: row.get::<usize, DateTime<UTC>>(1),
+            due_at: Some(row.get::<usize, DateTime<UTC>>(2)),
+            person,
+            copy_id: row.get::<usize, i32>(5) as usize,
+            book_id: Some(row.get::<usize, i32>(6) as usize),
+            returned_at: row.get::<usize, Option<DateTime<UTC>>>(7),
+            return_note: row.get::<usize, Option<String>>(8),
+            book: None,
+            person_record: None
+        }
+    }
+
+    fn load_includes(lendings: &mut [Lending], school_id: usize, conn: &Connection, includes: &Includes)
+        -> Result<(), ObsidianError> {
+        if includes.contains(&Includable::Book) {
+            let book_ids = lendings.iter().filter_map(|lending| lending.book_id).collect::<Vec<usize>>();
+            let books = Book::find_ids(&book_ids, school_id, conn)?;
+            for lending in lendings.iter_mut() {
+                lending.book = lending.book_id.and_then(|id| books.get(&id).cloned());
+            }
+        }
+        if includes.contains(&Includable::Person) {
+            let student_ids = lendings.iter()
+                .filter_map(|lending| match lending.person { Person::Student(id) => Some(id), _ => None })
+                .collect::<Vec<usize>>();
+            let teacher_ids = lendings.iter()
+                .filter_map(|lending| match lending.person { Person::Teacher(id) => Some(id), _ => None })
+                .collect::<Vec<usize>>();
+            let students = Student::find_ids(&student_ids, school_id, conn)?;
+            let teachers = Teacher::find_ids(&teacher_ids, school_id, conn)?;
+            for lending in lendings.iter_mut() {
+                lending.person_record = match lending.person {
+                    Person::Student(id) => students.get(&id).cloned().map(PersonRecord::Student),
+                    Person::Teacher(id) => teachers.get(&id).cloned().map(PersonRecord::Teacher)
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Lendings are sorted newest first unless `query` sorts them otherwise.
+    pub fn find_filtered(school_id: usize, query: &Query, filter: &LendingFilter, conn: &Connection,
+        includes: &Includes) -> Result<Page<Lending>, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        let mut query = query.clone();
+        if query.sort.is_empty() {
+            query.sort = vec![("created_at".to_string(), true), ("id".to_string(), true)];
+        }
+        let mut page = query.execute_with(QUERY_LENDINGS, &[&(school_id as i32), &filter.since, &filter.open],
+            LENDING_COLUMNS, conn, |row| Ok(Lending::from_db(row)))?;
+        Lending::load_includes(&mut page.items, school_id, conn, includes)?;
+        Ok(page)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Return {
+    note: Option<String>
+}
+
+impl Return {
+    pub fn perform(self, id: usize, school_id: usize, conn: &Connection) -> Result<Lending, ObsidianError> {
+        transaction(conn, || self.mark_returned(id, school_id, conn))
+    }
+
+    fn mark_returned(&self, id: usize, school_id: usize, conn: &Connection) -> Result<Lending, ObsidianError> {
+        let stmt = conn.prepare_cached(RETURN_LENDING)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32), &UTC::now(), &self.note])?;
+        if let Some(row) = rows.iter().next() {
+            let lending = Lending::from_db(row);
+            BookCopy::check_in(lending.copy_id, conn)?;
+            Ok(lending)
+        } else {
+            let stmt2 = conn.prepare_cached(QUERY_RETURNED)?;
+            let rows = stmt2.query(&[&(id as i32), &(school_id as i32)])?;
+            if rows.is_empty() {
+                Err(ObsidianError::RecordNotFound("Lending"))
+            } else {
+                Err(ObsidianError::AlreadyReturned)
+            }
+        }
+    }
+}
+
+impl ToCsv for Lending {
+    fn csv_header(includes: &Includes) -> Vec<&'static str> {
+        let mut header = vec!["id", "created_at", "due_at", "returned_at", "return_note", "person_type", "person_id",
+            "copy_id", "book_id"];
+        if includes.contains(&Includable::Book) {
+            header.extend_from_slice(&["isbn", "title", "form"]);
+        }
+        if includes.contains(&Includable::Person) {
+            header.push("name");
+        }
+        header
+    }
+
+    fn csv_rows(&self, includes: &Includes) -> Vec<Vec<String>> {
+        let (person_type, person_id) = match self.person {
+            Person::Student(id) => ("student", id),
+            Person::Teacher(id) => ("teacher", id)
+        };
+        let mut row = vec![
+            self.id.map(|id| id.to_string()).unwrap_or_default(),
+            self.created_at.to_rfc3339(),
+            self.due_at.map(|date| date.to_rfc3339()).unwrap_or_default(),
+            self.returned_at.map(|date| date.to_rfc3339()).unwrap_or_default(),
+            self.return_note.clone().unwrap_or_default(),
+            person_type.to_string(),
+            person_id.to_string(),
+            self.copy_id.to_string(),
+            self.book_id.map(|id| id.to_string()).unwrap_or_default()
+        ];
+        if includes.contains(&Includable::Book) {
+            row.extend(self.book.as_ref().map(Book::csv_fields).unwrap_or(vec![String::new(); 3]));
+        }
+        if includes.contains(&Includable::Person) {
+            row.push(match self.person_record {
+                Some(PersonRecord::Student(ref student)) => student.name().to_string(),
+                Some(PersonRecord::Teacher(ref teacher)) => teacher.name().to_string(),
+                None => String::new()
+            });
+        }
+        vec![row]
+    }
 }
 
+impl Validate for Lending {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.due_at.map(|due_at| due_at < self.created_at).unwrap_or(false) {
+            vec![FieldError::new("due_at", "The due date must not be before the lending")]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Validate for Return {}
+
 impl Model for Lending {
-    fn find_id(_: usize, _: usize, _: &Connection, _: &Includes) -> Result<Self, ObsidianError> {
-        unreachable!()
+    fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        let stmt = conn.prepare_cached(QUERY_LENDING)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Lending"))?;
+        let mut lendings = vec![Lending::from_db(row)];
+        Lending::load_includes(&mut lendings, school_id, conn, includes)?;
+        Ok(lendings.pop().unwrap())
+    }
+
+    fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        Lending::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
     }
 
-    fn find_all(_: usize, _: &Connection, _: &Includes) -> Result<Vec<Self>, ObsidianError> {
-        unreachable!()
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
+        Lending::find_filtered(school_id, query, &LendingFilter::default(), conn, includes)
     }
 
     fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
-        if let Some(_) = id {
+        if id.is_some() {
             unreachable!()
         } else {
             let (id, query) = match self.person {
                 Person::Student(id) => {
-                    try!(Student::find_id(id, school_id, conn, &HashSet::new()));
+                    Student::find_id(id, school_id, conn, &HashSet::new())?;
                     (id, INSERT_ST_LENDING)
                 },
                 Person::Teacher(id) => {
-                    try!(Teacher::find_id(id, school_id, conn, &HashSet::new()));
+                    Teacher::find_id(id, school_id, conn, &HashSet::new())?;
                     (id, INSERT_TE_LENDING)
                 }
             };
-            try!(Book::find_id(self.book_id, school_id, conn, &HashSet::new()));
-            let stmt = try!(conn.prepare_cached(query));
-            let rows = try!(stmt.query(&[&(id as i32), &(self.book_id as i32), &self.created_at]));
-            let row = rows.iter().next().unwrap();
-            self.id = Some(row.get::<usize, i32>(0) as usize);
-            Ok(self)
+            let due_at = match self.due_at {
+                Some(due_at) => due_at,
+                None => {
+                    let loan_period = Duration::days(schools::loan_period(school_id, conn)?);
+                    self.created_at.checked_add(loan_period).ok_or_else(|| ObsidianError::InvalidFields(vec![
+                        FieldError::new("due_at", "The loan period puts the due date out of range")]))?
+                }
+            };
+            self.due_at = Some(due_at);
+            transaction(conn, || {
+                self.book_id = Some(BookCopy::check_out(self.copy_id, school_id, conn)?);
+                let stmt = conn.prepare_cached(query)?;
+                let rows = stmt.query(&[&(id as i32), &(self.copy_id as i32), &self.created_at, &due_at])?;
+                let row = rows.iter().next().unwrap();
+                self.id = Some(row.get::<usize, i32>(0) as usize);
+                Ok(self)
+            })
         }
     }
 
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(DELETE_LENDING));
-        let modified = try!(stmt.execute(&[&(id as i32), &(school_id as i32)]));
+        let stmt = conn.prepare_cached(DELETE_LENDING)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -72,63 +350,3 @@ impl Model for Lending {
         }
     }
 }
-
-impl Encodable for Lending {
-    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
-        s.emit_struct("Lending", 5, |s| {
-            try!(s.emit_struct_field("id", 0, |s| self.id.encode(s)));
-            try!(s.emit_struct_field("created_at", 1, |s| s.emit_str(&self.created_at.to_rfc3339())));
-            match self.person {
-                Person::Student(id) => {
-                    try!(s.emit_struct_field("person_type", 2, |s| s.emit_str("student")));
-                    try!(s.emit_struct_field("person_id", 3, |s| s.emit_usize(id)));
-                },
-                Person::Teacher(id) => {
-                    try!(s.emit_struct_field("person_type", 2, |s| s.emit_str("teacher")));
-                    try!(s.emit_struct_field("person_id", 3, |s| s.emit_usize(id)));
-                }
-            }
-            try!(s.emit_struct_field("book_id", 4, |s| s.emit_usize(self.book_id)));
-            Ok(())
-        })
-    }
-}
-
-impl Decodable for Lending {
-    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        d.read_struct("Lending", 3, |d| {
-            let person_type = try!(d.read_struct_field("person_type", 0, D::read_str));
-            let person = if person_type == "student" {
-                Person::Student(try!(d.read_struct_field("person_id", 1, D::read_usize)))
-            } else if person_type == "teacher" {
-                Person::Teacher(try!(d.read_struct_field("person_id", 1, D::read_usize)))
-            } else {
-                return Err(d.error("person_type must be either 'student' or 'teacher'"))
-            };
-            let book_id = try!(d.read_struct_field("book_id", 2, D::read_usize));
-            Ok(Lending{
-                id: None,
-                created_at: UTC::now(),
-                person: person,
-                book_id: book_id
-            })
-        }).or_else(|_| d.read_struct("Lending", 4, |d| {
-            let id = try!(d.read_struct_field("id", 0, Option::<usize>::decode));
-            let person_type = try!(d.read_struct_field("person_type", 1, D::read_str));
-            let person = if person_type == "student" {
-                Person::Student(try!(d.read_struct_field("person_id", 2, D::read_usize)))
-            } else if person_type == "teacher" {
-                Person::Teacher(try!(d.read_struct_field("person_id", 2, D::read_usize)))
-            } else {
-                return Err(d.error("person_type must be either 'student' or 'teacher'"))
-            };
-            let book_id = try!(d.read_struct_field("book_id", 3, D::read_usize));
-            Ok(Lending{
-                id: id,
-                created_at: UTC::now(),
-                person: person,
-                book_id: book_id
-            })
-        }))
-    }
-}
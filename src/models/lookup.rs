@@ -0,0 +1,95 @@
+use postgres::Connection;
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use std::collections::HashSet;
+
+use crate::error::ObsidianError;
+use crate::models::Model;
+use crate::models::books::Book;
+use crate::models::copies::BookCopy;
+use crate::models::isbn;
+use crate::models::students::Student;
+use crate::models::teachers::Teacher;
+
+const QUERY_BOOKS_BY_ISBN: &str = "SELECT id FROM books WHERE school_id = $1 AND isbn = $2";
+const QUERY_BOOKS_BY_ALIAS: &str = "SELECT DISTINCT books.id FROM books, aliases
+WHERE aliases.book_id = books.id AND books.school_id = $1 AND lower(aliases.name) = lower($2) ORDER BY books.id";
+const QUERY_STUDENTS_BY_CARD: &str = "SELECT id FROM students WHERE school_id = $1 AND external_id = $2
+AND archived_at IS NULL";
+
+/// A record a scanned code or a search resolves to.
+#[derive(Debug)]
+pub enum Hit {
+    Book(Book),
+    Copy(BookCopy, Book),
+    Student(Student),
+    Teacher(Teacher)
+}
+
+fn find_ids(sql: &str, school_id: usize, code: &str, conn: &Connection) -> Result<Vec<usize>, ObsidianError> {
+    let stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query(&[&(school_id as i32), &code])?;
+    let ids = rows.iter().map(|row| row.get::<usize, i32>(0) as usize).collect::<Vec<usize>>();
+    Ok(ids)
+}
+
+/// Resolves a code scanned at the desk, which may be an ISBN, the inventory number of a
+/// copy, the name of an alias or the external id printed on a student card.
+pub fn lookup(code: &str, school_id: usize, conn: &Connection) -> Result<Vec<Hit>, ObsidianError> {
+    let code = code.trim();
+    let mut hits = Vec::new();
+
+    let mut book_ids = match isbn::normalise(code) {
+        Some(isbn) => find_ids(QUERY_BOOKS_BY_ISBN, school_id, &isbn, conn)?,
+        None => Vec::new()
+    };
+    for id in find_ids(QUERY_BOOKS_BY_ALIAS, school_id, code, conn)? {
+        if !book_ids.contains(&id) {
+            book_ids.push(id);
+        }
+    }
+    let mut books = Book::find_ids(&book_ids, school_id, conn)?;
+    hits.extend(book_ids.iter().filter_map(|id| books.remove(id)).map(Hit::Book));
+
+    if let Some(copy) = BookCopy::find_by_inventory_number(code, school_id, conn)? {
+        let book = Book::find_id(copy.book_id(), school_id, conn, &HashSet::new())?;
+        hits.push(Hit::Copy(copy, book));
+    }
+
+    let student_ids = find_ids(QUERY_STUDENTS_BY_CARD, school_id, code, conn)?;
+    let mut students = Student::find_ids(&student_ids, school_id, conn)?;
+    hits.extend(student_ids.iter().filter_map(|id| students.remove(id)).map(Hit::Student));
+    Ok(hits)
+}
+
+impl Serialize for Hit {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Hit::Book(ref book) => {
+                let mut hit = s.serialize_struct("Hit", 2)?;
+                hit.serialize_field("type", "book")?;
+                hit.serialize_field("record", book)?;
+                hit.end()
+            },
+            Hit::Copy(ref copy, ref book) => {
+                let mut hit = s.serialize_struct("Hit", 3)?;
+                hit.serialize_field("type", "copy")?;
+                hit.serialize_field("record", copy)?;
+                hit.serialize_field("book", book)?;
+                hit.end()
+            },
+            Hit::Student(ref student) => {
+                let mut hit = s.serialize_struct("Hit", 2)?;
+                hit.serialize_field("type", "student")?;
+                hit.serialize_field("record", student)?;
+                hit.end()
+            },
+            Hit::Teacher(ref teacher) => {
+                let mut hit = s.serialize_struct("Hit", 2)?;
+                hit.serialize_field("type", "teacher")?;
+                hit.serialize_field("record", teacher)?;
+                hit.end()
+            }
+        }
+    }
+}
@@ -1,33 +1,53 @@
 macro_rules! does_not_support {
-    ($includable:ident, $includes:expr) => (try!(
+    ($includable:ident, $includes:expr) => ((
         if $includes.contains(&$crate::models::Includable::$includable) {
             ::std::result::Result::Err($crate::error::ObsidianError::IncludeNotSupported($crate::models::Includable::$includable))
         } else {
             Ok(())
         }
-    ));
+    )?);
 }
 
+pub mod csv;
 pub mod students;
+pub mod student_import;
 pub mod books;
+pub mod isbn;
+pub mod lookup;
 pub mod aliases;
+pub mod api_keys;
+pub mod copies;
 pub mod teachers;
 pub mod base_sets;
 pub mod lendings;
+pub mod overdue;
+pub mod rollover;
+pub mod search;
 pub mod schools;
 pub mod sessions;
+pub mod throttle;
+pub mod users;
 
+use chrono::{DateTime, NaiveDate, UTC};
 use postgres::Connection;
-use rustc_serialize::{json, Encodable, Decodable};
-use std::collections::HashSet;
+use postgres::rows::Row;
+use postgres::types::{ToSql, Type};
+use serde::{Serialize, Serializer};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
 
-use error::ObsidianError;
+use crate::error::{FieldError, ObsidianError, ReqError};
+use crate::models::schools::ValidationRules;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub enum Includable {
     LentBooks,
     BaseSetBooks,
-    Aliases
+    Aliases,
+    LendingHistory,
+    Copies,
+    Book,
+    Person
 }
 
 pub type Includes = HashSet<Includable>;
@@ -37,24 +57,347 @@ impl Includable {
         val.split(',').filter_map(|item|
             match item.to_lowercase().as_ref() {
                 "aliases" => {Some(Includable::Aliases)},
+                "copies" => {Some(Includable::Copies)},
+                "book" => {Some(Includable::Book)},
+                "person" | "student" => {Some(Includable::Person)},
                 "lendings.book" | "lendings" => {Some(Includable::LentBooks)},
                 "basesets.book" | "basesets" => {Some(Includable::BaseSetBooks)},
+                "lendinghistory.book" | "lendinghistory" => {Some(Includable::LendingHistory)},
                 _ => {None}
             }).collect()
     }
 }
 
-pub trait Model: Encodable + Decodable {
+/// Maps the field names accepted in query strings to SQL expressions. The first entry
+/// is the primary key and serves as the default sort order.
+pub type Columns = &'static [(&'static str, &'static str)];
+
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    pub filters: Vec<(String, String)>,
+    pub sort: Vec<(String, bool)>,
+    pub page: usize,
+    pub per_page: Option<usize>
+}
+
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize
+}
+
+impl Query {
+    fn column(columns: Columns, field: &str) -> Result<(&'static str, &'static str), ObsidianError> {
+        columns.iter()
+            .find(|&&(name, _)| name == field)
+            .cloned()
+            .ok_or_else(|| ObsidianError::from(ReqError::UnknownField(field.to_string())))
+    }
+
+    /// Filter values arrive as strings and are converted to the type Postgres infers for
+    /// their parameter, so that the comparison can use an index on the column.
+    fn filter_value(ty: &Type, field: &'static str, value: &str) -> Result<Box<dyn ToSql>, ObsidianError> {
+        let invalid = || ObsidianError::from(ReqError::InvalidParameter(field));
+        Ok(match *ty {
+            Type::Int2 => Box::new(value.parse::<i16>().map_err(|_| invalid())?),
+            Type::Int4 => Box::new(value.parse::<i32>().map_err(|_| invalid())?),
+            Type::Int8 => Box::new(value.parse::<i64>().map_err(|_| invalid())?),
+            Type::Bool => Box::new(value.parse::<bool>().map_err(|_| invalid())?),
+            Type::Date => Box::new(NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?),
+            Type::Timestamptz => Box::new(DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&UTC))
+                .map_err(|_| invalid())?),
+            _ => Box::new(value.to_string())
+        })
+    }
+
+    /// Runs `base`, which must select from a single school given as `$1`, with the filters,
+    /// sort order and page of this query applied.
+    pub fn execute<T, F>(&self, base: &str, columns: Columns, school_id: usize, conn: &Connection, f: F)
+        -> Result<Page<T>, ObsidianError> where F: FnMut(Row) -> Result<T, ObsidianError> {
+        self.execute_with(base, &[&(school_id as i32)], columns, conn, f)
+    }
+
+    /// Like `execute`, for a `base` that takes the parameters `params` (starting with the
+    /// school) for conditions beyond plain equality.
+    pub fn execute_with<T, F>(&self, base: &str, params: &[&dyn ToSql], columns: Columns, conn: &Connection, f: F)
+        -> Result<Page<T>, ObsidianError> where F: FnMut(Row) -> Result<T, ObsidianError> {
+        let mut filtered = base.to_string();
+        let mut fields = Vec::new();
+        for (i, (field, _)) in self.filters.iter().enumerate() {
+            let (name, expr) = Query::column(columns, field)?;
+            filtered.push_str(&format!(" AND {} = ${}", expr, params.len() + i + 1));
+            fields.push(name);
+        }
+        let mut order = Vec::new();
+        for &(ref field, descending) in &self.sort {
+            order.push(format!("{} {}", Query::column(columns, field)?.1, if descending {"DESC"} else {"ASC"}));
+        }
+        order.push(format!("{} ASC", columns[0].1));
+
+        let (limit, offset) = match self.per_page {
+            Some(per_page) => {
+                let offset = self.page.saturating_sub(1).checked_mul(per_page)
+                    .and_then(|offset| if offset <= i64::MAX as usize { Some(offset as i64) } else { None })
+                    .ok_or(ReqError::InvalidParameter("page"))?;
+                (Some(per_page as i64), Some(offset))
+            },
+            None => (None, None)
+        };
+
+        let stmt = conn.prepare_cached(&format!("SELECT count(*) FROM ({}) AS filtered", filtered))?;
+        let values = self.filters.iter()
+            .zip(stmt.param_types()[params.len()..].iter())
+            .zip(fields.iter())
+            .map(|(((_, value), ty), field)| Query::filter_value(ty, field, value))
+            .collect::<Result<Vec<Box<dyn ToSql>>, ObsidianError>>()?;
+        let mut params = params.to_vec();
+        for value in &values {
+            params.push(&**value);
+        }
+        let rows = stmt.query(&params)?;
+        let total = rows.iter().next().unwrap().get::<usize, i64>(0) as usize;
+
+        let mut sql = format!("{} ORDER BY {}", filtered, order.join(", "));
+        if let (Some(limit), Some(offset)) = (&limit, &offset) {
+            sql.push_str(&format!(" LIMIT ${} OFFSET ${}", params.len() + 1, params.len() + 2));
+            params.push(limit);
+            params.push(offset);
+        }
+        let stmt = conn.prepare_cached(&sql)?;
+        let rows = stmt.query(&params)?;
+        let items = rows.iter().map(f).collect::<Result<Vec<T>, ObsidianError>>()?;
+        Ok(Page {
+            items,
+            total
+        })
+    }
+}
+
+/// Runs `sql` with an array of record ids as `$1` and groups the rows by the id in column
+/// `key`, so that a relation is loaded for any number of records with a single query.
+pub fn load_related<T, F>(sql: &str, ids: &[usize], key: usize, conn: &Connection, f: F)
+    -> Result<HashMap<usize, Vec<T>>, ObsidianError> where F: Fn(Row) -> T {
+    let mut related = HashMap::new();
+    if ids.is_empty() {
+        return Ok(related);
+    }
+    let ids = ids.iter().map(|&id| id as i32).collect::<Vec<i32>>();
+    let stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query(&[&ids])?;
+    for row in rows.iter() {
+        let id = row.get::<usize, i32>(key) as usize;
+        related.entry(id).or_insert_with(Vec::new).push(f(row));
+    }
+    Ok(related)
+}
+
+/// Serialises a timestamp as RFC3339, which is how the API represents all of them.
+pub fn write_time<S: Serializer>(time: &DateTime<UTC>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&time.to_rfc3339())
+}
+
+pub fn write_optional_time<S: Serializer>(time: &Option<DateTime<UTC>>, s: S) -> Result<S::Ok, S::Error> {
+    time.map(|time| time.to_rfc3339()).serialize(s)
+}
+
+/// Runs `f` in a transaction, or in the caller's one if it has opened a transaction already,
+/// e.g. to save all items of a bulk creation at once.
+pub fn transaction<T, F>(conn: &Connection, f: F) -> Result<T, ObsidianError>
+    where F: FnOnce() -> Result<T, ObsidianError> {
+    if !conn.is_active() {
+        return f();
+    }
+    let trans = conn.transaction()?;
+    let result = f()?;
+    trans.commit()?;
+    Ok(result)
+}
+
+/// Checks the fields of decoded records before they are used. Records without any
+/// requirements beyond their types can rely on the default.
+pub trait Validate {
+    /// Returns all problems of the record at once; an empty list means it is valid.
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        Vec::new()
+    }
+
+    fn check(&self, rules: &ValidationRules) -> Result<(), ObsidianError> {
+        let errors = self.validate(rules);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ObsidianError::InvalidFields(errors))
+        }
+    }
+}
+
+/// Decodes `body` and returns the fields it fails validation on, for testing `validate`.
+#[cfg(test)]
+pub fn invalid_fields<T: DeserializeOwned + Validate>(body: &str, rules: &ValidationRules) -> Vec<String> {
+    serde_json::from_str::<T>(body).unwrap().validate(rules).into_iter().map(|error| error.field).collect()
+}
+
+pub trait Model: Serialize + DeserializeOwned + Validate {
     fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError>;
     fn find_all(school_id: usize, conn: &Connection,includes: &Includes) -> Result<Vec<Self>, ObsidianError>;
+
+    /// Models without a column list can only be paged through, not filtered or sorted.
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
+        if let Some((field, _)) = query.filters.first() {
+            return Err(ObsidianError::from(ReqError::UnknownField(field.clone())));
+        }
+        if let Some((field, _)) = query.sort.first() {
+            return Err(ObsidianError::from(ReqError::UnknownField(field.clone())));
+        }
+        let mut items = Self::find_all(school_id, conn, includes)?;
+        let total = items.len();
+        if let Some(per_page) = query.per_page {
+            let start = query.page.saturating_sub(1).checked_mul(per_page).unwrap_or(total);
+            items = items.into_iter().skip(start).take(per_page).collect();
+        }
+        Ok(Page {
+            items,
+            total
+        })
+    }
     fn save(self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError>;
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError>;
 
     fn parse_str(body: &str) -> Result<Self, ObsidianError> {
-        json::decode::<Self>(body).map_err(ObsidianError::from)
+        serde_json::from_str::<Self>(body).map_err(ObsidianError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres::{Connection, TlsMode};
+    use std::env;
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use crate::migrations;
+    use crate::models::{Includable, Includes, Model, Query};
+    use crate::models::books::Book;
+    use crate::models::lendings::{Lending, LendingFilter};
+    use crate::models::students::Student;
+
+    /// Connects to `TEST_DATABASE_URL` through a proxy that counts the statements the client
+    /// executes, i.e. the Execute messages of the extended query protocol.
+    fn counting_connection() -> (Connection, Arc<AtomicUsize>) {
+        let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a scratch database");
+        let start = url.find('@').map(|at| at + 1).unwrap_or(url.find("//").unwrap() + 2);
+        let end = url[start..].find('/').map(|slash| start + slash).unwrap_or(url.len());
+        let target = url[start..end].to_string();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_url = format!("{}{}{}", &url[..start], listener.local_addr().unwrap(), &url[end..]);
+        let executed = Arc::new(AtomicUsize::new(0));
+        let counter = executed.clone();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut server = TcpStream::connect(&*target).unwrap();
+            server.set_nodelay(true).unwrap();
+            client.set_nodelay(true).unwrap();
+            let mut client_out = client.try_clone().unwrap();
+            let mut server_in = server.try_clone().unwrap();
+            thread::spawn(move || io::copy(&mut server_in, &mut client_out));
+            // The startup message has no type byte; every later one has.
+            let mut head = [0u8; 4];
+            let mut typed = false;
+            loop {
+                let mut tag = [0u8; 1];
+                if typed && client.read_exact(&mut tag).is_err() {
+                    return;
+                }
+                if client.read_exact(&mut head).is_err() {
+                    return;
+                }
+                let len = ((head[0] as usize) << 24) | ((head[1] as usize) << 16) | ((head[2] as usize) << 8) |
+                    head[3] as usize;
+                let mut body = vec![0u8; len - 4];
+                if client.read_exact(&mut body).is_err() {
+                    return;
+                }
+                if typed {
+                    if tag[0] == b'E' {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    }
+                    server.write_all(&tag).unwrap();
+                }
+                server.write_all(&head).unwrap();
+                server.write_all(&body).unwrap();
+                typed = true;
+            }
+        });
+        let conn = Connection::connect(&*proxy_url, TlsMode::None).unwrap();
+        migrations::up(&conn).unwrap();
+        (conn, executed)
+    }
+
+    /// Creates a school with `n` students, each with a book that has an alias, a lent copy,
+    /// a returned lending and a copy in a base set.
+    fn school_with_students(n: usize, conn: &Connection) -> usize {
+        let rows = conn.query("INSERT INTO schools (name) VALUES ('query count ' || $1::text) RETURNING id",
+            &[&(n as i32).to_string()]).unwrap();
+        let school_id: i32 = rows.get(0).get(0);
+        for i in 0..n as i32 {
+            let student_id: i32 = conn.query("INSERT INTO students (name, class_letter, graduation_year, school_id)
+                VALUES ('Student ' || $1::text, 'a', 2030, $2) RETURNING id", &[&i.to_string(), &school_id])
+                .unwrap().get(0).get(0);
+            let book_id: i32 = conn.query("INSERT INTO books (isbn, title, form, school_id)
+                VALUES ('978' || lpad($1::text, 10, '0'), 'Book', '{5}', $2) RETURNING id",
+                &[&i.to_string(), &school_id]).unwrap().get(0).get(0);
+            conn.execute("INSERT INTO aliases (name, book_id) VALUES ('Alias ' || $1::text, $2)",
+                &[&i.to_string(), &book_id]).unwrap();
+            for (kind, status) in [("lent", "lent"), ("returned", "available"), ("base", "available")] {
+                let copy_id: i32 = conn.query("INSERT INTO copies (book_id, inventory_number, status, school_id)
+                    VALUES ($1, $2 || $3::text, $4, $5) RETURNING id",
+                    &[&book_id, &kind, &i.to_string(), &status, &school_id]).unwrap().get(0).get(0);
+                match kind {
+                    "base" => conn.execute("INSERT INTO base_sets (student_id, copy_id, created_at)
+                        VALUES ($1, $2, now())", &[&student_id, &copy_id]),
+                    _ => conn.execute("INSERT INTO lendings (person_type, person_id, copy_id, created_at, due_at,
+                        returned_at) VALUES ('student', $1, $2, now(), now(), CASE WHEN $3 THEN now() END)",
+                        &[&student_id, &copy_id, &(kind == "returned")])
+                }.unwrap();
+            }
+        }
+        school_id as usize
+    }
+
+    fn includes(includables: &[Includable]) -> Includes {
+        includables.iter().cloned().collect()
+    }
+
+    /// Returns the number of statements it takes to list `n` students, books and lendings
+    /// with all their includes.
+    fn statements_for(n: usize) -> usize {
+        let (conn, executed) = counting_connection();
+        let _trans = conn.transaction().unwrap();
+        let school_id = school_with_students(n, &conn);
+        let query = Query::default();
+        let before = executed.load(Ordering::SeqCst);
+        let students = Student::find_page(school_id, &query, &conn,
+            &includes(&[Includable::LentBooks, Includable::BaseSetBooks, Includable::LendingHistory])).unwrap();
+        let books = Book::find_page(school_id, &query, &conn,
+            &includes(&[Includable::Aliases, Includable::Copies])).unwrap();
+        let lendings = Lending::find_filtered(school_id, &query, &LendingFilter::default(), &conn,
+            &includes(&[Includable::Book, Includable::Person])).unwrap();
+        let after = executed.load(Ordering::SeqCst);
+        assert_eq!(students.items.len(), n);
+        assert_eq!(books.items.len(), n);
+        assert_eq!(lendings.items.len(), 2 * n);
+        after - before
     }
 
-    fn to_str(&self) -> Result<String, ObsidianError> {
-        json::encode(self).map_err(ObsidianError::from)
+    #[test]
+    #[ignore]
+    fn includes_take_the_same_number_of_queries_for_any_number_of_rows() {
+        assert_eq!(statements_for(1), statements_for(25));
     }
 }
@@ -0,0 +1,94 @@
+use chrono::UTC;
+use postgres::Connection;
+use serde::Serialize;
+
+use crate::error::ObsidianError;
+use crate::models::students::LentBook;
+
+const QUERY_OVERDUE_STUDENTS: &str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id,
+copies.id, copies.inventory_number, lendings.returned_at, lendings.return_note, lendings.due_at,
+students.id, students.name, students.graduation_year, students.class_letter
+FROM lendings, copies, books, students
+WHERE lendings.person_type='student' AND lendings.person_id = students.id AND students.school_id=$1
+AND lendings.copy_id = copies.id AND copies.book_id = books.id
+AND lendings.returned_at IS NULL AND lendings.due_at < $2
+ORDER BY students.graduation_year DESC, students.class_letter, students.name, students.id, lendings.due_at";
+const QUERY_OVERDUE_TEACHERS: &str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id,
+copies.id, copies.inventory_number, lendings.returned_at, lendings.return_note, lendings.due_at,
+teachers.id, teachers.name
+FROM lendings, copies, books, teachers
+WHERE lendings.person_type='teacher' AND lendings.person_id = teachers.id AND teachers.school_id=$1
+AND lendings.copy_id = copies.id AND copies.book_id = books.id
+AND lendings.returned_at IS NULL AND lendings.due_at < $2
+ORDER BY teachers.name, teachers.id, lendings.due_at";
+
+#[derive(Serialize, Debug)]
+struct OverduePerson {
+    id: usize,
+    name: String,
+    lent_books: Vec<LentBook>
+}
+
+#[derive(Serialize, Debug)]
+struct OverdueClass {
+    graduation_year: i32,
+    class_letter: String,
+    students: Vec<OverduePerson>
+}
+
+#[derive(Serialize, Debug)]
+pub struct OverdueReport {
+    classes: Vec<OverdueClass>,
+    teachers: Vec<OverduePerson>
+}
+
+fn push_lending(people: &mut Vec<OverduePerson>, id: usize, name: String, lending: LentBook) {
+    if people.last().map(|person| person.id) != Some(id) {
+        people.push(OverduePerson {
+            id,
+            name,
+            lent_books: Vec::new()
+        });
+    }
+    people.last_mut().unwrap().lent_books.push(lending);
+}
+
+impl OverdueReport {
+    pub fn find(school_id: usize, conn: &Connection) -> Result<OverdueReport, ObsidianError> {
+        let now = UTC::now();
+        let mut classes: Vec<OverdueClass> = Vec::new();
+        let stmt = conn.prepare_cached(QUERY_OVERDUE_STUDENTS)?;
+        let rows = stmt.query(&[&(school_id as i32), &now])?;
+        for row in rows.iter() {
+            let id = row.get::<usize, i32>(11) as usize;
+            let name = row.get::<usize, String>(12);
+            let graduation_year = row.get::<usize, i32>(13);
+            let class_letter = row.get::<usize, String>(14);
+            let new_class = classes.last()
+                .map(|class| class.graduation_year != graduation_year || class.class_letter != class_letter)
+                .unwrap_or(true);
+            if new_class {
+                classes.push(OverdueClass {
+                    graduation_year,
+                    class_letter,
+                    students: Vec::new()
+                });
+            }
+            push_lending(&mut classes.last_mut().unwrap().students, id, name, LentBook::from_db(row));
+        }
+
+        let mut teachers = Vec::new();
+        let stmt2 = conn.prepare_cached(QUERY_OVERDUE_TEACHERS)?;
+        let rows = stmt2.query(&[&(school_id as i32), &now])?;
+        for row in rows.iter() {
+            let id = row.get::<usize, i32>(11) as usize;
+            let name = row.get::<usize, String>(12);
+            push_lending(&mut teachers, id, name, LentBook::from_db(row));
+        }
+
+        Ok(OverdueReport {
+            classes,
+            teachers
+        })
+    }
+}
@@ -0,0 +1,188 @@
+use chrono::{Datelike, UTC};
+use postgres::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{FieldError, ObsidianError};
+use crate::models::Validate;
+use crate::models::books::MAX_FORM;
+use crate::models::schools::ValidationRules;
+use crate::models::students::{self, DEFAULT_FINAL_FORM};
+
+const ARCHIVE_GRADUATES: &str = "UPDATE students SET archived_at=$3
+WHERE school_id=$1 AND graduation_year <= $2 AND archived_at IS NULL
+AND NOT EXISTS (SELECT * FROM lendings WHERE lendings.person_type='student' AND lendings.person_id = students.id
+    AND lendings.returned_at IS NULL)
+AND NOT EXISTS (SELECT * FROM base_sets WHERE base_sets.student_id = students.id)
+RETURNING id, name, class_letter, graduation_year";
+const QUERY_RETAINED: &str = "SELECT id, name, class_letter, graduation_year,
+(SELECT count(*) FROM lendings WHERE lendings.person_type='student' AND lendings.person_id = students.id
+    AND lendings.returned_at IS NULL),
+(SELECT count(*) FROM base_sets WHERE base_sets.student_id = students.id)
+FROM students WHERE school_id=$1 AND graduation_year <= $2 AND archived_at IS NULL
+ORDER BY graduation_year, class_letter, name";
+const QUERY_CLASSES: &str = "SELECT graduation_year, class_letter, count(*) FROM students
+WHERE school_id=$1 AND graduation_year > $2 AND archived_at IS NULL
+GROUP BY graduation_year, class_letter ORDER BY graduation_year DESC, class_letter";
+
+/// The school year a rollover on `date` ends by default: the one that just ended from August
+/// on, when rollovers usually run, and the one about to end before.
+fn ending_school_year<D: Datelike>(date: &D) -> i32 {
+    let school_year = students::school_year_of(date);
+    if date.month() >= 8 {
+        school_year - 1
+    } else {
+        school_year
+    }
+}
+
+/// Checks a form and final form given in a request against each other.
+pub fn validate_forms(form: Option<i32>, final_form: Option<i32>) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let final_form = final_form.unwrap_or(DEFAULT_FINAL_FORM);
+    if !(1..=MAX_FORM).contains(&final_form) {
+        errors.push(FieldError::new("final_form", &format!("The final form must be between 1 and {}", MAX_FORM)));
+    }
+    if form.map(|form| form < 1 || form > final_form).unwrap_or(false) {
+        errors.push(FieldError::new("form", "The form must be between 1 and the final form"));
+    }
+    errors
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Rollover {
+    school_year: Option<i32>,
+    final_form: Option<i32>,
+    dry_run: Option<bool>
+}
+
+#[derive(Serialize, Debug)]
+struct Graduate {
+    id: usize,
+    name: String,
+    class_letter: String,
+    graduation_year: i32
+}
+
+#[derive(Serialize, Debug)]
+struct RetainedGraduate {
+    id: usize,
+    name: String,
+    class_letter: String,
+    graduation_year: i32,
+    open_lendings: usize,
+    base_sets: usize
+}
+
+#[derive(Serialize, Debug)]
+struct Class {
+    form: i32,
+    class_letter: String,
+    graduation_year: i32,
+    students: usize
+}
+
+#[derive(Serialize, Debug)]
+pub struct RolloverSummary {
+    school_year: i32,
+    dry_run: bool,
+    archived: Vec<Graduate>,
+    retained: Vec<RetainedGraduate>,
+    classes: Vec<Class>
+}
+
+impl Rollover {
+    /// Ends the school year `school_year` (the calendar year it ends in, by default the one that
+    /// ends or just ended this calendar year): students graduating up to that year are archived
+    /// unless they still hold lent books or base sets, and the remaining students are grouped
+    /// into the forms of the next year.
+    pub fn perform(&self, school_id: usize, conn: &Connection) -> Result<RolloverSummary, ObsidianError> {
+        let now = UTC::now();
+        let school_year = self.school_year.unwrap_or(ending_school_year(&now));
+        let final_form = self.final_form.unwrap_or(DEFAULT_FINAL_FORM);
+        let dry_run = self.dry_run.unwrap_or(false);
+        let trans = conn.transaction()?;
+
+        let stmt = conn.prepare_cached(ARCHIVE_GRADUATES)?;
+        let rows = stmt.query(&[&(school_id as i32), &school_year, &now])?;
+        let archived = rows.iter()
+            .map(|row| Graduate {
+                id: row.get::<usize, i32>(0) as usize,
+                name: row.get(1),
+                class_letter: row.get(2),
+                graduation_year: row.get(3)
+            })
+            .collect::<Vec<Graduate>>();
+
+        let stmt2 = conn.prepare_cached(QUERY_RETAINED)?;
+        let rows = stmt2.query(&[&(school_id as i32), &school_year])?;
+        let retained = rows.iter()
+            .map(|row| RetainedGraduate {
+                id: row.get::<usize, i32>(0) as usize,
+                name: row.get(1),
+                class_letter: row.get(2),
+                graduation_year: row.get(3),
+                open_lendings: row.get::<usize, i64>(4) as usize,
+                base_sets: row.get::<usize, i64>(5) as usize
+            })
+            .collect::<Vec<RetainedGraduate>>();
+
+        let stmt3 = conn.prepare_cached(QUERY_CLASSES)?;
+        let rows = stmt3.query(&[&(school_id as i32), &school_year])?;
+        let classes = rows.iter()
+            .map(|row| {
+                let graduation_year = row.get::<usize, i32>(0);
+                Class {
+                    form: students::form(graduation_year, school_year + 1, final_form),
+                    class_letter: row.get(1),
+                    graduation_year,
+                    students: row.get::<usize, i64>(2) as usize
+                }
+            })
+            .collect::<Vec<Class>>();
+
+        if !dry_run {
+            trans.commit()?;
+        }
+        Ok(RolloverSummary {
+            school_year,
+            dry_run,
+            archived,
+            retained,
+            classes
+        })
+    }
+}
+
+impl Validate for Rollover {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        validate_forms(None, self.final_form)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::{ending_school_year, Rollover};
+
+    fn check(body: &str) -> Vec<String> {
+        invalid_fields::<Rollover>(body, &ValidationRules::default())
+    }
+
+    #[test]
+    fn checks_the_final_form() {
+        assert!(check("{}").is_empty());
+        assert!(check(r#"{"school_year":2027,"final_form":13,"dry_run":true}"#).is_empty());
+        assert_eq!(check(r#"{"final_form":0}"#), vec!["final_form"]);
+        assert_eq!(check(r#"{"final_form":14}"#), vec!["final_form"]);
+    }
+
+    #[test]
+    fn ends_the_school_year_that_just_ended_in_august() {
+        assert_eq!(ending_school_year(&NaiveDate::from_ymd(2027, 7, 20)), 2027);
+        assert_eq!(ending_school_year(&NaiveDate::from_ymd(2027, 8, 25)), 2027);
+        assert_eq!(ending_school_year(&NaiveDate::from_ymd(2027, 9, 10)), 2027);
+        assert_eq!(ending_school_year(&NaiveDate::from_ymd(2028, 1, 15)), 2028);
+    }
+}
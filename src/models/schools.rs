@@ -1,62 +1,141 @@
-use bcrypt::{hash, verify, DEFAULT_COST};
 use postgres::Connection;
+use serde::Deserialize;
 
-use error::ObsidianError;
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{Model, Validate};
+use crate::models::students::current_school_year;
+use crate::models::users::{self, Account, User};
 
-const QUERY_SCHOOLS: &'static str = "SELECT id, encrypted_password FROM schools WHERE name=$1";
-const QUERY_SCHOOL: &'static str = "SELECT encrypted_password FROM schools WHERE id=$1";
+const QUERY_NAME: &str = "SELECT name FROM schools WHERE id=$1";
+const QUERY_LOAN_PERIOD: &str = "SELECT loan_period_days FROM schools WHERE id=$1";
 
-const INSERT_SCHOOL: &'static str = "INSERT INTO schools (name, encrypted_password) VALUES ($1, $2) RETURNING id";
-const UPDATE_NAME: &'static str = "UPDATE schools SET name=$2 WHERE id=$1";
-const UPDATE_PASSWORD: &'static str = "UPDATE schools SET encrypted_password=$2 WHERE id=$1";
-const DELETE_SCHOOL: &'static str = "DELETE FROM schools WHERE id=$1";
+const QUERY_RULES: &str = "SELECT class_letters, graduation_years_back, graduation_years_ahead FROM schools
+WHERE id=$1";
 
-#[derive(RustcDecodable, Debug)]
+const INSERT_SCHOOL: &str = "INSERT INTO schools (name) VALUES ($1) RETURNING id";
+const UPDATE_NAME: &str = "UPDATE schools SET name=$2 WHERE id=$1";
+const UPDATE_LOAN_PERIOD: &str = "UPDATE schools SET loan_period_days=$2 WHERE id=$1";
+const UPDATE_SESSIONS: &str = "UPDATE schools SET session_lifetime_hours=$2, session_idle_minutes=$3
+WHERE id=$1";
+const UPDATE_RULES: &str = "UPDATE schools SET class_letters=$2, graduation_years_back=$3,
+graduation_years_ahead=$4 WHERE id=$1";
+const DELETE_SCHOOL: &str = "DELETE FROM schools WHERE id=$1";
+
+/// The longest loan period a school can set, which keeps due dates within what dates can hold.
+pub const MAX_LOAN_PERIOD_DAYS: u32 = 3650;
+
+pub fn loan_period(id: usize, conn: &Connection) -> Result<i64, ObsidianError> {
+    let stmt = conn.prepare_cached(QUERY_LOAN_PERIOD)?;
+    let rows = stmt.query(&[&(id as i32)])?;
+    let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("School"))?;
+    Ok(row.get::<usize, i32>(0) as i64)
+}
+
+pub fn name(id: usize, conn: &Connection) -> Result<String, ObsidianError> {
+    let stmt = conn.prepare_cached(QUERY_NAME)?;
+    let rows = stmt.query(&[&(id as i32)])?;
+    let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("School"))?;
+    Ok(row.get::<usize, String>(0))
+}
+
+/// The rules of a school that records are validated against.
+#[derive(Debug, Clone)]
+pub struct ValidationRules {
+    /// The letters classes may have; any letter is allowed if empty.
+    pub class_letters: String,
+    pub min_graduation_year: i32,
+    pub max_graduation_year: i32
+}
+
+impl Default for ValidationRules {
+    fn default() -> ValidationRules {
+        ValidationRules::new(String::new(), 1, 13)
+    }
+}
+
+impl ValidationRules {
+    fn new(class_letters: String, years_back: i32, years_ahead: i32) -> ValidationRules {
+        let school_year = current_school_year();
+        ValidationRules {
+            class_letters,
+            min_graduation_year: school_year - years_back,
+            max_graduation_year: school_year + years_ahead
+        }
+    }
+
+    pub fn load(id: usize, conn: &Connection) -> Result<ValidationRules, ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_RULES)?;
+        let rows = stmt.query(&[&(id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("School"))?;
+        Ok(ValidationRules::new(row.get::<usize, String>(0), row.get::<usize, i32>(1), row.get::<usize, i32>(2)))
+    }
+}
+
+/// The credentials of a user, given as the name of their school, their username and
+/// password. Without a username the school's `admin` account is meant.
+#[derive(Deserialize, Debug)]
 pub struct AuthData {
     name: String,
+    username: Option<String>,
     password: String
 }
 
 impl AuthData {
-    pub fn verify(&self, conn: &Connection) -> Result<usize, ObsidianError> {
-        let stmt = try!(conn.prepare_cached(QUERY_SCHOOLS));
-        let rows = try!(stmt.query(&[&self.name.to_lowercase()]));
-        let row = try!(rows.iter().next().ok_or(ObsidianError::RecordNotFound("School")));
-        if verify(&self.password, &row.get::<usize, String>(1)).unwrap_or(false) {
-            Ok(row.get::<usize, i32>(0) as usize)
-        } else {
-            Err(ObsidianError::WrongPassword)
-        }
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub fn save(&self, conn: &Connection) -> Result<usize, ObsidianError> {
-        let encrypted_password = try!(hash(&self.password, DEFAULT_COST));
-        let stmt = try!(conn.prepare_cached(INSERT_SCHOOL));
-        let rows = try!(stmt.query(&[&self.name.to_lowercase(), &encrypted_password]));
-        let row = rows.iter().next().unwrap();
-        Ok(row.get::<usize, i32>(0) as usize)
+    pub fn username(&self) -> &str {
+        self.username.as_ref().map(|username| username.as_ref()).unwrap_or("admin")
+    }
+
+    pub fn verify(&self, conn: &Connection) -> Result<Account, ObsidianError> {
+        users::login(&self.name, self.username(), &self.password, conn)
+    }
+
+    /// Creates the school together with its first admin account.
+    pub fn save(&self, conn: &Connection) -> Result<Account, ObsidianError> {
+        let trans = conn.transaction()?;
+        let stmt = conn.prepare_cached(INSERT_SCHOOL)?;
+        let rows = stmt.query(&[&self.name.to_lowercase()])?;
+        let school_id = rows.iter().next().unwrap().get::<usize, i32>(0) as usize;
+        let user = User::admin(self.username(), &self.password).save(None, school_id, conn)?;
+        trans.commit()?;
+        Ok(Account {
+            id: user.id().unwrap(),
+            school_id,
+            role: user.role(),
+            session_id: None
+        })
     }
 }
 
-#[derive(RustcDecodable, Debug)]
-pub struct PasswordChange {
-    old_password: String,
-    new_password: String
+#[derive(Deserialize, Debug)]
+pub struct NameChange {
+    name: String
 }
 
-impl PasswordChange {
+impl NameChange {
     pub fn perform(&self, id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(QUERY_SCHOOL));
-        let rows = try!(stmt.query(&[&(id as i32)]));
-        let row = try!(rows.iter().next().ok_or(ObsidianError::RecordNotFound("School")));
-        try!(if verify(&self.old_password, &row.get::<usize, String>(0)).unwrap_or(false) {
+        let stmt = conn.prepare_cached(UPDATE_NAME)?;
+        let modified = stmt.execute(&[&(id as i32), &self.name])?;
+        if modified == 1 {
             Ok(())
         } else {
-            Err(ObsidianError::WrongPassword)
-        });
-        let encrypted_password = try!(hash(&self.new_password, DEFAULT_COST));
-        let stmt2 = try!(conn.prepare_cached(UPDATE_PASSWORD));
-        let modified = try!(stmt2.execute(&[&(id as i32), &encrypted_password]));
+            Err(ObsidianError::RecordNotFound("School"))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoanPeriodChange {
+    loan_period_days: u32
+}
+
+impl LoanPeriodChange {
+    pub fn perform(&self, id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        let stmt = conn.prepare_cached(UPDATE_LOAN_PERIOD)?;
+        let modified = stmt.execute(&[&(id as i32), &(self.loan_period_days as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -65,15 +144,18 @@ impl PasswordChange {
     }
 }
 
-#[derive(RustcDecodable, Debug)]
-pub struct NameChange {
-    name: String
+#[derive(Deserialize, Debug)]
+pub struct RulesChange {
+    class_letters: String,
+    graduation_years_back: u32,
+    graduation_years_ahead: u32
 }
 
-impl NameChange {
+impl RulesChange {
     pub fn perform(&self, id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(UPDATE_NAME));
-        let modified = try!(stmt.execute(&[&(id as i32), &self.name]));
+        let stmt = conn.prepare_cached(UPDATE_RULES)?;
+        let modified = stmt.execute(&[&(id as i32), &self.class_letters.to_lowercase(),
+            &(self.graduation_years_back as i32), &(self.graduation_years_ahead as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -82,23 +164,38 @@ impl NameChange {
     }
 }
 
-#[derive(RustcDecodable, Debug)]
-pub struct Deletion {
-    password: String
+/// How long sessions last: at most `session_lifetime_hours` after logging in and, if set,
+/// `session_idle_minutes` after their last request.
+#[derive(Deserialize, Debug)]
+pub struct SessionChange {
+    session_lifetime_hours: u32,
+    session_idle_minutes: Option<u32>
 }
 
-impl Deletion {
+impl SessionChange {
     pub fn perform(&self, id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(QUERY_SCHOOL));
-        let rows = try!(stmt.query(&[&(id as i32)]));
-        let row = try!(rows.iter().next().ok_or(ObsidianError::RecordNotFound("School")));
-        try!(if verify(&self.password, &row.get::<usize, String>(0)).unwrap_or(false) {
+        let stmt = conn.prepare_cached(UPDATE_SESSIONS)?;
+        let modified = stmt.execute(&[&(id as i32), &(self.session_lifetime_hours as i32),
+            &self.session_idle_minutes.map(|minutes| minutes as i32)])?;
+        if modified == 1 {
             Ok(())
         } else {
-            Err(ObsidianError::WrongPassword)
-        });
-        let stmt2 = try!(conn.prepare_cached(DELETE_SCHOOL));
-        let modified = try!(stmt2.execute(&[&(id as i32)]));
+            Err(ObsidianError::RecordNotFound("School"))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Deletion {
+    password: String
+}
+
+impl Deletion {
+    /// Deletes the school `id` after checking the password of the admin `user_id`.
+    pub fn perform(&self, id: usize, user_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        users::check_password(user_id, &self.password, conn)?;
+        let stmt = conn.prepare_cached(DELETE_SCHOOL)?;
+        let modified = stmt.execute(&[&(id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -106,3 +203,104 @@ impl Deletion {
         }
     }
 }
+
+impl Validate for AuthData {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push(FieldError::new("name", "The name must not be empty"));
+        }
+        if self.username.as_ref().map(|username| username.trim().is_empty()).unwrap_or(false) {
+            errors.push(FieldError::new("username", "The username must not be empty"));
+        }
+        if self.password.is_empty() {
+            errors.push(FieldError::new("password", "The password must not be empty"));
+        }
+        errors
+    }
+}
+
+impl Validate for NameChange {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.name.trim().is_empty() {
+            vec![FieldError::new("name", "The name must not be empty")]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Validate for LoanPeriodChange {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.loan_period_days == 0 {
+            vec![FieldError::new("loan_period_days", "The loan period must be at least one day")]
+        } else if self.loan_period_days > MAX_LOAN_PERIOD_DAYS {
+            vec![FieldError::new("loan_period_days", "The loan period must not exceed ten years")]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Validate for RulesChange {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.class_letters.chars().all(char::is_alphabetic) {
+            Vec::new()
+        } else {
+            vec![FieldError::new("class_letters", "Only letters can be allowed for classes")]
+        }
+    }
+}
+
+impl Validate for SessionChange {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.session_lifetime_hours == 0 {
+            errors.push(FieldError::new("session_lifetime_hours", "Sessions must last at least one hour"));
+        }
+        if self.session_idle_minutes == Some(0) {
+            errors.push(FieldError::new("session_idle_minutes", "The idle timeout must be at least one minute"));
+        }
+        errors
+    }
+}
+
+impl Validate for Deletion {}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use super::{AuthData, LoanPeriodChange, NameChange, RulesChange, SessionChange, ValidationRules};
+
+    #[test]
+    fn checks_new_schools() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<AuthData>(r#"{"name":"Michaeli-Gymnasium","password":"test1234"}"#, &rules)
+            .is_empty());
+        assert_eq!(invalid_fields::<AuthData>(r#"{"name":" ","username":" ","password":""}"#, &rules),
+            vec!["name", "username", "password"]);
+    }
+
+    #[test]
+    fn checks_changes() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<NameChange>(r#"{"name":"Michaeli-Gymnasium"}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<NameChange>(r#"{"name":""}"#, &rules), vec!["name"]);
+        assert!(invalid_fields::<LoanPeriodChange>(r#"{"loan_period_days":1}"#, &rules).is_empty());
+        assert!(invalid_fields::<LoanPeriodChange>(r#"{"loan_period_days":3650}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<LoanPeriodChange>(r#"{"loan_period_days":0}"#, &rules), vec!["loan_period_days"]);
+        assert_eq!(invalid_fields::<LoanPeriodChange>(r#"{"loan_period_days":3651}"#, &rules),
+            vec!["loan_period_days"]);
+        assert!(invalid_fields::<RulesChange>(
+            r#"{"class_letters":"","graduation_years_back":0,"graduation_years_ahead":0}"#, &rules).is_empty());
+        assert!(invalid_fields::<RulesChange>(
+            r#"{"class_letters":"abcd","graduation_years_back":1,"graduation_years_ahead":13}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<RulesChange>(
+            r#"{"class_letters":"a,b","graduation_years_back":1,"graduation_years_ahead":13}"#, &rules),
+            vec!["class_letters"]);
+        assert!(invalid_fields::<SessionChange>(r#"{"session_lifetime_hours":1,"session_idle_minutes":1}"#, &rules)
+            .is_empty());
+        assert_eq!(invalid_fields::<SessionChange>(r#"{"session_lifetime_hours":0,"session_idle_minutes":0}"#,
+            &rules), vec!["session_lifetime_hours", "session_idle_minutes"]);
+    }
+}
@@ -0,0 +1,66 @@
+use postgres::Connection;
+
+use crate::error::ObsidianError;
+use crate::models::books::Book;
+use crate::models::lookup::Hit;
+use crate::models::students::Student;
+use crate::models::teachers::Teacher;
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+const QUERY_SEARCH: &str = "SELECT kind, id, max(rank) AS rank FROM (
+    SELECT CAST('student' AS TEXT) AS kind, id, similarity(name, $2) AS rank FROM students
+    WHERE school_id = $1 AND archived_at IS NULL AND (name % $2 OR name ILIKE $3)
+    UNION ALL
+    SELECT CAST('teacher' AS TEXT), id, similarity(name, $2) FROM teachers
+    WHERE school_id = $1 AND (name % $2 OR name ILIKE $3)
+    UNION ALL
+    SELECT CAST('book' AS TEXT), id, similarity(title, $2) FROM books
+    WHERE school_id = $1 AND (title % $2 OR title ILIKE $3)
+    UNION ALL
+    SELECT CAST('book' AS TEXT), books.id, similarity(aliases.name, $2) FROM aliases, books
+    WHERE aliases.book_id = books.id AND books.school_id = $1 AND (aliases.name % $2 OR aliases.name ILIKE $3)
+) AS matches GROUP BY kind, id ORDER BY rank DESC, kind, id LIMIT $4";
+
+/// Escapes the wildcards of a LIKE pattern and matches `term` anywhere in the text.
+fn contains_pattern(term: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in term.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Searches the names of students and teachers, the titles of books and the names of
+/// aliases for `term`, allowing for typos. Hits are ordered by their similarity to `term`;
+/// a book found by its title and one of its aliases is only listed once.
+pub fn search(term: &str, limit: usize, school_id: usize, conn: &Connection) -> Result<Vec<Hit>, ObsidianError> {
+    let term = term.trim();
+    let stmt = conn.prepare_cached(QUERY_SEARCH)?;
+    let rows = stmt.query(&[&(school_id as i32), &term, &contains_pattern(term), &(limit as i64)])?;
+    let matches = rows.iter()
+        .map(|row| (row.get::<usize, String>(0), row.get::<usize, i32>(1) as usize))
+        .collect::<Vec<(String, usize)>>();
+
+    let ids_of = |kind: &str| matches.iter()
+        .filter(|&(other, _)| other == kind)
+        .map(|&(_, id)| id)
+        .collect::<Vec<usize>>();
+    let mut students = Student::find_ids(&ids_of("student"), school_id, conn)?;
+    let mut teachers = Teacher::find_ids(&ids_of("teacher"), school_id, conn)?;
+    let mut books = Book::find_ids(&ids_of("book"), school_id, conn)?;
+
+    let hits = matches.iter()
+        .filter_map(|&(ref kind, id)| match kind.as_ref() {
+            "student" => students.remove(&id).map(Hit::Student),
+            "teacher" => teachers.remove(&id).map(Hit::Teacher),
+            _ => books.remove(&id).map(Hit::Book)
+        })
+        .collect::<Vec<Hit>>();
+    Ok(hits)
+}
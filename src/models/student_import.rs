@@ -0,0 +1,164 @@
+use postgres::Connection;
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::error::{ObsidianError, ReqError};
+use crate::models::{csv, Model, Validate};
+use crate::models::schools::ValidationRules;
+use crate::models::students::Student;
+
+#[derive(Debug)]
+pub struct ColumnMapping {
+    pub name: String,
+    pub class_letter: String,
+    pub graduation_year: String,
+    pub external_id: String
+}
+
+impl Default for ColumnMapping {
+    fn default() -> ColumnMapping {
+        ColumnMapping {
+            name: "name".to_string(),
+            class_letter: "class_letter".to_string(),
+            graduation_year: "graduation_year".to_string(),
+            external_id: "external_id".to_string()
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ImportRow {
+    /// The line of the file the row starts on.
+    row: usize,
+    action: &'static str,
+    student: Option<Student>,
+    error: Option<String>
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportReport {
+    dry_run: bool,
+    created: usize,
+    updated: usize,
+    rejected: usize,
+    rows: Vec<ImportRow>
+}
+
+fn find_column(header: &[String], name: &str) -> Option<usize> {
+    header.iter().position(|column| column.trim().to_lowercase() == name.trim().to_lowercase())
+}
+
+fn required_column(header: &[String], name: &str) -> Result<usize, ObsidianError> {
+    find_column(header, name).ok_or_else(|| ObsidianError::from(ReqError::MissingColumn(name.to_string())))
+}
+
+impl ImportReport {
+    /// Creates or updates a student for every row of a CSV file. All rows are processed in a
+    /// single transaction, which is rolled back if `dry_run` is set.
+    pub fn import(body: &str, mapping: &ColumnMapping, delimiter: char, dry_run: bool, school_id: usize,
+        conn: &Connection) -> Result<ImportReport, ObsidianError> {
+        let records = csv::parse(body.trim_start_matches('\u{feff}'), delimiter);
+        let header = records.first()
+            .map(|(_, header)| header)
+            .ok_or_else(|| ObsidianError::from(ReqError::MissingColumn(mapping.name.clone())))?;
+        let name_column = required_column(header, &mapping.name)?;
+        let class_letter_column = required_column(header, &mapping.class_letter)?;
+        let graduation_year_column = required_column(header, &mapping.graduation_year)?;
+        let external_id_column = find_column(header, &mapping.external_id);
+        let rules = ValidationRules::load(school_id, conn)?;
+
+        let mut report = ImportReport {
+            dry_run,
+            created: 0,
+            updated: 0,
+            rejected: 0,
+            rows: Vec::new()
+        };
+        let trans = conn.transaction()?;
+        for &(line, ref record) in records.iter().skip(1) {
+            let field = |column: usize| record.get(column).map(|value| value.trim()).unwrap_or("");
+            let external_id = external_id_column
+                .map(|column| field(column).to_string())
+                .and_then(|id| if id.is_empty() { None } else { Some(id) });
+            let student = match i32::from_str(field(graduation_year_column)) {
+                _ if field(name_column).is_empty() => Err("The name is missing".to_string()),
+                Ok(graduation_year) => Ok(Student::new(field(name_column).to_string(),
+                    field(class_letter_column).to_string(), graduation_year, external_id)),
+                Err(_) => Err("The graduation year is not a number".to_string())
+            }.and_then(|student| {
+                let errors = student.validate(&rules);
+                if errors.is_empty() {
+                    Ok(student)
+                } else {
+                    Err(errors.into_iter().map(|error| error.message).collect::<Vec<String>>().join("; "))
+                }
+            });
+            let matches = match student {
+                Ok(ref student) => student.find_matches(school_id, conn)?,
+                Err(_) => Vec::new()
+            };
+            let (action, student, error) = match student {
+                Err(error) => ("reject", None, Some(error)),
+                Ok(_) if matches.len() > 1 =>
+                    ("reject", None, Some("The row matches more than one student".to_string())),
+                Ok(student) => match matches.first() {
+                    Some(&id) => ("update", Some(student.save(Some(id), school_id, conn)?), None),
+                    None => ("create", Some(student.save(None, school_id, conn)?), None)
+                }
+            };
+            match action {
+                "create" => report.created += 1,
+                "update" => report.updated += 1,
+                _ => report.rejected += 1
+            }
+            report.rows.push(ImportRow {
+                row: line,
+                action,
+                student,
+                error
+            });
+        }
+        if !dry_run {
+            trans.commit()?;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres::{Connection, TlsMode};
+    use std::env;
+
+    use crate::migrations;
+    use crate::models::students::current_school_year;
+    use super::{ColumnMapping, ImportReport};
+
+    /// Needs a scratch database at `TEST_DATABASE_URL`; run it with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn links_students_entered_without_an_external_id() {
+        let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a scratch database");
+        let conn = Connection::connect(&*url, TlsMode::None).unwrap();
+        migrations::up(&conn).unwrap();
+        let school_id: i32 = conn.query("INSERT INTO schools (name) VALUES ('import ' || md5(random()::text))
+            RETURNING id", &[]).unwrap().get(0).get(0);
+        let graduation_year = current_school_year() + 1;
+        let student_id: i32 = conn.query("INSERT INTO students (name, class_letter, graduation_year, school_id)
+            VALUES ('Jael Veen', 'a', $1, $2) RETURNING id", &[&graduation_year, &school_id]).unwrap().get(0).get(0);
+
+        let body = format!("name,class_letter,graduation_year,external_id\njael veen,b,{},4711\n", graduation_year);
+        let report = ImportReport::import(&body, &ColumnMapping::default(), ',', false, school_id as usize, &conn);
+        let students = conn.query("SELECT id, class_letter, external_id FROM students WHERE school_id=$1",
+            &[&school_id]).unwrap();
+        conn.execute("DELETE FROM schools WHERE id=$1", &[&school_id]).unwrap();
+
+        let report = report.unwrap();
+        assert_eq!((report.created, report.updated, report.rejected), (0, 1, 0));
+        assert_eq!(students.len(), 1);
+        let student = students.get(0);
+        assert_eq!(student.get::<usize, i32>(0), student_id);
+        assert_eq!(student.get::<usize, String>(1), "b");
+        assert_eq!(student.get::<usize, Option<String>>(2), Some("4711".to_string()));
+    }
+}
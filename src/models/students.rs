@@ -1,142 +1,334 @@
-use rustc_serialize::{Decodable, Decoder};
 use postgres::Connection;
 use postgres::rows::Row;
-use chrono::{DateTime, UTC};
+use chrono::{Datelike, DateTime, UTC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-use error::ObsidianError;
-use models::{Includable, Includes, Model};
-use models::books::Book;
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{load_related, Columns, Includable, Includes, Model, Page, Query, Validate};
+use crate::models::books::Book;
+use crate::models::csv::ToCsv;
+use crate::models::schools::ValidationRules;
 
-#[derive(RustcEncodable, Debug)]
-struct LentBook {
+#[derive(Serialize, Debug, Clone)]
+pub struct LentBook {
     id: usize,
     created_at: String,
+    due_at: Option<String>,
+    returned_at: Option<String>,
+    return_note: Option<String>,
+    copy_id: usize,
+    inventory_number: String,
     book: Book,
 }
 
-const QUERY_STUDENT: &'static str = "SELECT id, name, class_letter, graduation_year FROM students
-WHERE id = $1, school_id=$2";
-const QUERY_STUDENTS: &'static str = "SELECT id, name, class_letter, graduation_year FROM students
-WHERE school_id=$1";
-const QUERY_LENDINGS: &'static str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id FROM lendings, books
-    WHERE lendings.person_id=$1 AND lendings.person_type='student' AND lendings.book_id = books.id";
-const QUERY_BASE_SETS: &'static str = "SELECT title, form, isbn, base_sets.created_at, books.id, base_sets.id FROM base_sets, books
-    WHERE base_sets.student_id=$1 AND base_sets.book_id = books.id";
-
-const INSERT_STUDENT: &'static str = "INSERT INTO students (name, graduation_year, class_letter, school_id)
-    VALUES ($1, $2, $3, $4) RETURNING id";
-const UPDATE_STUDENT: &'static str = "UPDATE students SET name=$2, graduation_year=$3,
-    class_letter=$4 WHERE id=$1 AND school_id=$5";
-const DELETE_STUDENT: &'static str = "DELETE FROM students WHERE id=$1 AND school_id=$2";
-
-#[derive(RustcEncodable, Debug)]
+impl LentBook {
+    fn csv_fields(&self, kind: &str) -> Vec<String> {
+        let mut fields = vec![kind.to_string(), self.inventory_number.clone()];
+        fields.extend(self.book.csv_fields());
+        fields.push(self.created_at.clone());
+        fields.push(self.due_at.clone().unwrap_or_default());
+        fields.push(self.returned_at.clone().unwrap_or_default());
+        fields
+    }
+
+    pub fn from_db(row: Row) -> LentBook {
+        LentBook {
+            id: row.get::<usize, i32>(5) as usize,
+            created_at: row.get::<usize, DateTime<UTC>>(3).to_rfc3339(),
+            due_at: row.get::<usize, Option<DateTime<UTC>>>(10).map(|date| date.to_rfc3339()),
+            returned_at: row.get::<usize, Option<DateTime<UTC>>>(8).map(|date| date.to_rfc3339()),
+            return_note: row.get::<usize, Option<String>>(9),
+            copy_id: row.get::<usize, i32>(6) as usize,
+            inventory_number: row.get::<usize, String>(7),
+            book: Book::new(Some(row.get::<usize, i32>(4) as usize), row.get::<usize, String>(2),
+                row.get::<usize, String>(0), row.get::<usize, Vec<i32>>(1))
+        }
+    }
+}
+
+pub const DEFAULT_FINAL_FORM: i32 = 12;
+
+/// The calendar year the school year of `date` ends in. School years start in August.
+pub fn school_year_of<D: Datelike>(date: &D) -> i32 {
+    if date.month() >= 8 {
+        date.year() + 1
+    } else {
+        date.year()
+    }
+}
+
+/// The calendar year the current school year ends in.
+pub fn current_school_year() -> i32 {
+    school_year_of(&UTC::now())
+}
+
+/// The form (Jahrgangsstufe) attended in the school year ending in `school_year` by a
+/// student graduating from `final_form` in `graduation_year`.
+pub fn form(graduation_year: i32, school_year: i32, final_form: i32) -> i32 {
+    final_form - (graduation_year - school_year)
+}
+
+const QUERY_STUDENT: &str = "SELECT id, name, class_letter, graduation_year, external_id FROM students
+WHERE id = $1 AND school_id=$2";
+const QUERY_STUDENTS_BY_ID: &str = "SELECT id, name, class_letter, graduation_year, external_id FROM students
+WHERE id = ANY($1) AND school_id=$2";
+const QUERY_STUDENTS: &str = "SELECT id, name, class_letter, graduation_year, external_id FROM students
+WHERE school_id=$1 AND archived_at IS NULL";
+const QUERY_BY_EXTERNAL_ID: &str = "SELECT id FROM students WHERE school_id=$1 AND external_id=$2";
+const QUERY_BY_NAME: &str = "SELECT id FROM students WHERE school_id=$1 AND lower(name)=lower($2)
+AND graduation_year=$3";
+/// Students entered before they had an external id, which a record with one may refer to.
+const QUERY_UNLINKED_BY_NAME: &str = "SELECT id FROM students WHERE school_id=$1 AND lower(name)=lower($2)
+AND graduation_year=$3 AND external_id IS NULL";
+const STUDENT_COLUMNS: Columns = &[("id", "id"), ("name", "name"), ("class_letter", "class_letter"),
+    ("graduation_year", "graduation_year"), ("external_id", "external_id")];
+const QUERY_LENDINGS: &str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id, copies.id,
+    copies.inventory_number, lendings.returned_at, lendings.return_note, lendings.due_at, lendings.person_id
+    FROM lendings, copies, books
+    WHERE lendings.person_id = ANY($1) AND lendings.person_type='student' AND lendings.copy_id = copies.id
+    AND copies.book_id = books.id AND lendings.returned_at IS NULL ORDER BY lendings.created_at, lendings.id";
+const QUERY_LENDING_HISTORY: &str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id, copies.id,
+    copies.inventory_number, lendings.returned_at, lendings.return_note, lendings.due_at, lendings.person_id
+    FROM lendings, copies, books
+    WHERE lendings.person_id = ANY($1) AND lendings.person_type='student' AND lendings.copy_id = copies.id
+    AND copies.book_id = books.id AND lendings.returned_at IS NOT NULL ORDER BY lendings.returned_at DESC";
+const QUERY_BASE_SETS: &str = "SELECT title, form, isbn, base_sets.created_at, books.id, base_sets.id, copies.id,
+    copies.inventory_number, NULL::timestamptz, NULL::text, NULL::timestamptz, base_sets.student_id
+    FROM base_sets, copies, books
+    WHERE base_sets.student_id = ANY($1) AND base_sets.copy_id = copies.id AND copies.book_id = books.id
+    ORDER BY base_sets.created_at, base_sets.id";
+
+const INSERT_STUDENT: &str = "INSERT INTO students (name, graduation_year, class_letter, school_id, external_id)
+    VALUES ($1, $2, $3, $4, NULLIF($5, '')) RETURNING id, external_id";
+const UPDATE_STUDENT: &str = "UPDATE students SET name=$2, graduation_year=$3,
+    class_letter=$4, external_id=NULLIF(COALESCE($6, external_id), '') WHERE id=$1 AND school_id=$5
+    RETURNING external_id";
+const DELETE_STUDENT: &str = "DELETE FROM students WHERE id=$1 AND school_id=$2";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Student {
+    #[serde(default)]
     id: Option<usize>,
     name: String,
     class_letter: String,
     graduation_year: i32,
+    #[serde(default)]
+    external_id: Option<String>,
+    #[serde(skip_deserializing)]
     lent_books: Option<Vec<LentBook>>,
-    base_sets: Option<Vec<LentBook>>
+    #[serde(skip_deserializing)]
+    base_sets: Option<Vec<LentBook>>,
+    #[serde(skip_deserializing)]
+    lending_history: Option<Vec<LentBook>>
 }
 
 impl Student {
-    fn find_base_sets(student_id: usize, conn: &Connection) -> Result<Vec<LentBook>, ObsidianError> {
-        let stmt = try!(conn.prepare_cached(QUERY_BASE_SETS));
-        let rows = try!(stmt.query(&[&(student_id as i32)]));
-        Ok(rows.iter()
-            .map(|row| LentBook {
-                id: row.get::<usize, i32>(5) as usize,
-                created_at: row.get::<usize, DateTime<UTC>>(3).to_rfc3339(),
-                book: Book::new(Some(row.get::<usize, i32>(4) as usize), row.get::<usize, String>(2),
-                    row.get::<usize, String>(0), row.get::<usize, String>(1))
-            })
-            .collect::<Vec<LentBook>>())
-    }
-
-    fn find_lendings(student_id: usize, conn: &Connection) -> Result<Vec<LentBook>, ObsidianError> {
-        let stmt = try!(conn.prepare_cached(QUERY_LENDINGS));
-        let rows = try!(stmt.query(&[&(student_id as i32)]));
-        Ok(rows.iter()
-            .map(|row| LentBook {
-                id: row.get::<usize, i32>(5) as usize,
-                created_at: row.get::<usize, DateTime<UTC>>(3).to_rfc3339(),
-                book: Book::new(Some(row.get::<usize, i32>(4) as usize), row.get::<usize, String>(2),
-                    row.get::<usize, String>(0), row.get::<usize, String>(1))
-            })
-            .collect::<Vec<LentBook>>())
+    pub fn new(name: String, class_letter: String, graduation_year: i32, external_id: Option<String>) -> Student {
+        Student {
+            id: None,
+            name,
+            class_letter,
+            graduation_year,
+            external_id,
+            lent_books: None,
+            base_sets: None,
+            lending_history: None
+        }
     }
 
-    fn from_db(conn: &Connection, includes: &Includes, row: Row) -> Result<Student, ObsidianError> {
-        let id = row.get::<usize, i32>(0) as usize;
-        let base_sets = if includes.contains(&Includable::BaseSetBooks) {
-            Some(try!(Student::find_base_sets(id, conn)))
-        } else {
-            None
-        };
-        let lendings = if includes.contains(&Includable::LentBooks) {
-            Some(try!(Student::find_lendings(id, conn)))
+    /// Looks up the ids of the students a record may refer to, by its external id if it has
+    /// one and by name and graduation year otherwise. A record whose external id is not known
+    /// yet falls back to the students of its name and graduation year that have none.
+    pub fn find_matches(&self, school_id: usize, conn: &Connection) -> Result<Vec<usize>, ObsidianError> {
+        if let Some(ref external_id) = self.external_id {
+            let stmt = conn.prepare_cached(QUERY_BY_EXTERNAL_ID)?;
+            let rows = stmt.query(&[&(school_id as i32), external_id])?;
+            let ids = rows.iter().map(|row| row.get::<usize, i32>(0) as usize).collect::<Vec<usize>>();
+            if !ids.is_empty() {
+                return Ok(ids);
+            }
+        }
+        let stmt = if self.external_id.is_some() {
+            conn.prepare_cached(QUERY_UNLINKED_BY_NAME)?
         } else {
-            None
+            conn.prepare_cached(QUERY_BY_NAME)?
         };
+        let rows = stmt.query(&[&(school_id as i32), &self.name, &self.graduation_year])?;
+        let ids = rows.iter().map(|row| row.get::<usize, i32>(0) as usize).collect::<Vec<usize>>();
+        Ok(ids)
+    }
 
-        Ok(Student{
-            id: Some(id),
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn from_db(row: Row) -> Student {
+        Student{
+            id: Some(row.get::<usize, i32>(0) as usize),
             name: row.get(1),
             class_letter: row.get(2),
             graduation_year: row.get(3),
-            lent_books: lendings,
-            base_sets: base_sets
-        })
+            external_id: row.get(4),
+            lent_books: None,
+            base_sets: None,
+            lending_history: None
+        }
+    }
+
+    fn load_includes(students: &mut [Student], conn: &Connection, includes: &Includes) -> Result<(), ObsidianError> {
+        let ids = students.iter().filter_map(|student| student.id).collect::<Vec<usize>>();
+        if includes.contains(&Includable::BaseSetBooks) {
+            let mut base_sets = load_related(QUERY_BASE_SETS, &ids, 11, conn, LentBook::from_db)?;
+            for student in students.iter_mut() {
+                student.base_sets = Some(student.id.and_then(|id| base_sets.remove(&id)).unwrap_or(Vec::new()));
+            }
+        }
+        if includes.contains(&Includable::LentBooks) {
+            let mut lendings = load_related(QUERY_LENDINGS, &ids, 11, conn, LentBook::from_db)?;
+            for student in students.iter_mut() {
+                student.lent_books = Some(student.id.and_then(|id| lendings.remove(&id)).unwrap_or(Vec::new()));
+            }
+        }
+        if includes.contains(&Includable::LendingHistory) {
+            let mut history = load_related(QUERY_LENDING_HISTORY, &ids, 11, conn, LentBook::from_db)?;
+            for student in students.iter_mut() {
+                student.lending_history = Some(student.id.and_then(|id| history.remove(&id)).unwrap_or(Vec::new()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find_ids(ids: &[usize], school_id: usize, conn: &Connection)
+        -> Result<HashMap<usize, Student>, ObsidianError> {
+        let ids = ids.iter().map(|&id| id as i32).collect::<Vec<i32>>();
+        let stmt = conn.prepare_cached(QUERY_STUDENTS_BY_ID)?;
+        let rows = stmt.query(&[&ids, &(school_id as i32)])?;
+        Ok(rows.iter()
+            .map(|row| (row.get::<usize, i32>(0) as usize, Student::from_db(row)))
+            .collect())
+    }
+}
+
+impl ToCsv for Student {
+    fn csv_header(includes: &Includes) -> Vec<&'static str> {
+        let mut header = vec!["id", "name", "class_letter", "graduation_year", "external_id"];
+        if includes.contains(&Includable::LentBooks) || includes.contains(&Includable::BaseSetBooks) ||
+            includes.contains(&Includable::LendingHistory) {
+            header.extend_from_slice(&["kind", "inventory_number", "isbn", "title", "form", "created_at", "due_at",
+                "returned_at"]);
+        }
+        header
+    }
+
+    fn csv_rows(&self, includes: &Includes) -> Vec<Vec<String>> {
+        let student = vec![self.id.map(|id| id.to_string()).unwrap_or_default(), self.name.clone(),
+            self.class_letter.clone(), self.graduation_year.to_string(),
+            self.external_id.clone().unwrap_or_default()];
+        let books = [(Includable::LentBooks, "lending", &self.lent_books),
+            (Includable::BaseSetBooks, "base_set", &self.base_sets),
+            (Includable::LendingHistory, "returned", &self.lending_history)];
+        if books.iter().all(|&(includable, _, _)| !includes.contains(&includable)) {
+            return vec![student];
+        }
+        let mut rows = books.iter()
+            .filter(|&&(includable, _, _)| includes.contains(&includable))
+            .filter_map(|&(_, kind, lent_books)| lent_books.as_ref().map(|lent_books| (kind, lent_books)))
+            .flat_map(|(kind, lent_books)| lent_books.iter().map(move |lent_book| (kind, lent_book)))
+            .map(|(kind, lent_book)| {
+                let mut row = student.clone();
+                row.extend(lent_book.csv_fields(kind));
+                row
+            })
+            .collect::<Vec<Vec<String>>>();
+        if rows.is_empty() {
+            let mut row = student.clone();
+            row.extend(vec![String::new(); 8]);
+            rows.push(row);
+        }
+        rows
+    }
+}
+
+impl Validate for Student {
+    fn validate(&self, rules: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push(FieldError::new("name", "The name must not be empty"));
+        }
+        let class_letter = self.class_letter.to_lowercase();
+        if class_letter.chars().count() > 1 || !class_letter.chars().all(char::is_alphabetic) {
+            errors.push(FieldError::new("class_letter", "The class letter must be a single letter"));
+        } else if !class_letter.is_empty() && !rules.class_letters.is_empty()
+            && !rules.class_letters.contains(&class_letter[..]) {
+            errors.push(FieldError::new("class_letter",
+                &format!("The class letter must be one of {}", rules.class_letters)));
+        }
+        if self.graduation_year < rules.min_graduation_year || self.graduation_year > rules.max_graduation_year {
+            errors.push(FieldError::new("graduation_year", &format!("The graduation year must be between {} and {}",
+                rules.min_graduation_year, rules.max_graduation_year)));
+        }
+        // An empty external id removes it, one of whitespace only is most likely a mistake.
+        if self.external_id.as_ref().map(|id| !id.is_empty() && id.trim().is_empty()).unwrap_or(false) {
+            errors.push(FieldError::new("external_id", "The external id must not be blank"));
+        }
+        errors
     }
 }
 
 impl Model for Student {
     fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Student, ObsidianError> {
         does_not_support!(Aliases, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_STUDENT));
-        let rows = try!(stmt.query(&[&(id as i32), &(school_id as i32)]));
-        let row = try!(rows.iter().next().ok_or(ObsidianError::RecordNotFound("Student")));
-        let student = try!(Student::from_db(conn, includes, row));
-        if student.id == Some(id) {
-            Ok(student)
-        } else {
-            Err(ObsidianError::RecordNotFound("Student"))
-        }
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let stmt = conn.prepare_cached(QUERY_STUDENT)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Student"))?;
+        let mut students = vec![Student::from_db(row)];
+        Student::load_includes(&mut students, conn, includes)?;
+        Ok(students.pop().unwrap())
     }
 
     fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Student>, ObsidianError> {
+        Student::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
+    }
+
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Student>, ObsidianError> {
         does_not_support!(Aliases, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_STUDENTS));
-        let rows = try!(stmt.query(&[&(school_id as i32)]));
-        rows.iter()
-            .map(|row| Student::from_db(conn, includes, row))
-            .collect::<Result<Vec<Student>, ObsidianError>>()
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let mut page = query.execute(QUERY_STUDENTS, STUDENT_COLUMNS, school_id, conn,
+            |row| Ok(Student::from_db(row)))?;
+        Student::load_includes(&mut page.items, conn, includes)?;
+        Ok(page)
     }
 
     fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
         if let Some(id) = id {
-            let stmt = try!(conn.prepare_cached(UPDATE_STUDENT));
-            let modified = try!(stmt.execute(&[&(id as i32), &self.name, &self.graduation_year,
-                &self.class_letter, &(school_id as i32)]));
-            if modified == 1 {
-                self.id = Some(id);
-                Ok(self)
-            } else {
-                Err(ObsidianError::RecordNotFound("Student"))
-            }
+            let stmt = conn.prepare_cached(UPDATE_STUDENT)?;
+            let rows = stmt.query(&[&(id as i32), &self.name, &self.graduation_year,
+                &self.class_letter, &(school_id as i32), &self.external_id])?;
+            let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Student"))?;
+            self.id = Some(id);
+            self.external_id = row.get(0);
+            Ok(self)
         } else {
-            let stmt = try!(conn.prepare_cached(INSERT_STUDENT));
-            let rows = try!(stmt.query(&[&self.name, &self.graduation_year, &self.class_letter,
-                &(school_id as i32)]));
+            let stmt = conn.prepare_cached(INSERT_STUDENT)?;
+            let rows = stmt.query(&[&self.name, &self.graduation_year, &self.class_letter,
+                &(school_id as i32), &self.external_id])?;
             let row = rows.iter().next().unwrap();
             self.id = Some(row.get::<usize, i32>(0) as usize);
+            self.external_id = row.get(1);
             Ok(self)
         }
     }
 
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(DELETE_STUDENT));
-        let modified = try!(stmt.execute(&[&(id as i32), &(school_id as i32)]));
+        let stmt = conn.prepare_cached(DELETE_STUDENT)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -145,25 +337,76 @@ impl Model for Student {
     }
 }
 
-impl Decodable for Student {
-    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        d.read_struct("Student", 0, |d| {
-            let id = try!(d.read_struct_field("id", 0, |d|
-                Option::<usize>::decode(d)));
-            let name = try!(d.read_struct_field("name", 0, |d|
-                d.read_str()));
-            let class_letter = try!(d.read_struct_field("class_letter", 0, |d|
-                d.read_str()));
-            let graduation_year = try!(d.read_struct_field("graduation_year", 0, |d|
-                d.read_i32()));
-            Ok(Student {
-                id: id,
-                name: name,
-                class_letter: class_letter,
-                graduation_year: graduation_year,
-                lent_books: None,
-                base_sets: None
-            })
-        })
+#[cfg(test)]
+mod tests {
+    use crate::models::{invalid_fields, Includable, Includes};
+    use crate::models::csv::ToCsv;
+    use crate::models::schools::ValidationRules;
+    use super::Student;
+
+    fn rules() -> ValidationRules {
+        ValidationRules {
+            class_letters: "abc".to_string(),
+            min_graduation_year: 2020,
+            max_graduation_year: 2030
+        }
+    }
+
+    fn check(body: &str) -> Vec<String> {
+        invalid_fields::<Student>(body, &rules())
+    }
+
+    #[test]
+    fn accepts_valid_students() {
+        assert!(check(r#"{"name":"Jael Veen","class_letter":"a","graduation_year":2027}"#).is_empty());
+        assert!(check(r#"{"name":"Jael Veen","class_letter":"C","graduation_year":2030,"external_id":"4711"}"#)
+            .is_empty());
+        assert!(check(r#"{"name":"Jael Veen","class_letter":"a","graduation_year":2020}"#).is_empty());
+    }
+
+    #[test]
+    fn allows_an_empty_class_letter() {
+        // Forms without classes, e.g. the upper school, have no class letter.
+        assert!(check(r#"{"name":"Katharina Maier","class_letter":"","graduation_year":2027}"#).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(check(r#"{"name":" ","class_letter":"a","graduation_year":2027}"#), vec!["name"]);
+        assert_eq!(check(r#"{"name":"Jael Veen","class_letter":"ab","graduation_year":2027}"#), vec!["class_letter"]);
+        assert_eq!(check(r#"{"name":"Jael Veen","class_letter":"1","graduation_year":2027}"#), vec!["class_letter"]);
+        assert_eq!(check(r#"{"name":"Jael Veen","class_letter":"d","graduation_year":2027}"#), vec!["class_letter"]);
+        assert_eq!(check(r#"{"name":"Jael Veen","class_letter":"a","graduation_year":2019}"#),
+            vec!["graduation_year"]);
+        assert_eq!(check(r#"{"name":"Jael Veen","class_letter":"a","graduation_year":2031}"#),
+            vec!["graduation_year"]);
+        assert_eq!(check(r#"{"name":"Jael Veen","class_letter":"a","graduation_year":2027,"external_id":" "}"#),
+            vec!["external_id"]);
+    }
+
+    #[test]
+    fn reports_all_invalid_fields_at_once() {
+        assert_eq!(check(r#"{"name":"","class_letter":"ab","graduation_year":1990,"external_id":" "}"#),
+            vec!["name", "class_letter", "graduation_year", "external_id"]);
+    }
+
+    #[test]
+    fn allows_any_class_letter_without_school_rules() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<Student>(
+            &format!(r#"{{"name":"Jael Veen","class_letter":"x","graduation_year":{}}}"#, rules.max_graduation_year),
+            &rules).is_empty());
+    }
+
+    #[test]
+    fn exports_the_columns_of_the_includes() {
+        let mut student = Student::new("Jael Veen".to_string(), "a".to_string(), 2025, None);
+        student.lent_books = Some(Vec::new());
+        let includes = Includes::new();
+        assert_eq!(Student::csv_header(&includes).len(), 5);
+        assert_eq!(student.csv_rows(&includes), vec![vec!["", "Jael Veen", "a", "2025", ""]]);
+        let includes = vec![Includable::LentBooks].into_iter().collect::<Includes>();
+        assert_eq!(Student::csv_header(&includes).len(), 13);
+        assert_eq!(student.csv_rows(&includes)[0].len(), 13);
     }
 }
@@ -1,57 +1,92 @@
-use chrono::{DateTime, UTC};
 use postgres::Connection;
 use postgres::rows::Row;
-use rustc_serialize::{Decodable, Decoder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-use error::ObsidianError;
-use models::{Model, Includes, Includable};
-use models::books::Book;
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{load_related, Columns, Model, Includes, Includable, Page, Query, Validate};
+use crate::models::schools::ValidationRules;
+use crate::models::students::LentBook;
 
-#[derive(RustcEncodable, Debug)]
-struct LentBook{
-    id: usize,
-    created_at: String,
-    book: Book
-}
-
-const QUERY_TEACHER: &'static str = "SELECT id, name FROM teachers WHERE id=$1 AND school_id=$2";
-const QUERY_TEACHERS: &'static str = "SELECT id, name FROM teachers WHERE school_id=$1";
-const QUERY_LENDINGS: &'static str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id FROM lendings, books
-WHERE lendings.person_type='teacher' AND lendings.person_id=$1 AND lendings.book_id = books.id";
+const QUERY_TEACHER: &str = "SELECT id, name FROM teachers WHERE id=$1 AND school_id=$2";
+const QUERY_TEACHERS_BY_ID: &str = "SELECT id, name FROM teachers WHERE id = ANY($1) AND school_id=$2";
+const QUERY_TEACHERS: &str = "SELECT id, name FROM teachers WHERE school_id=$1";
+const TEACHER_COLUMNS: Columns = &[("id", "id"), ("name", "name")];
+const QUERY_LENDINGS: &str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id, copies.id,
+copies.inventory_number, lendings.returned_at, lendings.return_note, lendings.due_at, lendings.person_id
+FROM lendings, copies, books
+WHERE lendings.person_type='teacher' AND lendings.person_id = ANY($1) AND lendings.copy_id = copies.id
+AND copies.book_id = books.id AND lendings.returned_at IS NULL ORDER BY lendings.created_at, lendings.id";
+const QUERY_LENDING_HISTORY: &str = "SELECT title, form, isbn, lendings.created_at, books.id, lendings.id, copies.id,
+copies.inventory_number, lendings.returned_at, lendings.return_note, lendings.due_at, lendings.person_id
+FROM lendings, copies, books
+WHERE lendings.person_type='teacher' AND lendings.person_id = ANY($1) AND lendings.copy_id = copies.id
+AND copies.book_id = books.id AND lendings.returned_at IS NOT NULL ORDER BY lendings.returned_at DESC";
 
-const INSERT_TEACHER: &'static str = "INSERT INTO teachers (name, school_id) VALUES ($1, $2) RETURNING id";
-const UPDATE_TEACHER: &'static str = "UPDATE teachers SET name=$2 WHERE id=$1 AND school_id=$3";
-const DELETE_TEACHER: &'static str = "DELETE FROM teachers WHERE id=$1 AND school_id=$2";
+const INSERT_TEACHER: &str = "INSERT INTO teachers (name, school_id) VALUES ($1, $2) RETURNING id";
+const UPDATE_TEACHER: &str = "UPDATE teachers SET name=$2 WHERE id=$1 AND school_id=$3";
+const DELETE_TEACHER: &str = "DELETE FROM teachers WHERE id=$1 AND school_id=$2";
 
-#[derive(RustcEncodable, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Teacher {
+    #[serde(default)]
     id: Option<usize>,
     name: String,
-    lent_books: Option<Vec<LentBook>>
+    #[serde(skip_deserializing)]
+    lent_books: Option<Vec<LentBook>>,
+    #[serde(skip_deserializing)]
+    lending_history: Option<Vec<LentBook>>
 }
 
 impl Teacher {
-    fn from_db(conn: &Connection, includes: &Includes, row: Row) -> Result<Teacher, ObsidianError> {
-        let id = row.get::<usize, i32>(0) as usize;
-        let lent_books = if includes.contains(&Includable::LentBooks) {
-            let stmt = try!(conn.prepare_cached(QUERY_LENDINGS));
-            let rows = try!(stmt.query(&[&(id as i32)]));
-            Some(rows.iter()
-                .map(|row| LentBook {
-                    id: row.get::<usize, i32>(5) as usize,
-                    created_at: row.get::<usize, DateTime<UTC>>(3).to_rfc3339(),
-                    book: Book::new(Some(row.get::<usize, i32>(4) as usize), row.get::<usize, String>(2),
-                        row.get::<usize, String>(0), row.get::<usize, String>(1))
-                })
-                .collect::<Vec<LentBook>>())
-        } else {
-            None
-        };
-        Ok(Teacher {
-            id: Some(id),
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn from_db(row: Row) -> Teacher {
+        Teacher {
+            id: Some(row.get::<usize, i32>(0) as usize),
             name: row.get::<usize, String>(1),
-            lent_books: lent_books
-        })
+            lent_books: None,
+            lending_history: None
+        }
+    }
+
+    fn load_includes(teachers: &mut [Teacher], conn: &Connection, includes: &Includes) -> Result<(), ObsidianError> {
+        let ids = teachers.iter().filter_map(|teacher| teacher.id).collect::<Vec<usize>>();
+        if includes.contains(&Includable::LentBooks) {
+            let mut lendings = load_related(QUERY_LENDINGS, &ids, 11, conn, LentBook::from_db)?;
+            for teacher in teachers.iter_mut() {
+                teacher.lent_books = Some(teacher.id.and_then(|id| lendings.remove(&id)).unwrap_or(Vec::new()));
+            }
+        }
+        if includes.contains(&Includable::LendingHistory) {
+            let mut history = load_related(QUERY_LENDING_HISTORY, &ids, 11, conn, LentBook::from_db)?;
+            for teacher in teachers.iter_mut() {
+                teacher.lending_history = Some(teacher.id.and_then(|id| history.remove(&id)).unwrap_or(Vec::new()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn find_ids(ids: &[usize], school_id: usize, conn: &Connection)
+        -> Result<HashMap<usize, Teacher>, ObsidianError> {
+        let ids = ids.iter().map(|&id| id as i32).collect::<Vec<i32>>();
+        let stmt = conn.prepare_cached(QUERY_TEACHERS_BY_ID)?;
+        let rows = stmt.query(&[&ids, &(school_id as i32)])?;
+        Ok(rows.iter()
+            .map(|row| (row.get::<usize, i32>(0) as usize, Teacher::from_db(row)))
+            .collect())
+    }
+}
+
+impl Validate for Teacher {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.name.trim().is_empty() {
+            vec![FieldError::new("name", "The name must not be empty")]
+        } else {
+            Vec::new()
+        }
     }
 }
 
@@ -59,10 +94,15 @@ impl Model for Teacher {
     fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError> {
         does_not_support!(BaseSetBooks, includes);
         does_not_support!(Aliases, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_TEACHER));
-        let rows = try!(stmt.query(&[&(id as i32), &(school_id as i32)]));
-        let row = try!(rows.iter().next().ok_or(ObsidianError::RecordNotFound("Teacher")));
-        let teacher = try!(Teacher::from_db(conn, includes, row));
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let stmt = conn.prepare_cached(QUERY_TEACHER)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Teacher"))?;
+        let mut teachers = vec![Teacher::from_db(row)];
+        Teacher::load_includes(&mut teachers, conn, includes)?;
+        let teacher = teachers.pop().unwrap();
         if teacher.id == Some(id) {
             Ok(teacher)
         } else {
@@ -71,19 +111,26 @@ impl Model for Teacher {
     }
 
     fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        Teacher::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
+    }
+
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
         does_not_support!(BaseSetBooks, includes);
         does_not_support!(Aliases, includes);
-        let stmt = try!(conn.prepare_cached(QUERY_TEACHERS));
-        let rows = try!(stmt.query(&[&(school_id as i32)]));
-        rows.iter()
-            .map(|row| Teacher::from_db(conn, includes, row))
-            .collect::<Result<Vec<Teacher>, ObsidianError>>()
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let mut page = query.execute(QUERY_TEACHERS, TEACHER_COLUMNS, school_id, conn,
+            |row| Ok(Teacher::from_db(row)))?;
+        Teacher::load_includes(&mut page.items, conn, includes)?;
+        Ok(page)
     }
 
     fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
         if let Some(id) = id {
-            let stmt = try!(conn.prepare_cached(UPDATE_TEACHER));
-            let modified = try!(stmt.execute(&[&(id as i32), &self.name, &(school_id as i32)]));
+            let stmt = conn.prepare_cached(UPDATE_TEACHER)?;
+            let modified = stmt.execute(&[&(id as i32), &self.name, &(school_id as i32)])?;
             if modified == 1  {
                 self.id = Some(id);
                 Ok(self)
@@ -91,8 +138,8 @@ impl Model for Teacher {
                 Err(ObsidianError::RecordNotFound("Teacher"))
             }
         } else {
-            let stmt = try!(conn.prepare_cached(INSERT_TEACHER));
-            let rows = try!(stmt.query(&[&self.name, &(school_id as i32)]));
+            let stmt = conn.prepare_cached(INSERT_TEACHER)?;
+            let rows = stmt.query(&[&self.name, &(school_id as i32)])?;
             let row = rows.iter().next().unwrap();
             self.id = Some(row.get::<usize, i32>(0) as usize);
             Ok(self)
@@ -100,8 +147,8 @@ impl Model for Teacher {
     }
 
     fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
-        let stmt = try!(conn.prepare_cached(DELETE_TEACHER));
-        let modified = try!(stmt.execute(&[&(id as i32), &(school_id as i32)]));
+        let stmt = conn.prepare_cached(DELETE_TEACHER)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
         if modified == 1 {
             Ok(())
         } else {
@@ -110,15 +157,16 @@ impl Model for Teacher {
     }
 }
 
-impl Decodable for Teacher {
-    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
-        d.read_struct("Teacher", 1, |d| d
-            .read_struct_field("name", 0, D::read_str)
-                .map(|name| Teacher{id: None, name: name, lent_books: None}))
-        .or_else(|_| d.read_struct("Teacher", 2, |d| {
-            let id = try!(d.read_struct_field("id", 0, Option::<usize>::decode));
-            let name = try!(d.read_struct_field("name", 1, D::read_str));
-            Ok(Teacher{id: id, name: name, lent_books: None})
-        }))
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::Teacher;
+
+    #[test]
+    fn requires_a_name() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<Teacher>(r#"{"name":"Frau Schmidt"}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<Teacher>(r#"{"name":""}"#, &rules), vec!["name"]);
     }
 }
@@ -0,0 +1,189 @@
+use postgres::Connection;
+use std::net::IpAddr;
+
+use crate::error::{ObsidianError, ReqError};
+
+/// Failed attempts allowed before a login, user, token or API key is locked.
+const FREE_ATTEMPTS: i32 = 5;
+/// Failed logins allowed before an address is locked, higher as a whole school may share one
+/// address.
+const FREE_ATTEMPTS_IP: i32 = 20;
+/// The first lockout, doubled with every further failure up to `MAX_LOCKOUT_SECS`.
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+
+const QUERY_LOCKED: &str = "SELECT CAST(ceil(extract(epoch FROM max(locked_until) - now())) AS BIGINT)
+FROM login_failures WHERE key = ANY($1) AND locked_until > now()";
+/// Failures are forgotten a day after the last one.
+const RECORD_FAILURE: &str = "INSERT INTO login_failures (key, failures, last_failure_at) VALUES ($1, 1, now())
+ON CONFLICT (key) DO UPDATE SET last_failure_at = now(), failures = CASE
+    WHEN login_failures.last_failure_at < now() - interval '1 day' THEN 1
+    ELSE login_failures.failures + 1 END
+RETURNING failures";
+const LOCK: &str = "UPDATE login_failures SET locked_until = now() + make_interval(secs => $2) WHERE key=$1";
+const DELETE_FAILURES: &str = "DELETE FROM login_failures WHERE key = ANY($1)";
+const DELETE_PREFIXED_FAILURES: &str = "DELETE FROM login_failures WHERE left(key, length($1)) = $1";
+const DELETE_EXPIRED: &str = "DELETE FROM login_failures WHERE last_failure_at < now() - interval '1 day'
+AND (locked_until IS NULL OR locked_until < now())";
+
+/// The school name as part of a key. Its length keeps a name and what follows it from adding
+/// up to the key of another school.
+fn school_part(school: &str) -> String {
+    let school = school.to_lowercase();
+    format!("{}:{}:", school.len(), school)
+}
+
+/// The prefix shared by the login keys of a school.
+fn school_prefix(school: &str) -> String {
+    format!("login:{}", school_part(school))
+}
+
+/// Counts the failed logins as `username` of the school `school`.
+pub fn login_key(school: &str, username: &str) -> String {
+    format!("{}{}", school_prefix(school), username.to_lowercase())
+}
+
+pub fn user_key(id: usize) -> String {
+    format!("user:{}", id)
+}
+
+pub fn token_key(id: usize) -> String {
+    format!("token:{}", id)
+}
+
+pub fn api_key_key(id: usize) -> String {
+    format!("key:{}", id)
+}
+
+/// Counts the failed logins to the school `school` coming from the address `ip`. The school is
+/// part of the key so that each school can only lift the lockouts of its own logins.
+pub fn ip_key(school: &str, ip: &IpAddr) -> String {
+    format!("ip:{}{}", school_part(school), ip)
+}
+
+/// Counts the failed logins to any school coming from the address `ip`, so that trying a
+/// password against many schools is locked as well.
+pub fn global_ip_key(ip: &IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+fn free_attempts(key: &str) -> i32 {
+    if key.starts_with("ip:") {
+        FREE_ATTEMPTS_IP
+    } else {
+        FREE_ATTEMPTS
+    }
+}
+
+fn lockout_secs(failures: i32, free_attempts: i32) -> i64 {
+    let doublings = ::std::cmp::min(failures - free_attempts - 1, 16) as u32;
+    ::std::cmp::min(BASE_LOCKOUT_SECS * 2i64.pow(doublings), MAX_LOCKOUT_SECS)
+}
+
+/// Fails with `TooManyAttempts` while any of `keys` is locked.
+pub fn check(keys: &[String], conn: &Connection) -> Result<(), ObsidianError> {
+    let stmt = conn.prepare_cached(QUERY_LOCKED)?;
+    let rows = stmt.query(&[&keys.to_vec()])?;
+    match rows.iter().next().and_then(|row| row.get::<usize, Option<i64>>(0)) {
+        Some(secs) => Err(ObsidianError::TooManyAttempts(::std::cmp::max(secs, 1) as u64)),
+        None => Ok(())
+    }
+}
+
+/// Counts a failed attempt against each of `keys` and locks those past their free attempts.
+pub fn record_failure(keys: &[String], conn: &Connection) -> Result<(), ObsidianError> {
+    let stmt = conn.prepare_cached(RECORD_FAILURE)?;
+    let stmt2 = conn.prepare_cached(LOCK)?;
+    for key in keys {
+        let rows = stmt.query(&[key])?;
+        let failures = rows.iter().next().ok_or(ObsidianError::RecordNotFound("Login failure"))?
+            .get::<usize, i32>(0);
+        let free_attempts = free_attempts(key);
+        if failures > free_attempts {
+            stmt2.execute(&[key, &(lockout_secs(failures, free_attempts) as f64)])?;
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the failures of `keys`, lifting any lockout.
+pub fn clear(keys: &[String], conn: &Connection) -> Result<(), ObsidianError> {
+    let stmt = conn.prepare_cached(DELETE_FAILURES)?;
+    stmt.execute(&[&keys.to_vec()])?;
+    Ok(())
+}
+
+/// Forgets the failed logins to all users of the school `school`.
+pub fn clear_school(school: &str, conn: &Connection) -> Result<(), ObsidianError> {
+    let stmt = conn.prepare_cached(DELETE_PREFIXED_FAILURES)?;
+    stmt.execute(&[&school_prefix(school)])?;
+    Ok(())
+}
+
+/// Removes failures that are forgotten anyway. Runs in the background with the token cleanup.
+pub fn delete_expired(conn: &Connection) -> Result<u64, ObsidianError> {
+    let stmt = conn.prepare_cached(DELETE_EXPIRED)?;
+    stmt.execute(&[]).map_err(ObsidianError::from)
+}
+
+fn is_rejection(err: &ObsidianError) -> bool {
+    matches!(*err, ObsidianError::WrongPassword | ObsidianError::RequestError(ReqError::NoAuth))
+}
+
+/// Runs the authentication `f` unless one of `keys` is locked, counting rejected credentials
+/// as failures. Other errors, e.g. of the database, are not counted.
+pub fn attempt<T, F>(keys: &[String], conn: &Connection, f: F) -> Result<T, ObsidianError>
+    where F: FnOnce() -> Result<T, ObsidianError> {
+    check(keys, conn)?;
+    match f() {
+        Err(err) => {
+            if is_rejection(&err) {
+                record_failure(keys, conn)?;
+            }
+            Err(err)
+        },
+        result => result
+    }
+}
+
+/// Runs the authentication `f` and only looks at `keys` if it rejects the credentials, so
+/// credentials that verify are never refused. Rejections count as failures, or fail with
+/// `TooManyAttempts` while one of `keys` is locked. Only meant for tokens and API keys, whose
+/// random secrets cannot be guessed anyway, so that nobody can lock their owners out; guessable
+/// passwords go through `attempt`.
+pub fn verify_first<T, F>(keys: &[String], conn: &Connection, f: F) -> Result<T, ObsidianError>
+    where F: FnOnce() -> Result<T, ObsidianError> {
+    match f() {
+        Err(err) => {
+            if is_rejection(&err) {
+                check(keys, conn)?;
+                record_failure(keys, conn)?;
+            }
+            Err(err)
+        },
+        result => result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use super::{free_attempts, global_ip_key, ip_key, login_key, school_prefix, FREE_ATTEMPTS_IP};
+
+    #[test]
+    fn addresses_are_counted_per_school() {
+        let ip = "192.0.2.10".parse::<IpAddr>().unwrap();
+        assert_eq!(ip_key("Gymnasium", &ip), ip_key("gymnasium", &ip));
+        assert!(ip_key("Gymnasium", &ip) != ip_key("Realschule", &ip));
+        assert!(!ip_key("Gymnasium", &ip).starts_with(&school_prefix("Gymnasium")));
+        assert!(!login_key("Gymnasium", "ip").starts_with("ip:"));
+    }
+
+    #[test]
+    fn addresses_are_counted_across_schools() {
+        let ip = "192.0.2.10".parse::<IpAddr>().unwrap();
+        assert!(global_ip_key(&ip) != ip_key("Gymnasium", &ip));
+        assert!(global_ip_key(&ip) != global_ip_key(&"192.0.2.11".parse::<IpAddr>().unwrap()));
+        assert_eq!(free_attempts(&global_ip_key(&ip)), FREE_ATTEMPTS_IP);
+    }
+}
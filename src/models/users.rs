@@ -0,0 +1,335 @@
+use bcrypt::{hash, verify, DEFAULT_COST};
+use postgres::Connection;
+use postgres::rows::Row;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+use std::str::FromStr;
+
+use crate::error::{FieldError, ObsidianError};
+use crate::models::{transaction, Columns, Model, Includes, Page, Query, Validate};
+use crate::models::schools::ValidationRules;
+use crate::models::sessions::Session;
+
+const QUERY_USER: &str = "SELECT id, username, role FROM users WHERE id=$1 AND school_id=$2";
+const QUERY_USERS: &str = "SELECT id, username, role FROM users WHERE school_id=$1";
+const USER_COLUMNS: Columns = &[("id", "id"), ("username", "username"), ("role", "role")];
+const QUERY_LOGIN: &str = "SELECT users.id, users.school_id, users.role, users.encrypted_password
+FROM users, schools WHERE users.school_id = schools.id AND schools.name=$1 AND users.username=$2";
+const QUERY_PASSWORD: &str = "SELECT encrypted_password FROM users WHERE id=$1";
+const QUERY_USERNAME_TAKEN: &str = "SELECT 1 FROM users WHERE school_id=$1 AND username=$2
+AND id IS DISTINCT FROM $3";
+const QUERY_OTHER_ADMINS: &str = "SELECT count(*) FROM users WHERE school_id=$1 AND role='admin' AND id<>$2";
+
+const INSERT_USER: &str = "INSERT INTO users (school_id, username, encrypted_password, role)
+VALUES ($1, $2, $3, $4) RETURNING id";
+const UPDATE_USER: &str = "UPDATE users SET username=$3, role=$4 WHERE id=$1 AND school_id=$2";
+const UPDATE_PASSWORD: &str = "UPDATE users SET encrypted_password=$2 WHERE id=$1";
+const DELETE_USER: &str = "DELETE FROM users WHERE id=$1 AND school_id=$2";
+
+/// What a user may do. Roles are ordered, each one allowing everything the lower ones do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    ReadOnly,
+    Librarian,
+    Admin
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::ReadOnly => "read_only",
+            Role::Librarian => "librarian",
+            Role::Admin => "admin"
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "read_only" => Ok(Role::ReadOnly),
+            "librarian" => Ok(Role::Librarian),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role {}, expected admin, librarian or read_only", s))
+        }
+    }
+}
+
+impl Serialize for Role {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Role, D::Error> {
+        let role = String::deserialize(d)?;
+        role.parse::<Role>().map_err(de::Error::custom)
+    }
+}
+
+/// The user a request is made by, as established by its token.
+#[derive(Debug, Clone, Copy)]
+pub struct Account {
+    pub id: usize,
+    pub school_id: usize,
+    pub role: Role,
+    /// The token the request is authenticated with, `None` while logging in.
+    pub session_id: Option<usize>
+}
+
+/// Checks `password` against the stored password of the user `id`.
+pub fn check_password(id: usize, password: &str, conn: &Connection) -> Result<(), ObsidianError> {
+    let stmt = conn.prepare_cached(QUERY_PASSWORD)?;
+    let rows = stmt.query(&[&(id as i32)])?;
+    let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("User"))?;
+    if verify(password, &row.get::<usize, String>(0)).unwrap_or(false) {
+        Ok(())
+    } else {
+        Err(ObsidianError::WrongPassword)
+    }
+}
+
+/// Logs the user `username` of the school `school` in. Unknown schools and users fail with
+/// the same error as wrong passwords and take as long, so neither can be probed for.
+pub fn login(school: &str, username: &str, password: &str, conn: &Connection) -> Result<Account, ObsidianError> {
+    let stmt = conn.prepare_cached(QUERY_LOGIN)?;
+    let rows = stmt.query(&[&school.to_lowercase(), &username.to_lowercase()])?;
+    let row = match rows.iter().next() {
+        Some(row) => row,
+        None => {
+            // Hashing costs as much as verifying a stored hash of the same cost
+            let _ = hash(password, DEFAULT_COST);
+            return Err(ObsidianError::WrongPassword);
+        }
+    };
+    if verify(password, &row.get::<usize, String>(3)).unwrap_or(false) {
+        Ok(Account {
+            id: row.get::<usize, i32>(0) as usize,
+            school_id: row.get::<usize, i32>(1) as usize,
+            role: row.get::<usize, String>(2).parse::<Role>().map_err(|_| ObsidianError::RecordNotFound("User"))?,
+            session_id: None
+        })
+    } else {
+        Err(ObsidianError::WrongPassword)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    #[serde(default)]
+    id: Option<usize>,
+    username: String,
+    role: Role,
+    /// Only given to set the password; it is never sent back.
+    #[serde(default, skip_serializing)]
+    password: Option<String>
+}
+
+impl User {
+    /// Creates the first admin of a new school.
+    pub fn admin(username: &str, password: &str) -> User {
+        User {
+            id: None,
+            username: username.to_string(),
+            role: Role::Admin,
+            password: Some(password.to_string())
+        }
+    }
+
+    pub fn id(&self) -> Option<usize> {
+        self.id
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    fn from_db(row: Row) -> Result<User, ObsidianError> {
+        Ok(User {
+            id: Some(row.get::<usize, i32>(0) as usize),
+            username: row.get::<usize, String>(1),
+            role: row.get::<usize, String>(2).parse::<Role>().map_err(|_| ObsidianError::RecordNotFound("User"))?,
+            password: None
+        })
+    }
+
+    /// Refuses to demote or delete the user `id` if no other admin would be left.
+    fn check_other_admins(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        let stmt = conn.prepare_cached(QUERY_OTHER_ADMINS)?;
+        let rows = stmt.query(&[&(school_id as i32), &(id as i32)])?;
+        if rows.iter().next().unwrap().get::<usize, i64>(0) > 0 {
+            Ok(())
+        } else {
+            Err(ObsidianError::LastAdmin)
+        }
+    }
+
+    /// Updates the user `id`, logging them out everywhere if their password or role changes.
+    fn update(&self, id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        let current = User::find_id(id, school_id, conn, &Includes::new())?;
+        if current.role == Role::Admin && self.role != Role::Admin {
+            User::check_other_admins(id, school_id, conn)?;
+        }
+        let stmt = conn.prepare_cached(UPDATE_USER)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32), &self.username,
+            &self.role.as_str()])?;
+        if modified != 1 {
+            return Err(ObsidianError::RecordNotFound("User"));
+        }
+        if let Some(ref password) = self.password {
+            let encrypted_password = hash(password, DEFAULT_COST)?;
+            let stmt2 = conn.prepare_cached(UPDATE_PASSWORD)?;
+            stmt2.execute(&[&(id as i32), &encrypted_password])?;
+        }
+        if self.password.is_some() || self.role != current.role {
+            Session::delete_for_user(id, None, conn)?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for User {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.username.trim().is_empty() {
+            errors.push(FieldError::new("username", "The username must not be empty"));
+        }
+        if self.password.as_ref().map(|password| password.is_empty()).unwrap_or(false) {
+            errors.push(FieldError::new("password", "The password must not be empty"));
+        }
+        errors
+    }
+}
+
+impl Model for User {
+    fn find_id(id: usize, school_id: usize, conn: &Connection, includes: &Includes) -> Result<Self, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        let stmt = conn.prepare_cached(QUERY_USER)?;
+        let rows = stmt.query(&[&(id as i32), &(school_id as i32)])?;
+        let row = rows.iter().next().ok_or(ObsidianError::RecordNotFound("User"))?;
+        User::from_db(row)
+    }
+
+    fn find_all(school_id: usize, conn: &Connection, includes: &Includes) -> Result<Vec<Self>, ObsidianError> {
+        User::find_page(school_id, &Query::default(), conn, includes).map(|page| page.items)
+    }
+
+    fn find_page(school_id: usize, query: &Query, conn: &Connection, includes: &Includes)
+        -> Result<Page<Self>, ObsidianError> {
+        does_not_support!(LentBooks, includes);
+        does_not_support!(BaseSetBooks, includes);
+        does_not_support!(Aliases, includes);
+        does_not_support!(LendingHistory, includes);
+        does_not_support!(Copies, includes);
+        does_not_support!(Book, includes);
+        does_not_support!(Person, includes);
+        query.execute(QUERY_USERS, USER_COLUMNS, school_id, conn, User::from_db)
+    }
+
+    fn save(mut self, id: Option<usize>, school_id: usize, conn: &Connection) -> Result<Self, ObsidianError> {
+        self.username = self.username.trim().to_lowercase();
+        let stmt = conn.prepare_cached(QUERY_USERNAME_TAKEN)?;
+        let rows = stmt.query(&[&(school_id as i32), &self.username, &id.map(|id| id as i32)])?;
+        if !rows.is_empty() {
+            return Err(ObsidianError::InvalidFields(vec![
+                FieldError::new("username", "The username is already taken")]));
+        }
+        if let Some(id) = id {
+            transaction(conn, || self.update(id, school_id, conn))?;
+            self.id = Some(id);
+        } else {
+            let password = self.password.clone().ok_or(ObsidianError::InvalidFields(vec![
+                FieldError::new("password", "A password is needed for new users")]))?;
+            let encrypted_password = hash(&password, DEFAULT_COST)?;
+            let stmt = conn.prepare_cached(INSERT_USER)?;
+            let rows = stmt.query(&[&(school_id as i32), &self.username, &encrypted_password,
+                &self.role.as_str()])?;
+            self.id = Some(rows.iter().next().unwrap().get::<usize, i32>(0) as usize);
+        }
+        self.password = None;
+        Ok(self)
+    }
+
+    fn delete(id: usize, school_id: usize, conn: &Connection) -> Result<(), ObsidianError> {
+        let user = User::find_id(id, school_id, conn, &Includes::new())?;
+        if user.role == Role::Admin {
+            User::check_other_admins(id, school_id, conn)?;
+        }
+        let stmt = conn.prepare_cached(DELETE_USER)?;
+        let modified = stmt.execute(&[&(id as i32), &(school_id as i32)])?;
+        if modified == 1 {
+            Ok(())
+        } else {
+            Err(ObsidianError::RecordNotFound("User"))
+        }
+    }
+}
+
+/// Changes the password of the user making the request.
+#[derive(Deserialize, Debug)]
+pub struct PasswordChange {
+    old_password: String,
+    new_password: String
+}
+
+impl PasswordChange {
+    /// Logs `account` out of all their other sessions, keeping the one the change is made with.
+    pub fn perform(&self, account: &Account, conn: &Connection) -> Result<(), ObsidianError> {
+        check_password(account.id, &self.old_password, conn)?;
+        let encrypted_password = hash(&self.new_password, DEFAULT_COST)?;
+        transaction(conn, || {
+            let stmt = conn.prepare_cached(UPDATE_PASSWORD)?;
+            let modified = stmt.execute(&[&(account.id as i32), &encrypted_password])?;
+            if modified != 1 {
+                return Err(ObsidianError::RecordNotFound("User"));
+            }
+            Session::delete_for_user(account.id, account.session_id, conn)?;
+            Ok(())
+        })
+    }
+}
+
+impl Validate for PasswordChange {
+    fn validate(&self, _: &ValidationRules) -> Vec<FieldError> {
+        if self.new_password.is_empty() {
+            vec![FieldError::new("new_password", "The password must not be empty")]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::invalid_fields;
+    use crate::models::schools::ValidationRules;
+    use super::{PasswordChange, User};
+
+    #[test]
+    fn checks_users() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<User>(r#"{"username":"schmidt","role":"librarian","password":"test1234"}"#, &rules)
+            .is_empty());
+        assert!(invalid_fields::<User>(r#"{"username":"schmidt","role":"admin"}"#, &rules).is_empty());
+        assert_eq!(invalid_fields::<User>(r#"{"username":" ","role":"read_only","password":""}"#, &rules),
+            vec!["username", "password"]);
+    }
+
+    #[test]
+    fn checks_password_changes() {
+        let rules = ValidationRules::default();
+        assert!(invalid_fields::<PasswordChange>(r#"{"old_password":"test1234","new_password":"geheim"}"#, &rules)
+            .is_empty());
+        assert_eq!(invalid_fields::<PasswordChange>(r#"{"old_password":"test1234","new_password":""}"#, &rules),
+            vec!["new_password"]);
+    }
+}
@@ -1,55 +1,85 @@
-use iron::Chain;
-use iron_cors::CorsMiddleware;
-use router::Router;
+use axum::Router;
+use axum::routing::{delete, get, post, put};
+use tower_http::cors::CorsLayer;
 
 use super::handlers;
-use handlers::auth;
-use middleware::{PostgresConnection, RequestBody};
-
-pub fn get_chain() -> Chain {
-    let mut c = Chain::new(get_routes());
-    c.link_before(PostgresConnection::new().unwrap());
-    c.link_before(RequestBody::new());
-    c.link_around(CorsMiddleware::with_allow_any(true));
-    c
+use crate::config::Config;
+use crate::error::ObsidianError;
+use crate::handlers::{auth, auth_as, open};
+use crate::middleware::{AppState, PostgresConnection};
+use crate::models::users::Role;
+
+pub fn get_router(config: &Config) -> Result<Router, ObsidianError> {
+    let state = AppState {
+        db: PostgresConnection::new(&config.database)?,
+        max_body_bytes: config.server.max_body_bytes
+    };
+    Ok(get_routes()
+        .layer(CorsLayer::permissive())
+        .with_state(state))
 }
 
-fn get_routes() -> Router {
-    router!(
-        students_index: get "/students" =>         auth(handlers::students::index),
-        students_show: get "/students/:id" =>      auth(handlers::students::show),
-        students_edit: put "/students/:id" =>      auth(handlers::students::edit),
-        students_new: post "/students" =>          auth(handlers::students::new),
-        students_delete: delete "/students/:id" => auth(handlers::students::delete),
-
-        books_index: get "/books" =>         auth(handlers::books::index),
-        books_show: get "/books/:id" =>      auth(handlers::books::show),
-        books_edit: put "/books/:id" =>      auth(handlers::books::edit),
-        books_new: post "/books" =>          auth(handlers::books::new),
-        books_delete: delete "/books/:id" => auth(handlers::books::delete),
-
-        aliases_index: get "/aliases" =>         auth(handlers::aliases::index),
-        aliases_edit: put "/aliases/:id" =>      auth(handlers::aliases::edit),
-        aliases_new: post "/aliases" =>          auth(handlers::aliases::new),
-        aliases_delete: delete "/aliases/:id" => auth(handlers::aliases::delete),
-
-        teachers_index: get "/teachers" =>         auth(handlers::teachers::index),
-        teachers_show: get "/teachers/:id" =>      auth(handlers::teachers::show),
-        teachers_edit: put "/teachers/:id" =>      auth(handlers::teachers::edit),
-        teachers_new: post "/teachers" =>          auth(handlers::teachers::new),
-        teachers_delete: delete "/teachers/:id" => auth(handlers::teachers::delete),
-
-        base_sets_new: post "/base_sets" =>          auth(handlers::base_sets::new),
-        base_sets_delete: delete "/base_sets/:id" => auth(handlers::base_sets::delete),
-
-        lendings_new: post "/lendings" =>          auth(handlers::lendings::new),
-        lendings_delete: delete "/lendings/:id" => auth(handlers::lendings::delete),
-
-        schools_edit: put "/schools" =>      auth(handlers::schools::edit),
-        schools_new: post "/schools" =>      handlers::schools::new,
-        schools_delete: delete "/schools" => auth(handlers::schools::delete),
-
-        sessions_new: post "/sessions" =>      handlers::sessions::new,
-        sessions_delete: delete "/sessions" => handlers::sessions::delete
-    )
+fn get_routes() -> Router<AppState> {
+    Router::new()
+        .route("/students", get(auth(handlers::students::index)).post(auth(handlers::students::new)))
+        .route("/students.csv", get(auth(handlers::students::export)))
+        .route("/students/import", post(auth(handlers::students::import)))
+        .route("/students/{id}", get(auth(handlers::students::show))
+            .put(auth(handlers::students::edit))
+            .delete(auth(handlers::students::delete)))
+
+        .route("/books", get(auth(handlers::books::index)).post(auth(handlers::books::new)))
+        .route("/books.csv", get(auth(handlers::books::export)))
+        .route("/books/{id}", get(auth(handlers::books::show))
+            .put(auth(handlers::books::edit))
+            .delete(auth(handlers::books::delete)))
+
+        .route("/aliases", get(auth(handlers::aliases::index)).post(auth(handlers::aliases::new)))
+        .route("/aliases/{id}", put(auth(handlers::aliases::edit)).delete(auth(handlers::aliases::delete)))
+
+        .route("/copies", get(auth(handlers::copies::index)).post(auth(handlers::copies::new)))
+        .route("/copies/{id}", get(auth(handlers::copies::show))
+            .put(auth(handlers::copies::edit))
+            .delete(auth(handlers::copies::delete)))
+
+        .route("/teachers", get(auth(handlers::teachers::index)).post(auth(handlers::teachers::new)))
+        .route("/teachers/{id}", get(auth(handlers::teachers::show))
+            .put(auth(handlers::teachers::edit))
+            .delete(auth(handlers::teachers::delete)))
+
+        .route("/base_sets", get(auth(handlers::base_sets::index)).post(auth(handlers::base_sets::new)))
+        .route("/base_sets/assign", post(auth(handlers::base_sets::assign)))
+        .route("/base_sets/{id}", get(auth(handlers::base_sets::show)).delete(auth(handlers::base_sets::delete)))
+
+        .route("/lendings", get(auth(handlers::lendings::index)).post(auth(handlers::lendings::new)))
+        .route("/lendings.csv", get(auth(handlers::lendings::export)))
+        .route("/lendings/overdue", get(auth(handlers::lendings::overdue)))
+        .route("/lendings/{id}", get(auth(handlers::lendings::show)).delete(auth(handlers::lendings::delete)))
+        .route("/lendings/{id}/return", post(auth(handlers::lendings::return_book)))
+
+        .route("/lookup", get(auth(handlers::lookup::index)))
+        .route("/search", get(auth(handlers::search::index)))
+
+        .route("/schools", put(auth_as(Role::Admin, handlers::schools::edit))
+            .post(open(handlers::schools::new))
+            .delete(auth_as(Role::Admin, handlers::schools::delete)))
+        .route("/schools/rollover", post(auth_as(Role::Admin, handlers::schools::rollover)))
+        .route("/schools/lockout", delete(auth_as(Role::Admin, handlers::schools::unlock)))
+
+        .route("/users", get(auth_as(Role::Admin, handlers::users::index))
+            .post(auth_as(Role::Admin, handlers::users::new)))
+        .route("/users/password", put(auth_as(Role::ReadOnly, handlers::users::password)))
+        .route("/users/{id}", get(auth_as(Role::Admin, handlers::users::show))
+            .put(auth_as(Role::Admin, handlers::users::edit))
+            .delete(auth_as(Role::Admin, handlers::users::delete)))
+
+        .route("/api_keys", get(auth_as(Role::Admin, handlers::api_keys::index))
+            .post(auth_as(Role::Admin, handlers::api_keys::new)))
+        .route("/api_keys/{id}", delete(auth_as(Role::Admin, handlers::api_keys::delete)))
+
+        .route("/sessions", get(auth_as(Role::ReadOnly, handlers::sessions::index))
+            .post(open(handlers::sessions::new))
+            .delete(open(handlers::sessions::delete)))
+        .route("/sessions/all", delete(auth_as(Role::ReadOnly, handlers::sessions::revoke_all)))
+        .route("/sessions/{id}", delete(auth_as(Role::ReadOnly, handlers::sessions::revoke)))
 }
@@ -0,0 +1,974 @@
+//! Runs the server binary and checks its responses against the API described in README.md.
+//! Like the other tests that need Postgres, these are ignored unless run with `--ignored`
+//! and `TEST_DATABASE_URL` naming a scratch database. Every test signs up a school of its
+//! own and deletes it again, so the tests can run in parallel.
+
+extern crate serde_json;
+
+use postgres::{Connection, TlsMode};
+use serde_json::Value;
+use std::env;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::Once;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const PASSWORD: &str = "password123";
+
+static MIGRATE: Once = Once::new();
+static SCHOOLS: AtomicUsize = AtomicUsize::new(0);
+
+fn database_url() -> String {
+    env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a scratch database")
+}
+
+/// The server binary listening on a free port, stopped when dropped.
+struct Server {
+    child: Child,
+    addr: String
+}
+
+impl Server {
+    fn start() -> Server {
+        let url = database_url();
+        MIGRATE.call_once(|| {
+            let status = Command::new(env!("CARGO_BIN_EXE_obsidian_rust"))
+                .args(["migrate", "up"])
+                .env("DATABASE_URL", &url)
+                .env_remove("OBSIDIAN_CONFIG")
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success(), "could not migrate the test database");
+            // All tests log in from the same address, whose failures would add up over runs
+            let conn = Connection::connect(&*url, TlsMode::None).unwrap();
+            conn.execute("DELETE FROM login_failures WHERE key = 'ip:127.0.0.1'", &[]).unwrap();
+        });
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let child = Command::new(env!("CARGO_BIN_EXE_obsidian_rust"))
+            .env("DATABASE_URL", &url)
+            .env("OBSIDIAN_BIND", &addr)
+            .env_remove("OBSIDIAN_CONFIG")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Server {
+            child,
+            addr
+        };
+        for _ in 0..200 {
+            if TcpStream::connect(&*server.addr).is_ok() {
+                return server;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("the server did not start listening on {}", server.addr);
+    }
+
+    /// Sends a request and reads the response until the server closes the connection.
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> Response {
+        let mut stream = TcpStream::connect(&*self.addr).unwrap();
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method, path, self.addr, body.len());
+        for &(name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(body.as_bytes()).unwrap();
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).unwrap();
+        Response::parse(&raw)
+    }
+
+    fn post_json(&self, path: &str, body: &Value) -> Response {
+        self.request("POST", path, &[("Content-Type", "application/json")], &body.to_string())
+    }
+
+    /// Signs up a school with a name no other test uses.
+    fn new_school(&self) -> School<'_> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let name = format!("api test {}-{}-{}", std::process::id(), SCHOOLS.fetch_add(1, Ordering::SeqCst), nanos);
+        let res = self.post_json("/schools", &json(&format!(r#"{{"name":"{}","password":"{}"}}"#, name, PASSWORD)));
+        assert_eq!(res.status, 201, "{}", res.body);
+        School {
+            name,
+            auth: Auth::from_token(&res.json()),
+            server: self
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String
+}
+
+impl Response {
+    fn parse(raw: &[u8]) -> Response {
+        let split = raw.windows(4).position(|window| window == b"\r\n\r\n").expect("no end of headers");
+        let head = String::from_utf8(raw[..split].to_vec()).unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap().parse::<u16>().unwrap();
+        let headers = lines
+            .map(|line| {
+                let colon = line.find(':').unwrap();
+                (line[..colon].trim().to_lowercase(), line[colon + 1..].trim().to_string())
+            })
+            .collect::<Vec<(String, String)>>();
+        let mut body = raw[split + 4..].to_vec();
+        let chunked = headers.iter()
+            .any(|(name, value)| name == "transfer-encoding" && value.contains("chunked"));
+        if chunked {
+            body = dechunk(&body);
+        }
+        Response {
+            status,
+            headers,
+            body: String::from_utf8(body).unwrap()
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&(other, _)| other == &name.to_lowercase())
+            .map(|(_, value)| value.as_ref())
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|err| panic!("{}: {}", err, self.body))
+    }
+
+    /// Checks that the response is the problem document `problem` with the status `status`.
+    fn assert_problem(&self, status: u16, problem: &str) {
+        assert_eq!(self.status, status, "{}", self.body);
+        assert!(self.header("Content-Type").unwrap().starts_with("application/json"));
+        assert_eq!(self.json(), json(problem));
+    }
+}
+
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let end = body.windows(2).position(|window| window == b"\r\n").unwrap();
+        let size = std::str::from_utf8(&body[..end]).unwrap().split(';').next().unwrap();
+        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+        if size == 0 {
+            return out;
+        }
+        out.extend_from_slice(&body[end + 2..end + 2 + size]);
+        body = &body[end + 4 + size..];
+    }
+}
+
+fn json(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|err| panic!("{}: {}", err, text))
+}
+
+/// Replaces the values of the fields `names` anywhere in `value` by `"…"` unless they are
+/// null, after checking that timestamps among them are RFC 3339 in UTC.
+fn masked(value: &Value, names: &[&str]) -> Value {
+    match *value {
+        Value::Object(ref fields) => Value::Object(fields.iter()
+            .map(|(name, field)| {
+                let field = if names.contains(&name.as_ref()) && !field.is_null() {
+                    if name.ends_with("_at") {
+                        let time = field.as_str().unwrap();
+                        assert!(time.len() >= 25 && &time[10..11] == "T" && time.ends_with("+00:00"), "{}", time);
+                    }
+                    Value::String("…".to_string())
+                } else {
+                    masked(field, names)
+                };
+                (name.clone(), field)
+            })
+            .collect()),
+        Value::Array(ref items) => Value::Array(items.iter().map(|item| masked(item, names)).collect()),
+        ref other => other.clone()
+    }
+}
+
+fn base64(input: &str) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let bytes = input.as_bytes();
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The calendar year the current school year ends in, school years starting in August.
+fn current_school_year() -> i64 {
+    // Converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64 / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    if month >= 8 { year + 1 } else { year }
+}
+
+/// The username and password of Basic authentication.
+#[derive(Debug, Clone)]
+struct Auth(String, String);
+
+impl Auth {
+    fn from_token(token: &Value) -> Auth {
+        assert_eq!(token.as_object().unwrap().len(), 2, "{}", token);
+        let secret = token["secret"].as_str().unwrap();
+        assert_eq!(secret.len(), 32);
+        Auth(token["token_id"].as_u64().unwrap().to_string(), secret.to_string())
+    }
+
+    fn header(&self) -> String {
+        format!("Basic {}", base64(&format!("{}:{}", self.0, self.1)))
+    }
+}
+
+/// A school signed up by a test together with the token of its admin. The school is deleted
+/// when dropped, unless the test deleted it already.
+struct School<'a> {
+    name: String,
+    auth: Auth,
+    server: &'a Server
+}
+
+impl<'a> School<'a> {
+    fn as_user(&self, auth: &Auth) -> School<'a> {
+        School {
+            name: self.name.clone(),
+            auth: auth.clone(),
+            server: self.server
+        }
+    }
+
+    fn send(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> Response {
+        let auth = self.auth.header();
+        let mut headers = headers.to_vec();
+        headers.push(("Authorization", &auth));
+        self.server.request(method, path, &headers, body)
+    }
+
+    fn get(&self, path: &str) -> Response {
+        self.send("GET", path, &[("Accept", "application/json")], "")
+    }
+
+    fn post(&self, path: &str, body: &str) -> Response {
+        self.send("POST", path, &[("Content-Type", "application/json")], body)
+    }
+
+    fn put(&self, path: &str, body: &str) -> Response {
+        self.send("PUT", path, &[("Content-Type", "application/json")], body)
+    }
+
+    fn delete(&self, path: &str) -> Response {
+        self.send("DELETE", path, &[], "")
+    }
+
+    /// Creates a record and returns it, which must be a JSON object with an `id`.
+    fn create(&self, path: &str, body: &str) -> Value {
+        let res = self.post(path, body);
+        assert_eq!(res.status, 201, "{}", res.body);
+        let record = res.json();
+        assert!(record["id"].is_u64(), "{}", record);
+        record
+    }
+
+    fn login(&self, username: &str, password: &str) -> Response {
+        self.server.post_json("/sessions", &json(&format!(r#"{{"name":"{}","username":"{}","password":"{}"}}"#,
+            self.name, username, password)))
+    }
+}
+
+impl<'a> Drop for School<'a> {
+    fn drop(&mut self) {
+        let auth = self.auth.header();
+        self.server.request("DELETE", "/schools", &[("Authorization", &auth), ("Content-Type", "application/json")],
+            &format!(r#"{{"password":"{}"}}"#, PASSWORD));
+    }
+}
+
+fn id(record: &Value) -> u64 {
+    record["id"].as_u64().unwrap()
+}
+
+#[test]
+#[ignore]
+fn signing_up_and_authenticating() {
+    let server = Server::start();
+    let res = server.request("POST", "/schools", &[], r#"{"name":"x","password":"y"}"#);
+    res.assert_problem(400, r#"{"code":"expected_json","message":"Content-Type needs to be application/json",
+        "field":null,"details":null}"#);
+    let res = server.post_json("/schools", &json(r#"{"name":" ","password":""}"#));
+    res.assert_problem(422, r#"{"code":"invalid_fields","message":"Some fields of the record are invalid",
+        "field":"name","details":[{"field":"name","message":"The name must not be empty"},
+        {"field":"password","message":"The password must not be empty"}]}"#);
+    let res = server.post_json("/schools", &json(r#"{"name":"Michaeli-Gymnasium"}"#));
+    res.assert_problem(400, r#"{"code":"invalid_body","message":"The request body could not be parsed",
+        "field":"password","details":"The field is missing"}"#);
+    let res = server.request("POST", "/schools", &[("Content-Type", "application/json")], "{");
+    res.assert_problem(400, r#"{"code":"invalid_body","message":"The request body could not be parsed",
+        "field":null,"details":"The body is not valid JSON"}"#);
+
+    let school = server.new_school();
+    let missing = r#"{"code":"missing_credentials","message":"The request needs to be authenticated",
+        "field":null,"details":null}"#;
+    let res = server.request("GET", "/students", &[], "");
+    res.assert_problem(401, missing);
+    assert_eq!(res.header("WWW-Authenticate"), Some("Basic: realm=\"Token and secret\""));
+    let res = school.as_user(&Auth(school.auth.0.clone(), "x".repeat(32))).get("/students");
+    res.assert_problem(401, missing);
+    assert_eq!(res.header("WWW-Authenticate"), Some("Basic: realm=\"Token and secret\""));
+    school.as_user(&Auth("abc".to_string(), "x".to_string())).get("/students").assert_problem(401, missing);
+
+    let res = school.login("admin", "wrong");
+    res.assert_problem(401, r#"{"code":"invalid_credentials","message":"The credentials are invalid",
+        "field":null,"details":null}"#);
+    assert_eq!(res.header("WWW-Authenticate"), Some("Basic: realm=\"Token and secret\""));
+    let res = server.post_json("/sessions", &json(&format!(r#"{{"name":"{}","password":"{}"}}"#,
+        school.name.to_uppercase(), PASSWORD)));
+    assert_eq!(res.status, 201, "{}", res.body);
+    let second = school.as_user(&Auth::from_token(&res.json()));
+
+    let res = second.get("/sessions");
+    assert_eq!(res.status, 200, "{}", res.body);
+    let sessions = res.json();
+    assert_eq!(masked(&sessions, &["id", "created_at", "last_used_at"]), json(r#"[
+        {"id":"…","created_at":"…","last_used_at":"…","user_agent":null,"current":true},
+        {"id":"…","created_at":"…","last_used_at":"…","user_agent":null,"current":false}]"#));
+    assert_eq!(sessions[1]["id"].as_u64().unwrap().to_string(), school.auth.0);
+
+    let res = second.send("DELETE", "/sessions", &[], "");
+    assert_eq!(res.status, 204, "{}", res.body);
+    assert_eq!(res.body, "");
+    second.get("/students").assert_problem(401, missing);
+    let res = school.get("/sessions");
+    assert_eq!(res.json().as_array().unwrap().len(), 1);
+
+    let res = school.put("/schools", r#"{"loan_period_days":0}"#);
+    res.assert_problem(422, r#"{"code":"invalid_fields","message":"Some fields of the record are invalid",
+        "field":"loan_period_days","details":[{"field":"loan_period_days",
+        "message":"The loan period must be at least one day"}]}"#);
+    let res = school.post("/students", &" ".repeat(4 * 1024 * 1024 + 1));
+    res.assert_problem(413, r#"{"code":"body_too_large","message":"The request body is too large","field":null,
+        "details":"At most 4194304 bytes are accepted"}"#);
+    let res = school.put("/schools", r#"{"colour":"red"}"#);
+    assert_eq!(res.status, 400, "{}", res.body);
+    assert_eq!(res.json()["code"], json(r#""invalid_body""#));
+    for body in &[r#"{"loan_period_days":14}"#, r#"{"class_letters":"abc","graduation_years_back":2,
+        "graduation_years_ahead":13}"#, r#"{"session_lifetime_hours":12,"session_idle_minutes":30}"#,
+        r#"{"name":"Renamed School","loan_period_days":21,"session_lifetime_hours":8}"#] {
+        let res = school.put("/schools", body);
+        assert_eq!(res.status, 204, "{}", res.body);
+    }
+    let res = school.put("/schools", r#"{"class_letters":"abcd","graduation_years_back":2,
+        "graduation_years_ahead":13,"loan_period_days":0}"#);
+    res.assert_problem(422, r#"{"code":"invalid_fields","message":"Some fields of the record are invalid",
+        "field":"loan_period_days","details":[{"field":"loan_period_days",
+        "message":"The loan period must be at least one day"}]}"#);
+    let res = school.post("/students", &format!(r#"{{"name":"Jael Veen","class_letter":"d",
+        "graduation_year":{}}}"#, current_school_year() + 1));
+    res.assert_problem(422, r#"{"code":"invalid_fields","message":"Some fields of the record are invalid",
+        "field":"class_letter","details":[{"field":"class_letter","message":"The class letter must be one of abc"}]}"#);
+
+    let res = school.send("DELETE", "/schools", &[("Content-Type", "application/json")], r#"{"password":"wrong"}"#);
+    res.assert_problem(401, r#"{"code":"invalid_credentials","message":"The credentials are invalid",
+        "field":null,"details":null}"#);
+    let res = school.send("DELETE", "/schools", &[("Content-Type", "application/json")],
+        &format!(r#"{{"password":"{}"}}"#, PASSWORD));
+    assert_eq!(res.status, 204, "{}", res.body);
+    school.get("/students").assert_problem(401, missing);
+    school.login("admin", PASSWORD).assert_problem(401, r#"{"code":"invalid_credentials",
+        "message":"The credentials are invalid","field":null,"details":null}"#);
+}
+
+#[test]
+#[ignore]
+fn locking_out_and_unlocking() {
+    let server = Server::start();
+    let school = server.new_school();
+    for _ in 0..6 {
+        assert_eq!(school.login("admin", "wrong").status, 401);
+    }
+    let res = school.login("admin", PASSWORD);
+    assert_eq!(res.status, 429, "{}", res.body);
+    let secs = res.header("Retry-After").unwrap().parse::<u64>().unwrap();
+    assert!(secs > 0 && secs <= 30, "{}", secs);
+    assert_eq!(res.json(), json(&format!(r#"{{"code":"too_many_attempts",
+        "message":"Too many failed attempts, authentication is locked for now","field":null,
+        "details":"Retry after {} seconds"}}"#, secs)));
+
+    let res = school.delete("/schools/lockout?address=192.0.2.10");
+    assert_eq!(res.status, 204, "{}", res.body);
+    assert_eq!(school.login("admin", PASSWORD).status, 201);
+    school.delete("/schools/lockout?address=banana").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"address","details":null}"#);
+
+    // Wrong secrets lock the token, but its correct secret is still accepted.
+    let wrong = school.as_user(&Auth(school.auth.0.clone(), "x".repeat(32)));
+    for _ in 0..6 {
+        assert_eq!(wrong.get("/students").status, 401);
+    }
+    let res = wrong.get("/students");
+    assert_eq!(res.status, 429, "{}", res.body);
+    assert!(res.header("Retry-After").is_some());
+    assert_eq!(school.get("/students").status, 200);
+}
+
+#[test]
+#[ignore]
+fn managing_students() {
+    let server = Server::start();
+    let school = server.new_school();
+    let year = current_school_year() + 1;
+
+    let jael = school.create("/students", &format!(r#"{{"name":"Jael Veen","class_letter":"b",
+        "graduation_year":{},"external_id":"4711"}}"#, year));
+    assert_eq!(jael, json(&format!(r#"{{"id":{},"name":"Jael Veen","class_letter":"b","graduation_year":{},
+        "external_id":"4711","lent_books":null,"base_sets":null,"lending_history":null}}"#, id(&jael), year)));
+    let res = school.get(&format!("/students/{}", id(&jael)));
+    assert_eq!(res.status, 200);
+    assert_eq!(res.json(), jael);
+    let res = school.get(&format!("/students/{}?include=lendings,basesets,lendinghistory", id(&jael)));
+    assert_eq!(res.json(), json(&format!(r#"{{"id":{},"name":"Jael Veen","class_letter":"b","graduation_year":{},
+        "external_id":"4711","lent_books":[],"base_sets":[],"lending_history":[]}}"#, id(&jael), year)));
+    school.get("/students/abc").assert_problem(400, r#"{"code":"invalid_id",
+        "message":"The id needs to be an integer","field":null,"details":null}"#);
+    school.get("/students/999999999").assert_problem(404, r#"{"code":"not_found",
+        "message":"The record does not exist","field":null,"details":"Student not found"}"#);
+    school.get(&format!("/students/{}?include=aliases", id(&jael))).assert_problem(400,
+        r#"{"code":"include_not_supported","message":"The relation to be included is not supported by this route",
+        "field":"include","details":"Aliases"}"#);
+
+    // An id in the body is ignored and the external id kept unless given.
+    let res = school.put(&format!("/students/{}", id(&jael)), &format!(r#"{{"id":1,"name":"Jael Veen",
+        "class_letter":"a","graduation_year":{}}}"#, year));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json()["id"], jael["id"]);
+    assert_eq!(res.json()["class_letter"], json(r#""a""#));
+    assert_eq!(res.json()["external_id"], json(r#""4711""#));
+    let res = school.put("/students/999999999", &format!(r#"{{"name":"Nobody","class_letter":"a",
+        "graduation_year":{}}}"#, year));
+    assert_eq!(res.status, 404, "{}", res.body);
+    school.post("/students", &format!(r#"{{"name":"","class_letter":"ab","graduation_year":{}}}"#, year + 40))
+        .assert_problem(422, &format!(r#"{{"code":"invalid_fields","message":"Some fields of the record are invalid",
+        "field":"name","details":[{{"field":"name","message":"The name must not be empty"}},
+        {{"field":"class_letter","message":"The class letter must be a single letter"}},
+        {{"field":"graduation_year","message":"The graduation year must be between {} and {}"}}]}}"#,
+        year - 2, year + 12));
+
+    // Bulk creation
+    let res = school.post("/students", &format!(r#"[{{"name":"Katharina Maier","class_letter":"a",
+        "graduation_year":{0}}},{{"name":"","class_letter":"a","graduation_year":{0}}}]"#, year));
+    res.assert_problem(422, r#"{"code":"invalid_fields","message":"Some fields of the record are invalid",
+        "field":"1.name","details":[{"field":"1.name","message":"The name must not be empty"}]}"#);
+    let res = school.post("/students", &format!(r#"[{{"name":"Katharina Maier","class_letter":"a",
+        "graduation_year":{0}}},{{"name":"Lena Abt","class_letter":"b","graduation_year":{0}}}]"#, year));
+    assert_eq!(res.status, 201, "{}", res.body);
+    let created = res.json();
+    assert_eq!(masked(&created, &["id"]), json(&format!(r#"[
+        {{"id":"…","name":"Katharina Maier","class_letter":"a","graduation_year":{0},"external_id":null,
+        "lent_books":null,"base_sets":null,"lending_history":null}},
+        {{"id":"…","name":"Lena Abt","class_letter":"b","graduation_year":{0},"external_id":null,
+        "lent_books":null,"base_sets":null,"lending_history":null}}]"#, year)));
+    let res = school.post("/students?atomic=false", &format!(r#"[{{"name":"Mia Zorn","class_letter":"c",
+        "graduation_year":{0}}},{{"name":" ","class_letter":"c","graduation_year":{0}}},{{"class_letter":"c"}}]"#,
+        year + 1));
+    assert_eq!(res.status, 207, "{}", res.body);
+    assert_eq!(masked(&res.json(), &["id"]), json(&format!(r#"[
+        {{"index":0,"status":201,"record":{{"id":"…","name":"Mia Zorn","class_letter":"c","graduation_year":{},
+        "external_id":null,"lent_books":null,"base_sets":null,"lending_history":null}},"error":null}},
+        {{"index":1,"status":422,"record":null,"error":{{"code":"invalid_fields",
+        "message":"Some fields of the record are invalid","field":"name",
+        "details":[{{"field":"name","message":"The name must not be empty"}}]}}}},
+        {{"index":2,"status":400,"record":null,"error":{{"code":"invalid_body",
+        "message":"The request body could not be parsed","field":"name","details":"The field is missing"}}}}]"#,
+        year + 1)));
+
+    // Filtering, sorting and pagination
+    let res = school.get("/students?sort=-name");
+    assert_eq!(res.header("X-Total-Count"), Some("4"));
+    assert_eq!(res.header("Link"), None);
+    let names = res.json().as_array().unwrap().iter()
+        .map(|student| student["name"].as_str().unwrap().to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(names, vec!["Mia Zorn", "Lena Abt", "Katharina Maier", "Jael Veen"]);
+    let res = school.get(&format!("/students?graduation_year={}&per_page=1&page=2", year));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.header("X-Total-Count"), Some("3"));
+    assert_eq!(res.header("Link"), Some(&*format!("<?graduation_year={0}&per_page=1&page=1>; rel=\"first\", \
+        <?graduation_year={0}&per_page=1&page=1>; rel=\"prev\", <?graduation_year={0}&per_page=1&page=3>; \
+        rel=\"next\", <?graduation_year={0}&per_page=1&page=3>; rel=\"last\"", year)));
+    assert_eq!(res.json()[0]["name"], json(r#""Katharina Maier""#));
+    let res = school.get("/students?class_letter=a&name=Jael%20Veen");
+    assert_eq!(res.json().as_array().unwrap().len(), 1);
+    assert_eq!(res.json()[0]["id"], jael["id"]);
+    school.get("/students?colour=red").assert_problem(400, r#"{"code":"unknown_field",
+        "message":"The field cannot be used for filtering or sorting","field":"colour","details":null}"#);
+    school.get("/students?sort=colour").assert_problem(400, r#"{"code":"unknown_field",
+        "message":"The field cannot be used for filtering or sorting","field":"colour","details":null}"#);
+    school.get("/students?graduation_year=soon").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"graduation_year","details":null}"#);
+    for param in &["page=0", "per_page=1001", "per_page=x"] {
+        let res = school.get(&format!("/students?{}", param));
+        assert_eq!(res.status, 400, "{}", param);
+        assert_eq!(res.json()["code"], json(r#""invalid_parameter""#));
+    }
+
+    // CSV export
+    let rows = format!("id,name,class_letter,graduation_year,external_id\r\n{},Jael Veen,a,{},4711\r\n",
+        id(&jael), year);
+    let res = school.get("/students.csv?name=Jael%20Veen");
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.header("Content-Type"), Some("text/csv; charset=utf-8"));
+    assert_eq!(res.header("Content-Disposition"), Some("attachment; filename=\"students.csv\""));
+    assert_eq!(res.body, rows);
+    let res = school.send("GET", "/students?name=Jael%20Veen&delimiter=;&bom=true", &[("Accept", "text/csv")], "");
+    assert_eq!(res.header("Content-Disposition"), Some("attachment; filename=\"students.csv\""));
+    assert_eq!(res.body, format!("\u{feff}{}", rows.replace(',', ";")));
+    let res = school.get("/students.csv?name=Jael%20Veen&include=lendings");
+    assert_eq!(res.body, format!("id,name,class_letter,graduation_year,external_id,kind,inventory_number,isbn,title,\
+        form,created_at,due_at,returned_at\r\n{},Jael Veen,a,{},4711,,,,,,,,\r\n", id(&jael), year));
+    school.get("/students.csv?delimiter=;;").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"delimiter","details":null}"#);
+
+    // Import
+    let csv = format!("ID;Name;Klasse;Abschluss\n4711;Jael Veen;c;{0}\n;Nora Sell;d;{0}\n4712;Katharina Maier;;zwei\n",
+        year);
+    let res = school.send("POST", "/students/import?name_column=Name&class_letter_column=Klasse&\
+        graduation_year_column=Abschluss&external_id_column=ID&delimiter=;&dry_run=true",
+        &[("Content-Type", "text/csv")], &csv);
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(masked(&res.json(), &["id"]), json(&format!(r#"{{"dry_run":true,"created":1,"updated":1,
+        "rejected":1,"rows":[
+        {{"row":2,"action":"update","student":{{"id":"…","name":"Jael Veen","class_letter":"c",
+        "graduation_year":{0},"external_id":"4711","lent_books":null,"base_sets":null,"lending_history":null}},
+        "error":null}},
+        {{"row":3,"action":"create","student":{{"id":"…","name":"Nora Sell","class_letter":"d",
+        "graduation_year":{0},"external_id":null,"lent_books":null,"base_sets":null,"lending_history":null}},
+        "error":null}},
+        {{"row":4,"action":"reject","student":null,"error":"The graduation year is not a number"}}]}}"#, year)));
+    assert_eq!(school.get("/students").header("X-Total-Count"), Some("4"));
+    school.post("/students/import", &csv).assert_problem(400, r#"{"code":"expected_csv",
+        "message":"Content-Type needs to be text/csv","field":null,"details":null}"#);
+    school.send("POST", "/students/import", &[("Content-Type", "text/csv")], &csv).assert_problem(400,
+        r#"{"code":"missing_column","message":"The CSV file lacks a required column","field":"name","details":null}"#);
+
+    let res = school.delete(&format!("/students/{}", id(&jael)));
+    assert_eq!(res.status, 204, "{}", res.body);
+    assert_eq!(school.get(&format!("/students/{}", id(&jael))).status, 404);
+    assert_eq!(school.delete(&format!("/students/{}", id(&jael))).status, 404);
+
+    // Records of other schools do not exist for a school.
+    let other = server.new_school();
+    assert_eq!(other.get(&format!("/students/{}", created[0]["id"])).status, 404);
+    assert_eq!(other.get("/students").json(), json("[]"));
+}
+
+#[test]
+#[ignore]
+fn managing_books_aliases_and_copies() {
+    let server = Server::start();
+    let school = server.new_school();
+
+    let book = school.create("/books", r#"{"isbn":"3-12-731810-3","title":"Mathematik 5","form":"5,6"}"#);
+    assert_eq!(book, json(&format!(r#"{{"id":{},"isbn":"9783127318104","title":"Mathematik 5","form":[5,6],
+        "aliases":null,"availability":null,"copies":null}}"#, id(&book))));
+    let other = school.create("/books", r#"{"isbn":"9783060312832","title":"Green Line 1","form":[5]}"#);
+    let res = school.post("/books", r#"{"isbn":"978-3-12-731810-4","title":"Mathematik","form":[5]}"#);
+    res.assert_problem(409, &format!(r#"{{"code":"duplicate_isbn","message":"A book with this ISBN already exists",
+        "field":"isbn","details":"/books/{}"}}"#, id(&book)));
+    assert_eq!(res.header("Location"), Some(&*format!("/books/{}", id(&book))));
+    school.post("/books", r#"{"isbn":"978-3-12-731810-3","title":" ","form":[5]}"#).assert_problem(422,
+        r#"{"code":"invalid_fields","message":"Some fields of the record are invalid","field":"isbn","details":[
+        {"field":"isbn","message":"The ISBN is not a valid ISBN-10 or ISBN-13"},
+        {"field":"title","message":"The title must not be empty"}]}"#);
+    school.post("/books", r#"{"isbn":"9783127318104","title":"Mathematik","form":[14]}"#).assert_problem(400,
+        r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,
+        "details":"14 is not a form between 1 and 13"}"#);
+    school.post("/books", r#"{"isbn":"9783127318104","title":"Mathematik","form":"5,x"}"#).assert_problem(400,
+        r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,
+        "details":"'x' is not a form"}"#);
+    let res = school.put(&format!("/books/{}", id(&other)), r#"{"isbn":"9783060312832","title":"Green Line 1",
+        "form":[6,5,5]}"#);
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json()["form"], json("[5,6]"));
+
+    let res = school.get("/books?form=6&sort=-title");
+    assert_eq!(res.header("X-Total-Count"), Some("2"));
+    assert_eq!(res.json()[0]["id"], book["id"]);
+    assert_eq!(school.get("/books?form=7").json(), json("[]"));
+    school.get("/books?form=x").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"form","details":null}"#);
+
+    let alias = school.create("/aliases", &format!(r#"{{"book_id":{},"name":"Mathe 5"}}"#, id(&book)));
+    assert_eq!(alias, json(&format!(r#"{{"id":{},"book_id":{},"name":"Mathe 5"}}"#, id(&alias), id(&book))));
+    let res = school.put(&format!("/aliases/{}", id(&alias)), &format!(r#"{{"book_id":{},"name":"Mathe"}}"#,
+        id(&book)));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json()["name"], json(r#""Mathe""#));
+    let res = school.get("/aliases");
+    assert_eq!(res.header("X-Total-Count"), Some("1"));
+    assert_eq!(res.json(), json(&format!(r#"[{{"id":{},"book_id":{},"name":"Mathe"}}]"#, id(&alias), id(&book))));
+    assert_eq!(school.post("/aliases", r#"{"book_id":999999999,"name":"Mathe"}"#).status, 404);
+
+    let copy = school.create("/copies", &format!(r#"{{"book_id":{},"inventory_number":"000414","condition":"new",
+        "acquired_on":"2017-08-30"}}"#, id(&book)));
+    assert_eq!(copy, json(&format!(r#"{{"id":{},"book_id":{},"inventory_number":"000414","condition":"new",
+        "acquired_on":"2017-08-30","status":"available"}}"#, id(&copy), id(&book))));
+    let second = school.create("/copies", &format!(r#"{{"book_id":{},"inventory_number":"000415"}}"#, id(&book)));
+    assert_eq!(second["condition"], Value::Null);
+    assert_eq!(second["acquired_on"], Value::Null);
+    let res = school.post("/copies", &format!(r#"{{"book_id":{},"inventory_number":"000414"}}"#, id(&book)));
+    res.assert_problem(409, &format!(r#"{{"code":"duplicate_inventory_number",
+        "message":"A copy with this inventory number already exists","field":"inventory_number",
+        "details":"/copies/{}"}}"#, id(&copy)));
+    assert_eq!(res.header("Location"), Some(&*format!("/copies/{}", id(&copy))));
+    school.post("/copies", &format!(r#"{{"book_id":{},"inventory_number":"000416","status":"lent"}}"#, id(&book)))
+        .assert_problem(409, r#"{"code":"copy_status_conflict",
+        "message":"Only lendings and base sets can mark a copy as lent or release it","field":"status",
+        "details":null}"#);
+    let res = school.put(&format!("/copies/{}", id(&second)), &format!(r#"{{"book_id":{},
+        "inventory_number":"000415","condition":"water damage","status":"lost"}}"#, id(&book)));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json(), json(&format!(r#"{{"id":{},"book_id":{},"inventory_number":"000415",
+        "condition":"water damage","acquired_on":null,"status":"lost"}}"#, id(&second), id(&book))));
+    let res = school.get(&format!("/copies?book_id={}&sort=-inventory_number", id(&book)));
+    assert_eq!(res.header("X-Total-Count"), Some("2"));
+    assert_eq!(res.json()[0]["id"], second["id"]);
+    assert_eq!(school.get(&format!("/copies/{}", id(&copy))).json(), copy);
+
+    let res = school.get(&format!("/books/{}?include=aliases,copies", id(&book)));
+    assert_eq!(res.json(), json(&format!(r#"{{"id":{},"isbn":"9783127318104","title":"Mathematik 5","form":[5,6],
+        "aliases":[{{"id":{},"book_id":{},"name":"Mathe"}}],
+        "availability":{{"total":2,"available":1,"lent":0,"lost":1,"retired":0}},
+        "copies":[{},{}]}}"#, id(&book), id(&alias), id(&book), copy, res.json()["copies"][1])));
+    let res = school.get("/books.csv?include=copies&sort=title");
+    assert_eq!(res.header("Content-Disposition"), Some("attachment; filename=\"books.csv\""));
+    assert_eq!(res.body.lines().next(), Some("id,isbn,title,form,total,available,lent,lost,retired"));
+
+    let res = school.delete(&format!("/copies/{}", id(&copy)));
+    assert_eq!(res.status, 204, "{}", res.body);
+    assert_eq!(school.get(&format!("/copies/{}", id(&copy))).json()["status"], json(r#""retired""#));
+    assert_eq!(school.delete("/copies/999999999").status, 404);
+    assert_eq!(school.delete(&format!("/aliases/{}", id(&alias))).status, 204);
+    assert_eq!(school.delete(&format!("/books/{}", id(&other))).status, 204);
+    school.get(&format!("/books/{}", id(&other))).assert_problem(404, r#"{"code":"not_found",
+        "message":"The record does not exist","field":null,"details":"Book not found"}"#);
+}
+
+#[test]
+#[ignore]
+fn lending_books_and_base_sets() {
+    let server = Server::start();
+    let school = server.new_school();
+    let year = current_school_year();
+    let book = school.create("/books", r#"{"isbn":"9783127318104","title":"Mathematik 5","form":[5]}"#);
+    let copies = (1..5)
+        .map(|n| school.create("/copies", &format!(r#"{{"book_id":{},"inventory_number":"00041{}"}}"#, id(&book), n)))
+        .collect::<Vec<Value>>();
+    let student = school.create("/students", &format!(r#"{{"name":"Jael Veen","class_letter":"a",
+        "graduation_year":{}}}"#, year + 7));
+    let teacher = school.create("/teachers", r#"{"name":"Frau Schmidt"}"#);
+    assert_eq!(teacher, json(&format!(r#"{{"id":{},"name":"Frau Schmidt","lent_books":null,
+        "lending_history":null}}"#, id(&teacher))));
+    let res = school.put(&format!("/teachers/{}", id(&teacher)), r#"{"name":"Frau Dr. Schmidt"}"#);
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json()["name"], json(r#""Frau Dr. Schmidt""#));
+    assert_eq!(school.get("/teachers?name=Frau%20Dr.%20Schmidt").header("X-Total-Count"), Some("1"));
+
+    let lending = school.create("/lendings", &format!(r#"{{"person_type":"student","person_id":{},"copy_id":{}}}"#,
+        id(&student), id(&copies[0])));
+    assert_eq!(masked(&lending, &["created_at", "due_at"]), json(&format!(r#"{{"id":{},"created_at":"…",
+        "due_at":"…","person_type":"student","person_id":{},"copy_id":{},"book_id":{},"returned_at":null,
+        "return_note":null,"book":null,"person":null}}"#, id(&lending), id(&student), id(&copies[0]), id(&book))));
+    let res = school.post("/lendings", &format!(r#"{{"person_type":"teacher","person_id":{},"copy_id":{},
+        "due_at":"2030-07-31T12:00:00+02:00"}}"#, id(&teacher), id(&copies[1])));
+    assert_eq!(res.status, 201, "{}", res.body);
+    let teacher_lending = res.json();
+    assert_eq!(teacher_lending["due_at"], json(r#""2030-07-31T10:00:00+00:00""#));
+    school.post("/lendings", &format!(r#"{{"person_type":"student","person_id":{},"copy_id":{},
+        "due_at":"2016-11-01T10:21:05+00:00"}}"#, id(&student), id(&copies[2]))).assert_problem(422,
+        r#"{"code":"invalid_fields","message":"Some fields of the record are invalid","field":"due_at",
+        "details":[{"field":"due_at","message":"The due date must not be before the lending"}]}"#);
+    school.post("/lendings", &format!(r#"{{"person_type":"pupil","person_id":{},"copy_id":{}}}"#,
+        id(&student), id(&copies[2]))).assert_problem(400, r#"{"code":"invalid_body",
+        "message":"The request body could not be parsed","field":null,
+        "details":"person_type must be either 'student' or 'teacher'"}"#);
+    school.post("/lendings", &format!(r#"{{"person_type":"student","person_id":{},"copy_id":{}}}"#,
+        id(&student), id(&copies[0]))).assert_problem(409, r#"{"code":"copy_unavailable",
+        "message":"The copy is not available for lending","field":null,"details":null}"#);
+    assert_eq!(school.post("/lendings", &format!(r#"{{"person_type":"student","person_id":999999999,
+        "copy_id":{}}}"#, id(&copies[2]))).status, 404);
+    let res = school.post("/lendings?atomic=false", &format!(r#"[
+        {{"person_type":"student","person_id":{0},"copy_id":{1}}},
+        {{"person_type":"student","person_id":{0},"copy_id":{2}}}]"#, id(&student), id(&copies[2]), id(&copies[0])));
+    assert_eq!(res.status, 207, "{}", res.body);
+    let results = res.json();
+    assert_eq!(results[0]["status"], json("201"));
+    assert_eq!(results[0]["record"]["copy_id"], copies[2]["id"]);
+    assert_eq!(results[1], json(r#"{"index":1,"status":409,"record":null,"error":{"code":"copy_unavailable",
+        "message":"The copy is not available for lending","field":null,"details":null}}"#));
+    assert_eq!(school.get(&format!("/copies/{}", id(&copies[0]))).json()["status"], json(r#""lent""#));
+
+    let res = school.get(&format!("/lendings/{}?include=book,person", id(&lending)));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json()["book"], book);
+    assert_eq!(res.json()["person"], student);
+    let res = school.get("/lendings?person_type=teacher&include=person");
+    assert_eq!(res.header("X-Total-Count"), Some("1"));
+    assert_eq!(res.json()[0]["person"]["name"], json(r#""Frau Dr. Schmidt""#));
+    school.get("/lendings?person_type=pupil").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"person_type","details":null}"#);
+    school.get("/lendings?since=yesterday").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"since","details":null}"#);
+    assert_eq!(school.get("/lendings?since=2017-08-01&open=true").header("X-Total-Count"), Some("3"));
+    assert_eq!(school.get("/lendings?since=2999-01-01").header("X-Total-Count"), Some("0"));
+
+    let res = school.post(&format!("/lendings/{}/return", id(&lending)), r#"{"note":"Cover torn"}"#);
+    assert_eq!(res.status, 200, "{}", res.body);
+    let returned = res.json();
+    assert!(returned["returned_at"].is_string());
+    assert_eq!(returned["return_note"], json(r#""Cover torn""#));
+    let res = school.send("POST", &format!("/lendings/{}/return", id(&teacher_lending)), &[], "");
+    assert_eq!(res.status, 200, "{}", res.body);
+    school.post(&format!("/lendings/{}/return", id(&lending)), "").assert_problem(409, r#"{"code":"already_returned",
+        "message":"The lending has already been returned","field":null,"details":null}"#);
+    assert_eq!(school.post("/lendings/999999999/return", "").status, 404);
+    assert_eq!(school.get("/lendings?open=false").header("X-Total-Count"), Some("2"));
+    assert_eq!(school.get(&format!("/copies/{}", id(&copies[0]))).json()["status"], json(r#""available""#));
+
+    let res = school.get(&format!("/teachers/{}?include=lendings,lendinghistory", id(&teacher)));
+    assert_eq!(masked(&res.json(), &["created_at", "due_at", "returned_at"]), json(&format!(r#"{{"id":{},
+        "name":"Frau Dr. Schmidt","lent_books":[],"lending_history":[{{"id":{},"created_at":"…","due_at":"…",
+        "returned_at":"…","return_note":null,"copy_id":{},"inventory_number":"000412","book":{}}}]}}"#,
+        id(&teacher), id(&teacher_lending), id(&copies[1]), book)));
+    let res = school.get("/lendings.csv?include=book,person&open=true");
+    assert_eq!(res.header("Content-Disposition"), Some("attachment; filename=\"lendings.csv\""));
+    let lines = res.body.lines().collect::<Vec<&str>>();
+    assert_eq!(lines[0], "id,created_at,due_at,returned_at,return_note,person_type,person_id,copy_id,book_id,\
+        isbn,title,form,name");
+    assert!(lines[1].ends_with(&*format!(",,,student,{},{},{},9783127318104,Mathematik 5,5,Jael Veen",
+        id(&student), id(&copies[2]), id(&book))), "{}", lines[1]);
+
+    let res = school.get("/lendings/overdue");
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json(), json(r#"{"classes":[],"teachers":[]}"#));
+    assert_eq!(school.delete(&format!("/lendings/{}", id(&lending))).status, 204);
+    assert_eq!(school.get(&format!("/lendings/{}", id(&lending))).status, 404);
+
+    // Base sets
+    let base_set = school.create("/base_sets", &format!(r#"{{"student_id":{},"copy_id":{}}}"#, id(&student),
+        id(&copies[0])));
+    assert_eq!(masked(&base_set, &["created_at"]), json(&format!(r#"{{"id":{},"student_id":{},"copy_id":{},
+        "book_id":{},"created_at":"…","book":null,"student":null}}"#, id(&base_set), id(&student), id(&copies[0]),
+        id(&book))));
+    let res = school.get(&format!("/base_sets/{}?include=book,student", id(&base_set)));
+    assert_eq!(res.json()["book"], book);
+    assert_eq!(res.json()["student"], student);
+    assert_eq!(school.get("/base_sets?since=2017-08-01").header("X-Total-Count"), Some("1"));
+    let res = school.get(&format!("/students/{}?include=basesets", id(&student)));
+    assert_eq!(res.json()["base_sets"][0]["copy_id"], copies[0]["id"]);
+    school.delete(&format!("/copies/{}", id(&copies[0]))).assert_problem(409, r#"{"code":"copy_status_conflict",
+        "message":"Only lendings and base sets can mark a copy as lent or release it","field":"status",
+        "details":null}"#);
+
+    let other = school.create("/students", &format!(r#"{{"name":"Lena Abt","class_letter":"a",
+        "graduation_year":{}}}"#, year + 7));
+    let res = school.post("/base_sets/assign", r#"{"form":5,"class_letter":"a","dry_run":true}"#);
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(masked(&res.json(), &["id", "created_at", "copy_id"]), json(&format!(r#"{{"school_year":{},
+        "dry_run":true,"students":2,"already_held":1,"assigned":[{{"id":"…","student_id":{},"copy_id":"…",
+        "book_id":{},"created_at":"…","book":null,"student":null}}],"missing_copies":[]}}"#, year, id(&other),
+        id(&book))));
+    assert_eq!(school.get("/base_sets").header("X-Total-Count"), Some("1"));
+    school.post("/base_sets/assign", r#"{"form":13}"#).assert_problem(422, r#"{"code":"invalid_fields",
+        "message":"Some fields of the record are invalid","field":"form",
+        "details":[{"field":"form","message":"The form must be between 1 and the final form"}]}"#);
+    assert_eq!(school.delete(&format!("/base_sets/{}", id(&base_set))).status, 204);
+    assert_eq!(school.get(&format!("/base_sets/{}", id(&base_set))).status, 404);
+    assert_eq!(school.delete(&format!("/teachers/{}", id(&teacher))).status, 204);
+}
+
+#[test]
+#[ignore]
+fn looking_up_and_searching() {
+    let server = Server::start();
+    let school = server.new_school();
+    let book = school.create("/books", r#"{"isbn":"9783127318104","title":"Mathematik 5","form":[5]}"#);
+    school.create("/aliases", &format!(r#"{{"book_id":{},"name":"Lambacher"}}"#, id(&book)));
+    let copy = school.create("/copies", &format!(r#"{{"book_id":{},"inventory_number":"000412"}}"#, id(&book)));
+    let student = school.create("/students", &format!(r#"{{"name":"Jael Veen","class_letter":"a",
+        "graduation_year":{},"external_id":"4711"}}"#, current_school_year() + 1));
+    let teacher = school.create("/teachers", r#"{"name":"Frau Schmidt"}"#);
+
+    let res = school.get("/lookup?code=3-12-731810-3");
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json(), json(&format!(r#"[{{"type":"book","record":{}}}]"#, book)));
+    assert_eq!(school.get("/lookup?code=lambacher").json(), json(&format!(r#"[{{"type":"book","record":{}}}]"#, book)));
+    assert_eq!(school.get("/lookup?code=000412").json(), json(&format!(r#"[{{"type":"copy","record":{},
+        "book":{}}}]"#, copy, book)));
+    assert_eq!(school.get("/lookup?code=4711").json(), json(&format!(r#"[{{"type":"student","record":{}}}]"#,
+        student)));
+    assert_eq!(school.get("/lookup?code=0815").json(), json("[]"));
+    school.get("/lookup?code=%20").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"code","details":null}"#);
+
+    let res = school.get("/search?q=schmid");
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json(), json(&format!(r#"[{{"type":"teacher","record":{}}}]"#, teacher)));
+    let res = school.get("/search?q=Mathematik&limit=1");
+    assert_eq!(res.json(), json(&format!(r#"[{{"type":"book","record":{}}}]"#, book)));
+    school.get("/search?q=a&limit=0").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"limit","details":null}"#);
+    school.get("/search").assert_problem(400, r#"{"code":"invalid_parameter",
+        "message":"A query parameter has an invalid value","field":"q","details":null}"#);
+}
+
+#[test]
+#[ignore]
+fn managing_users_and_api_keys() {
+    let server = Server::start();
+    let school = server.new_school();
+    let librarian = school.create("/users", r#"{"username":"Schmidt","role":"librarian","password":"test1234"}"#);
+    assert_eq!(librarian, json(&format!(r#"{{"id":{},"username":"schmidt","role":"librarian"}}"#, id(&librarian))));
+    let reader = school.create("/users", r#"{"username":"gast","role":"read_only","password":"test1234"}"#);
+    school.post("/users", r#"{"username":"schmidt","role":"admin","password":"x"}"#).assert_problem(422,
+        r#"{"code":"invalid_fields","message":"Some fields of the record are invalid","field":"username",
+        "details":[{"field":"username","message":"The username is already taken"}]}"#);
+    school.post("/users", r#"{"username":"neu","role":"admin"}"#).assert_problem(422, r#"{"code":"invalid_fields",
+        "message":"Some fields of the record are invalid","field":"password",
+        "details":[{"field":"password","message":"A password is needed for new users"}]}"#);
+    school.post("/users", r#"{"username":"neu","role":"boss","password":"x"}"#).assert_problem(400,
+        r#"{"code":"invalid_body","message":"The request body could not be parsed","field":null,
+        "details":"Unknown role boss, expected admin, librarian or read_only"}"#);
+    let res = school.get("/users?sort=username");
+    assert_eq!(res.header("X-Total-Count"), Some("3"));
+    assert_eq!(masked(&res.json(), &["id"]), json(r#"[{"id":"…","username":"admin","role":"admin"},
+        {"id":"…","username":"gast","role":"read_only"},{"id":"…","username":"schmidt","role":"librarian"}]"#));
+    assert_eq!(school.get(&format!("/users/{}", id(&reader))).json(), reader);
+
+    let res = school.login("gast", "test1234");
+    assert_eq!(res.status, 201, "{}", res.body);
+    let as_reader = school.as_user(&Auth::from_token(&res.json()));
+    assert_eq!(as_reader.get("/students").status, 200);
+    as_reader.post("/teachers", r#"{"name":"Frau Schmidt"}"#).assert_problem(403, r#"{"code":"forbidden",
+        "message":"The user's role does not allow this request","field":null,"details":"Needs the role librarian"}"#);
+    as_reader.get("/users").assert_problem(403, r#"{"code":"forbidden",
+        "message":"The user's role does not allow this request","field":null,"details":"Needs the role admin"}"#);
+    as_reader.put("/users/password", r#"{"old_password":"wrong","new_password":"geheim"}"#).assert_problem(401,
+        r#"{"code":"invalid_credentials","message":"The credentials are invalid","field":null,"details":null}"#);
+    let res = as_reader.put("/users/password", r#"{"old_password":"test1234","new_password":"geheim"}"#);
+    assert_eq!(res.status, 204, "{}", res.body);
+    assert_eq!(school.login("gast", "geheim").status, 201);
+    assert_eq!(as_reader.get("/students").status, 200);
+
+    // Changing the role logs the user out.
+    let res = school.put(&format!("/users/{}", id(&reader)), r#"{"username":"gast","role":"librarian"}"#);
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json(), json(&format!(r#"{{"id":{},"username":"gast","role":"librarian"}}"#, id(&reader))));
+    assert_eq!(as_reader.get("/students").status, 401);
+    let admin_id = school.get("/users?username=admin").json()[0]["id"].as_u64().unwrap();
+    school.put(&format!("/users/{}", admin_id), r#"{"username":"admin","role":"librarian"}"#).assert_problem(409,
+        r#"{"code":"last_admin","message":"The school needs to keep at least one admin","field":null,"details":null}"#);
+    assert_eq!(school.delete(&format!("/users/{}", admin_id)).status, 409);
+    assert_eq!(school.delete(&format!("/users/{}", id(&reader))).status, 204);
+    assert_eq!(school.get(&format!("/users/{}", id(&reader))).status, 404);
+
+    // API keys
+    let res = school.post("/api_keys", r#"{"name":"Card printer","scopes":["students:read","search:read"]}"#);
+    assert_eq!(res.status, 201, "{}", res.body);
+    let key = res.json();
+    assert_eq!(masked(&key, &["created_at", "secret"]), json(&format!(r#"{{"id":{0},"name":"Card printer",
+        "scopes":["students:read","search:read"],"created_at":"…","last_used_at":null,"revoked_at":null,
+        "username":"key-{0}","secret":"…"}}"#, id(&key))));
+    school.post("/api_keys", r#"{"name":"Card printer","scopes":["users:write"]}"#).assert_problem(422,
+        r#"{"code":"invalid_fields","message":"Some fields of the record are invalid","field":"scopes",
+        "details":[{"field":"scopes","message":"Unknown scope users:write"}]}"#);
+    let as_key = school.as_user(&Auth(key["username"].as_str().unwrap().to_string(),
+        key["secret"].as_str().unwrap().to_string()));
+    assert_eq!(as_key.get("/students").status, 200);
+    assert_eq!(as_key.get("/students.csv").status, 200);
+    assert_eq!(as_key.get("/search?q=x").status, 200);
+    as_key.post("/students", "{}").assert_problem(403, r#"{"code":"missing_scope",
+        "message":"The API key lacks the scope needed for this request","field":null,"details":"students:write"}"#);
+    as_key.get("/books").assert_problem(403, r#"{"code":"missing_scope",
+        "message":"The API key lacks the scope needed for this request","field":null,"details":"books:read"}"#);
+    as_key.get("/lendings.csv").assert_problem(403, r#"{"code":"missing_scope",
+        "message":"The API key lacks the scope needed for this request","field":null,"details":"lendings:read"}"#);
+    as_key.get("/sessions").assert_problem(403, r#"{"code":"forbidden",
+        "message":"The user's role does not allow this request","field":null,"details":"Needs the role read_only"}"#);
+    let res = school.get("/api_keys");
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(masked(&res.json(), &["created_at", "last_used_at"]), json(&format!(r#"[{{"id":{},
+        "name":"Card printer","scopes":["students:read","search:read"],"created_at":"…","last_used_at":"…",
+        "revoked_at":null,"username":null,"secret":null}}]"#, id(&key))));
+    assert_eq!(school.delete(&format!("/api_keys/{}", id(&key))).status, 204);
+    assert_eq!(as_key.get("/students").status, 401);
+    assert!(school.get("/api_keys").json()[0]["revoked_at"].is_string());
+    assert_eq!(school.delete(&format!("/api_keys/{}", id(&key))).status, 404);
+}
+
+#[test]
+#[ignore]
+fn rolling_over_the_school_year() {
+    let server = Server::start();
+    let school = server.new_school();
+    let year = current_school_year();
+    let graduate = school.create("/students", &format!(r#"{{"name":"Jael Veen","class_letter":"",
+        "graduation_year":{}}}"#, year));
+    let book = school.create("/books", r#"{"isbn":"9783127318104","title":"Mathematik 5","form":[5]}"#);
+    let copy = school.create("/copies", &format!(r#"{{"book_id":{},"inventory_number":"000412"}}"#, id(&book)));
+    let holder = school.create("/students", &format!(r#"{{"name":"Katharina Maier","class_letter":"",
+        "graduation_year":{}}}"#, year));
+    school.create("/lendings", &format!(r#"{{"person_type":"student","person_id":{},"copy_id":{}}}"#,
+        id(&holder), id(&copy)));
+    school.create("/students", &format!(r#"{{"name":"Lena Abt","class_letter":"a","graduation_year":{}}}"#,
+        year + 7));
+
+    let res = school.post("/schools/rollover", &format!(r#"{{"school_year":{},"dry_run":true}}"#, year));
+    assert_eq!(res.status, 200, "{}", res.body);
+    assert_eq!(res.json(), json(&format!(r#"{{"school_year":{0},"dry_run":true,
+        "archived":[{{"id":{1},"name":"Jael Veen","class_letter":"","graduation_year":{0}}}],
+        "retained":[{{"id":{2},"name":"Katharina Maier","class_letter":"","graduation_year":{0},"open_lendings":1,
+        "base_sets":0}}],
+        "classes":[{{"form":6,"class_letter":"a","graduation_year":{3},"students":1}}]}}"#,
+        year, id(&graduate), id(&holder), year + 7)));
+    assert_eq!(school.get("/students").header("X-Total-Count"), Some("3"));
+    let res = school.post("/schools/rollover", &format!(r#"{{"school_year":{}}}"#, year));
+    assert_eq!(res.json()["dry_run"], json("false"));
+    assert_eq!(school.get("/students").header("X-Total-Count"), Some("2"));
+    assert_eq!(school.get(&format!("/students/{}", id(&graduate))).status, 200);
+    school.post("/schools/rollover", r#"{"final_form":14}"#).assert_problem(422, r#"{"code":"invalid_fields",
+        "message":"Some fields of the record are invalid","field":"final_form",
+        "details":[{"field":"final_form","message":"The final form must be between 1 and 13"}]}"#);
+
+    let reader = school.create("/users", r#"{"username":"gast","role":"librarian","password":"test1234"}"#);
+    let as_librarian = school.as_user(&Auth::from_token(&school.login("gast", "test1234").json()));
+    assert_eq!(as_librarian.post("/schools/rollover", "{}").status, 403);
+    assert_eq!(as_librarian.delete("/schools/lockout").status, 403);
+    assert_eq!(school.delete(&format!("/users/{}", id(&reader))).status, 204);
+}